@@ -1,22 +1,48 @@
-use std::{sync::mpsc::channel, time::{Duration, Instant}};
+use std::{cell::RefCell, path::{Path, PathBuf}, rc::Rc, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
 
 use anyhow::{anyhow, Result};
-use slint::{Image, Timer, TimerMode};
+use slint::{Image, ModelRc, SharedString, Timer, TimerMode, VecModel};
 
-use crate::camera::Camera;
+use crate::camera::{Camera, CameraInfo, ClipFormat, ClipOptions, ClipRecorder, EncodeOptions, FrameMailbox, GpuContext, Lut3d, RecordOptions, Recorder, SequenceOptions, StillOptions, TimeLapse};
+#[cfg(not(target_os = "android"))]
+use crate::camera::{PlaybackCamera, PlaybackOptions, TestPatternCamera};
+
+/// 连拍的张数
+const BURST_COUNT: usize = 10;
+/// 延时拍摄的间隔和总时长
+const TIME_LAPSE_INTERVAL: Duration = Duration::from_secs(5);
+const TIME_LAPSE_DURATION: Duration = Duration::from_secs(60);
 
 pub fn run(
     #[cfg(target_os = "android")]
     android_app: slint::android::AndroidApp,
 ) -> Result<()> {
     slint::slint! {
-        import { Button, VerticalBox, HorizontalBox } from "std-widgets.slint";
+        import { Button, ComboBox, VerticalBox, HorizontalBox } from "std-widgets.slint";
         export component MainWindow inherits Window {
             in-out property <image> camera-texture <=> camera-texture.source;
+            in property <[string]> cameras;
+            in property <[string]> sizes;
+            in-out property <int> camera-index;
+            in-out property <int> size-index;
+            in property <[string]> luts;
+            in-out property <int> lut-index;
             callback open-camera(bool);
+            callback camera-changed(int);
+            callback lut-changed(int);
+            in-out property <bool> time-lapse-running;
+            // 拍照、连拍还没有保存完时禁用按钮
+            in-out property <bool> capturing;
+            callback capture-still();
+            callback capture-burst();
+            callback time-lapse(bool);
+            in-out property <bool> recording;
+            in-out property <bool> recording-paused;
+            callback record(bool);
+            callback pause-record(bool);
+            callback export-clip(bool);
 
             Rectangle {
-                padding: 0px;
                 width: 100%;
                 height: 100%;
                 HorizontalLayout {
@@ -29,13 +55,41 @@ pub fn run(
                 Rectangle {
                     x: 0px;
                     y: 0px;
-                    width: 100px;
+                    width: 600px;
                     height: 40px;
-                    Text { text: "相机"; }
+                    HorizontalBox {
+                        padding: 0px;
+                        Text {
+                            text: "相机";
+                            vertical-alignment: center;
+                        }
+                        ComboBox {
+                            model: cameras;
+                            current-index <=> camera-index;
+                            selected => {
+                                camera-changed(self.current-index);
+                            }
+                        }
+                        ComboBox {
+                            model: sizes;
+                            current-index <=> size-index;
+                        }
+                        Text {
+                            text: "调色";
+                            vertical-alignment: center;
+                        }
+                        ComboBox {
+                            model: luts;
+                            current-index <=> lut-index;
+                            selected => {
+                                lut-changed(self.current-index);
+                            }
+                        }
+                    }
                 }
                 Rectangle {
                     height: 40px;
-                    width: 100px;
+                    width: 500px;
                     x: (parent.width/2 - self.width/2);
                     y: (parent.height - self.height);
                     HorizontalBox {
@@ -46,6 +100,50 @@ pub fn run(
                                 open-camera(true);
                             }
                         }
+                        Button {
+                            text: "拍照";
+                            enabled: !capturing;
+                            clicked => {
+                                capture-still();
+                            }
+                        }
+                        Button {
+                            text: "连拍";
+                            enabled: !capturing;
+                            clicked => {
+                                capture-burst();
+                            }
+                        }
+                        Button {
+                            text: time-lapse-running ? "停止延时" : "延时";
+                            clicked => {
+                                time-lapse(!time-lapse-running);
+                            }
+                        }
+                        Button {
+                            text: recording ? "停止录像" : "录像";
+                            clicked => {
+                                record(!recording);
+                            }
+                        }
+                        if recording : Button {
+                            text: recording-paused ? "继续" : "暂停";
+                            clicked => {
+                                pause-record(!recording-paused);
+                            }
+                        }
+                        Button {
+                            text: "GIF";
+                            clicked => {
+                                export-clip(false);
+                            }
+                        }
+                        Button {
+                            text: "WebP";
+                            clicked => {
+                                export-clip(true);
+                            }
+                        }
                         Button {
                             text: "关闭相机";
                             clicked => {
@@ -58,30 +156,418 @@ pub fn run(
         }
     }
 
+    // 界面和相机共用 wgpu 设备时预览帧的纹理直接显示，不用回读到内存
+    let display_gpu = select_wgpu_backend();
     let app = MainWindow::new()?;
     
-    let (image_sender, image_receiver) = channel();
+    // 相机线程只保留最新的一帧，界面来不及显示时丢弃旧帧
+    let image_sender = FrameMailbox::new();
+    let mailbox = image_sender.clone();
 
-    let mut camera = Camera::new(#[cfg(target_os = "android")]android_app, image_sender)?;
+    // 相机后端不处理 LUT 时在这里用 CPU 处理
+    let cpu_lut: Arc<Mutex<Option<Lut3d>>> = Arc::new(Mutex::new(None));
 
-    let app_clone = app.as_weak();
-    let timer = Timer::default();
-    timer.start(TimerMode::Repeated, std::time::Duration::from_millis(10), move || {
-        if let (Ok(buffer), Some(app)) = (image_receiver.try_recv(), app_clone.upgrade()){
-            app.set_camera_texture(Image::from_rgba8(buffer));
+    // 有新帧时唤醒界面线程显示，显示之前到达的帧会覆盖信箱中的旧帧
+    {
+        let app_clone = app.as_weak();
+        let cpu_lut = cpu_lut.clone();
+        mailbox.set_waker(move |mailbox|{
+            let mailbox = mailbox.clone();
+            let app_clone = app_clone.clone();
+            let cpu_lut = cpu_lut.clone();
+            let _ = slint::invoke_from_event_loop(move ||{
+                let (Some(frame), Some(app)) = (mailbox.take(), app_clone.upgrade()) else { return };
+                // GPU 上的帧在界面线程提交，和 slint 的渲染在同一个线程使用队列
+                if let Some(gpu_frame) = frame.gpu.as_ref(){
+                    match Image::try_from(gpu_frame.submit()){
+                        Ok(image) => app.set_camera_texture(image),
+                        Err(err) => println!("纹理导入失败:{:?}", err),
+                    }
+                    return;
+                }
+                match frame.to_pixel_buffer(){
+                    Ok(mut buffer) => {
+                        if let Some(lut) = cpu_lut.lock().unwrap().as_ref(){
+                            lut.apply_rgba(buffer.make_mut_bytes());
+                        }
+                        app.set_camera_texture(Image::from_rgba8(buffer))
+                    }
+                    Err(err) => println!("帧转换失败:{:?}", err),
+                }
+            });
+        });
+    }
+
+    #[cfg(target_os = "android")]
+    let lut_dir = android_app.external_data_path().map(|path| path.join("luts")).unwrap_or_default();
+    #[cfg(not(target_os = "android"))]
+    let lut_dir = PathBuf::from(std::env::var("CAMERA_LUT_DIR").unwrap_or(String::from("luts")));
+    #[cfg(target_os = "android")]
+    let photo_dir = android_app.external_data_path().map(|path| path.join("photos")).unwrap_or_default();
+    #[cfg(not(target_os = "android"))]
+    let photo_dir = PathBuf::from(std::env::var("CAMERA_PHOTO_DIR").unwrap_or(String::from("photos")));
+
+    #[cfg(target_os = "android")]
+    let mut camera = Camera::new(android_app, image_sender)?;
+    #[cfg(not(target_os = "android"))]
+    let mut camera = match std::env::var("CAMERA_SOURCE").as_deref(){
+        // 没有相机的环境(CI、无头Linux)使用测试图案
+        Ok("pattern") => Camera::with_backend(Box::new(TestPatternCamera::new(image_sender, 30))),
+        // 回放图片目录、Y4M 或 NV21 数据
+        Ok(path) => Camera::with_backend(Box::new(PlaybackCamera::new(image_sender, path, PlaybackOptions::default()))),
+        _ => Camera::new(image_sender)?,
+    };
+
+    if let Some(gpu) = display_gpu.as_ref(){
+        if let Err(err) = camera.set_display_device(Some(gpu.clone())){
+            println!("设置显示设备失败:{:?}", err);
         }
-    });
+    }
+
+    // 相机列表和分辨率列表
+    let cameras = Rc::new(camera.list_cameras().unwrap_or_else(|err| {
+        println!("获取相机列表失败:{:?}", err);
+        vec![]
+    }));
+    app.set_cameras(ModelRc::new(VecModel::from(
+        cameras.iter().map(|info| SharedString::from(info.name.as_str())).collect::<Vec<_>>(),
+    )));
+    let update_sizes = {
+        let app_clone = app.as_weak();
+        let cameras = cameras.clone();
+        move |camera_index: i32| {
+            let (Some(app), Some(info)) = (app_clone.upgrade(), cameras.get(camera_index as usize)) else {
+                return;
+            };
+            app.set_sizes(ModelRc::new(VecModel::from(
+                info.supported_sizes.iter().map(|(w, h)| SharedString::from(format!("{w}x{h}"))).collect::<Vec<_>>(),
+            )));
+            app.set_size_index(default_size_index(info) as i32);
+        }
+    };
+    update_sizes(0);
+    app.on_camera_changed(update_sizes);
+
+    // LUT 列表，第一项为不调色
+    let luts = list_luts(&lut_dir);
+    app.set_luts(ModelRc::new(VecModel::from(
+        std::iter::once(SharedString::from("无"))
+            .chain(luts.iter().map(|path| SharedString::from(path.file_stem().unwrap_or_default().to_string_lossy().as_ref())))
+            .collect::<Vec<_>>(),
+    )));
+    let camera = Rc::new(RefCell::new(camera));
+    {
+        let camera = camera.clone();
+        let cpu_lut = cpu_lut.clone();
+        app.on_lut_changed(move |lut_index| {
+            let lut = match usize::try_from(lut_index - 1).ok().and_then(|index| luts.get(index)) {
+                Some(path) => match Lut3d::load(path) {
+                    Ok(lut) => Some(lut),
+                    Err(err) => {
+                        println!("加载LUT失败:{:?}", err);
+                        None
+                    }
+                },
+                None => None,
+            };
+            match camera.borrow_mut().set_lut(lut.clone()) {
+                Ok(true) => *cpu_lut.lock().unwrap() = None,
+                Ok(false) => {
+                    // 在 CPU 上调色需要像素数据，这时不使用 GPU 上的帧
+                    if let Some(gpu) = display_gpu.as_ref(){
+                        if let Err(err) = camera.borrow_mut().set_display_device(lut.is_none().then(|| gpu.clone())){
+                            println!("设置显示设备失败:{:?}", err);
+                        }
+                    }
+                    *cpu_lut.lock().unwrap() = lut
+                }
+                Err(err) => println!("设置LUT失败:{:?}", err),
+            }
+        });
+    }
+
+    // 拍照和连拍在工作线程中等待和保存，完成后回到界面线程恢复按钮
+    {
+        let camera = camera.clone();
+        let photo_dir = photo_dir.clone();
+        let app_clone = app.as_weak();
+        app.on_capture_still(move ||{
+            let Some(app) = app_clone.upgrade() else { return };
+            let mut options = StillOptions::default();
+            options.path = Some(photo_dir.join(format!("IMG_{}.{}", unix_millis(), options.format.extension())));
+            let app_weak = app.as_weak();
+            let res = std::fs::create_dir_all(&photo_dir)
+                .map_err(|err| anyhow!("create {photo_dir:?} failed: {err}"))
+                .and_then(|_| camera.borrow_mut().capture_still_async(&options, move |res| {
+                    match res{
+                        Ok(photo) => println!("拍照:{:?} {}x{}", photo.path, photo.width, photo.height),
+                        Err(err) => println!("拍照失败:{:?}", err),
+                    }
+                    let _ = app_weak.upgrade_in_event_loop(|app| app.set_capturing(false));
+                }));
+            match res{
+                Ok(()) => app.set_capturing(true),
+                Err(err) => println!("拍照失败:{:?}", err),
+            }
+        });
+    }
+
+    // 连拍和延时拍摄分别保存到 photo_dir 下的子目录中
+    {
+        let camera = camera.clone();
+        let photo_dir = photo_dir.clone();
+        let app_clone = app.as_weak();
+        app.on_capture_burst(move ||{
+            let Some(app) = app_clone.upgrade() else { return };
+            let options = SequenceOptions::new(photo_dir.join(format!("BURST_{}", unix_millis())), "IMG_");
+            let (dir, app_weak) = (options.dir.clone(), app.as_weak());
+            let res = camera.borrow_mut().capture_burst_async(BURST_COUNT, &options, move |res| {
+                match res{
+                    Ok(photos) => println!("连拍:{}张 {:?}", photos.len(), dir),
+                    Err(err) => println!("连拍失败:{:?}", err),
+                }
+                let _ = app_weak.upgrade_in_event_loop(|app| app.set_capturing(false));
+            });
+            match res{
+                Ok(()) => app.set_capturing(true),
+                Err(err) => println!("连拍失败:{:?}", err),
+            }
+        });
+    }
+    {
+        let camera = camera.clone();
+        let photo_dir = photo_dir.clone();
+        let app_clone = app.as_weak();
+        let time_lapse: Rc<RefCell<Option<TimeLapse>>> = Rc::new(RefCell::new(None));
+        let timer = Rc::new(Timer::default());
+        app.on_time_lapse(move |start|{
+            let Some(app) = app_clone.upgrade() else { return };
+            timer.stop();
+            time_lapse.borrow_mut().take();
+            app.set_time_lapse_running(false);
+            if !start{
+                return;
+            }
+            let options = SequenceOptions::new(photo_dir.join(format!("TIMELAPSE_{}", unix_millis())), "IMG_");
+            match TimeLapse::new(options, TIME_LAPSE_INTERVAL, TIME_LAPSE_DURATION){
+                Ok(new_time_lapse) => *time_lapse.borrow_mut() = Some(new_time_lapse),
+                Err(err) => {
+                    println!("延时拍摄失败:{:?}", err);
+                    return;
+                }
+            }
+            app.set_time_lapse_running(true);
+            let camera = camera.clone();
+            let app_clone = app_clone.clone();
+            let time_lapse = time_lapse.clone();
+            let timer_weak = Rc::downgrade(&timer);
+            timer.start(TimerMode::Repeated, Duration::from_millis(100), move ||{
+                let mut time_lapse = time_lapse.borrow_mut();
+                let Some(current) = time_lapse.as_mut() else { return };
+                let (index, count) = (current.captured() + 1, current.count());
+                let res = current.poll(&mut camera.borrow_mut(), move |res| match res{
+                    Ok(photo) => println!("延时拍摄:{}/{} {:?}", index, count, photo.path),
+                    Err(err) => println!("延时拍摄失败:{:?}", err),
+                });
+                if let Err(err) = res{
+                    println!("延时拍摄失败:{:?}", err);
+                }
+                if current.is_finished(){
+                    time_lapse.take();
+                    if let Some(app) = app_clone.upgrade(){
+                        app.set_time_lapse_running(false);
+                    }
+                    if let Some(timer) = timer_weak.upgrade(){
+                        timer.stop();
+                    }
+                }
+            });
+        });
+    }
+
+    // 录像保存到 photo_dir，后端支持硬件编码时为 MP4，否则为 MJPEG AVI
+    {
+        let camera = camera.clone();
+        let mailbox = mailbox.clone();
+        let photo_dir = photo_dir.clone();
+        let app_clone = app.as_weak();
+        let recording: Rc<RefCell<Option<Recording>>> = Rc::new(RefCell::new(None));
+        {
+            let camera = camera.clone();
+            let recording = recording.clone();
+            app.on_record(move |start|{
+                let Some(app) = app_clone.upgrade() else { return };
+                match recording.borrow_mut().take(){
+                    Some(Recording::Hardware(path)) => match camera.borrow_mut().stop_recording(){
+                        Ok(stats) => println!("录像结束:{:?} {:?}", path, stats),
+                        Err(err) => println!("录像失败:{:?}", err),
+                    },
+                    Some(Recording::Frames(recorder)) => {
+                        let path = recorder.path().to_path_buf();
+                        match recorder.stop(){
+                            Ok(stats) => println!("录像结束:{:?} {:?}", path, stats),
+                            Err(err) => println!("录像失败:{:?}", err),
+                        }
+                    }
+                    None => (),
+                }
+                app.set_recording(false);
+                app.set_recording_paused(false);
+                if !start{
+                    return;
+                }
+                if let Err(err) = std::fs::create_dir_all(&photo_dir){
+                    println!("录像失败:create {photo_dir:?} failed: {err}");
+                    return;
+                }
+                let path = photo_dir.join(format!("VID_{}.mp4", unix_millis()));
+                let res = match camera.borrow_mut().start_recording(&path, &EncodeOptions::default()){
+                    Ok(()) => Ok(Recording::Hardware(path)),
+                    Err(err) => {
+                        println!("不使用硬件编码:{:?}", err);
+                        let options = RecordOptions::default();
+                        let path = path.with_extension(options.format.extension());
+                        Recorder::start(&mailbox, path, options).map(Recording::Frames)
+                    }
+                };
+                match res{
+                    Ok(new_recording) => {
+                        println!("开始录像:{:?}", new_recording.path());
+                        *recording.borrow_mut() = Some(new_recording);
+                        app.set_recording(true);
+                    }
+                    Err(err) => println!("录像失败:{:?}", err),
+                }
+            });
+        }
+        let app_clone = app.as_weak();
+        app.on_pause_record(move |pause|{
+            let Some(app) = app_clone.upgrade() else { return };
+            let res = match recording.borrow().as_ref(){
+                Some(Recording::Hardware(_)) => camera.borrow_mut().pause_recording(pause),
+                Some(Recording::Frames(recorder)) => {
+                    if pause{
+                        recorder.pause();
+                    }else{
+                        recorder.resume();
+                    }
+                    Ok(())
+                }
+                None => return,
+            };
+            match res{
+                Ok(()) => app.set_recording_paused(pause),
+                Err(err) => println!("暂停录像失败:{:?}", err),
+            }
+        });
+    }
+
+    // 一直缓存最近几秒缩小后的预览帧，点击时导出为动图
+    {
+        let clip_recorder = ClipRecorder::start(&mailbox, ClipOptions::default());
+        app.on_export_clip(move |webp|{
+            let format = if webp { ClipFormat::WebP } else { ClipFormat::Gif };
+            let path = photo_dir.join(format!("CLIP_{}.{}", unix_millis(), format.extension()));
+            let buffer = clip_recorder.buffer().clone();
+            let photo_dir = photo_dir.clone();
+            // 编码需要几百毫秒，不阻塞界面
+            std::thread::spawn(move ||{
+                let res = std::fs::create_dir_all(&photo_dir)
+                    .map_err(|err| anyhow!("create {photo_dir:?} failed: {err}"))
+                    .and_then(|_| buffer.save(format, &path));
+                match res{
+                    Ok(len) => println!("导出动图:{:?} {}KB", path, len / 1024),
+                    Err(err) => println!("导出动图失败:{:?}", err),
+                }
+            });
+        });
+    }
 
+    let app_clone = app.as_weak();
     app.on_open_camera(move |open|{
         if open{
-            let res = camera.start_preview(0, 1280, 720);
+            let Some(app) = app_clone.upgrade() else { return };
+            let camera_index = app.get_camera_index().max(0) as usize;
+            let (width, height) = cameras
+                .get(camera_index)
+                .and_then(|info| info.supported_sizes.get(app.get_size_index().max(0) as usize))
+                .cloned()
+                .unwrap_or((1280, 720));
+            let res = camera.borrow_mut().start_preview(camera_index, width, height);
             println!("相机启动:{:?}", res);
         }else{
-            let res = camera.stop_preview();
-            println!("相机结束:{:?}", res);
+            let res = camera.borrow_mut().stop_preview();
+            println!("相机结束:{:?} {:?}", res, mailbox.stats());
         }
     });
 
     app.run()?;
     Ok(())
 }
+
+/// 正在进行的录像: 后端的硬件编码，或者订阅预览帧的 Recorder
+enum Recording{
+    Hardware(PathBuf),
+    Frames(Recorder),
+}
+
+impl Recording{
+    fn path(&self) -> &Path{
+        match self{
+            Recording::Hardware(path) => path,
+            Recording::Frames(recorder) => recorder.path(),
+        }
+    }
+}
+
+/// 文件名中使用的时间戳(毫秒)
+fn unix_millis() -> u128{
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+/// 目录中的 .cube 文件，按文件名排序
+fn list_luts(dir: &Path) -> Vec<PathBuf>{
+    let mut luts: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("cube")))
+                .collect()
+        })
+        .unwrap_or_default();
+    luts.sort();
+    luts
+}
+
+/// 默认选择最接近 1280x720 的分辨率
+fn default_size_index(info: &CameraInfo) -> usize{
+    info.supported_sizes
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, (w, h))| (*w as i64 * *h as i64 - 1280 * 720).abs())
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// 让 slint 在相机解码用的 wgpu 设备上渲染，失败时使用默认的渲染器，预览帧回读后显示
+///
+/// android 上要在 slint::android::init 之后、创建窗口之前调用
+fn select_wgpu_backend() -> Option<GpuContext>{
+    // skia 只能在 Vulkan、Metal、DX12 上使用 wgpu 的设备
+    let gpu = GpuContext::new(wgpu::Backends::PRIMARY, false)
+        .map_err(|err| println!("创建 wgpu 设备失败:{:?}", err))
+        .ok()?;
+    let configuration = slint::wgpu_30::WGPUConfiguration::Manual{
+        instance: gpu.instance.clone(),
+        adapter: gpu.adapter.clone(),
+        device: gpu.device.clone(),
+        queue: gpu.queue.clone(),
+    };
+    match slint::BackendSelector::new().require_wgpu_30(configuration).select(){
+        Ok(()) => Some(gpu),
+        Err(err) => {
+            println!("界面不能使用 wgpu 渲染，预览帧回读后显示:{:?}", err);
+            None
+        }
+    }
+}
@@ -9,6 +9,7 @@ use jni::{
 use log::{error, info};
 use ndk_sys::{
     acamera_metadata_tag, camera_status_t, media_status_t, ACameraCaptureSession,
+    ACameraCaptureSession_capture, ACameraCaptureSession_captureCallbacks, ACameraCaptureSession_close,
     ACameraCaptureSession_setRepeatingRequest, ACameraCaptureSession_stateCallbacks, ACameraDevice,
     ACameraDevice_StateCallbacks, ACameraDevice_close, ACameraDevice_createCaptureRequest,
     ACameraDevice_createCaptureSession, ACameraDevice_getId, ACameraDevice_request_template,
@@ -17,35 +18,57 @@ use ndk_sys::{
     ACameraManager_openCamera, ACameraMetadata, ACameraMetadata_const_entry, ACameraMetadata_free,
     ACameraMetadata_getConstEntry, ACameraOutputTarget, ACameraOutputTarget_create,
     ACameraOutputTarget_free, ACaptureRequest, ACaptureRequest_addTarget, ACaptureRequest_free,
+    ACaptureRequest_setEntry_u8,
     ACaptureSessionOutput, ACaptureSessionOutputContainer, ACaptureSessionOutputContainer_add,
     ACaptureSessionOutputContainer_create, ACaptureSessionOutputContainer_free,
-    ACaptureSessionOutput_create, ACaptureSessionOutput_free, AImageCropRect, AImageReader,
-    AImageReader_ImageListener, AImageReader_acquireLatestImage, AImageReader_getFormat,
+    ACaptureSessionOutput_create, ACaptureSessionOutput_free, AImage, AImageCropRect, AImageReader,
+    AImageReader_ImageListener, AImageReader_acquireLatestImage, AImageReader_acquireNextImage,
+    AImageReader_delete, AImageReader_getFormat,
     AImageReader_getHeight, AImageReader_getWidth, AImageReader_getWindow, AImageReader_new,
     AImageReader_setImageListener, AImage_delete, AImage_getCropRect, AImage_getNumberOfPlanes,
     AImage_getPlaneData, AImage_getPlanePixelStride, AImage_getPlaneRowStride, AImage_getTimestamp,
     AImage_getWidth, ANativeWindow, AIMAGE_FORMATS,
 };
-use pollster::FutureExt;
 use std::{
-    borrow::Cow,
-    ffi::{c_int, c_void, CStr},
+    collections::VecDeque,
+    ffi::{c_char, c_int, c_void, CStr, CString},
     mem::zeroed,
+    path::{Path, PathBuf},
     ptr::null_mut,
-    sync::mpsc::Sender,
-    time::Instant,
+    sync::{mpsc::{channel, Sender}, Mutex},
+    time::{Duration, Instant},
 };
-use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    BindGroup, ComputePipeline, Device, Limits, Queue, Texture, TextureView,
+
+use super::{
+    convert::{self, ColorSpace, Orientation, Plane},
+    encoder::{EncodeOptions, EncodeRecorder, EncodeSession, EncodeStats},
+    gpu::{GpuContext, OutputScale, ScaleFilter, ScaleMode, YuvGpuDecoder, READBACK_BUFFERS},
+    mailbox::STILL_TIMEOUT,
+    mediacodec::{MediaCodecEncoder, MediaMuxer},
+    CameraBackend, CameraInfo, Exposure, FilterStage, Frame, FrameMailbox, LensFacing, Lut3d, PendingStill, PixelFormat,
 };
 
+/// 拍照结果的发送端，没有拍照请求在等待时为 None
+type StillSender<T> = Mutex<Option<Sender<T>>>;
+
 #[link(name = "camera2ndk")]
 extern "C" {}
 
 #[link(name = "mediandk")]
 extern "C" {}
 
+extern "C" {
+    fn __system_property_get(name: *const c_char, value: *mut c_char) -> c_int;
+}
+
+/// 硬件编码录像的状态
+enum VideoRecording {
+    /// 录像参数，编码器在收到第一帧、知道帧的大小之后创建
+    Pending(PathBuf, EncodeOptions),
+    /// 预览帧旋转之前的 YUV 送入编码器
+    Started(EncodeRecorder),
+}
+
 pub struct AndroidCamera {
     app: slint::android::AndroidApp,
     camera_device: *mut ACameraDevice,
@@ -54,26 +77,59 @@ pub struct AndroidCamera {
     session_output: *mut ACaptureSessionOutput,
     capture_session_output_container: *mut ACaptureSessionOutputContainer,
     image_reader: *mut AImageReader,
+    capture_session: *mut ACameraCaptureSession,
+    /// 拍照用的 JPEG 输出，和预览在同一个会话中
+    still_reader: *mut AImageReader,
+    still_output: *mut ACaptureSessionOutput,
+    still_target: *mut ACameraOutputTarget,
+    /// 支持的最大 JPEG 尺寸
+    still_size: Option<(i32, i32)>,
     /// width,height,format
     image_formats: Vec<(i32, i32, i32)>,
     camera_id: Option<String>,
     image_listener: AImageReader_ImageListener,
     capture_session_state_callbacks: ACameraCaptureSession_stateCallbacks,
+    /// 拍照请求的回调，读取拍摄结果中的曝光参数
+    still_capture_callbacks: ACameraCaptureSession_captureCallbacks,
+    /// JPEG 输出的回调，在 NDK 的线程中取出照片
+    still_listener: AImageReader_ImageListener,
+    /// 当前拍照请求的照片(JPEG, 时间戳)和曝光参数，在 NDK 的线程中发送，工作线程中接收
+    still_images: StillSender<(Vec<u8>, i64)>,
+    still_exposures: StillSender<Exposure>,
     device_state_callbacks: ACameraDevice_StateCallbacks,
     preview_width: u32,
     preview_height: u32,
     timer: Instant,
     frame_count: i32,
+    /// 帧序号
+    sequence: u64,
+    /// 为 None 时使用 CPU 解码
     decoder_gpu: Option<YuvGpuDecoder>,
+    /// 和界面共用设备的解码器，没有订阅者时只记录命令，由界面线程提交后直接显示纹理
+    decoder_display: Option<YuvGpuDecoder>,
+    /// 界面渲染使用的设备，只在回调线程中修改
+    display: Option<GpuContext>,
+    /// set_display_device() 设置、还没有应用的设备，回调线程处理下一帧之前取走
+    pending_display: Mutex<Option<Option<GpuContext>>>,
+    /// 已提交给 GPU 还没有取回的帧: (时间戳, 方向变换)
+    gpu_in_flight: VecDeque<(i64, Orientation)>,
     rgba_buffer: Vec<u8>,
-    image_sender: Sender<SharedPixelBuffer<Rgba8Pixel>>,
+    /// CPU 解码时旋转之前的 rgba
+    decode_buffer: Vec<u8>,
+    image_sender: FrameMailbox,
+    /// 调色用的 LUT，GPU 解码时作为滤镜执行，只在回调线程中修改
+    lut: Option<Lut3d>,
+    /// set_lut() 设置、还没有应用的 LUT，回调线程处理下一帧之前取走
+    pending_lut: Mutex<Option<Option<Lut3d>>>,
     lens_facing: u8,
     sensor_orientation: i32,
+    /// 硬件编码录像，界面线程开始、停止，回调线程创建编码器、送入帧
+    video_recording: Mutex<Option<VideoRecording>>,
     color_image: Option<SharedPixelBuffer<Rgba8Pixel>>,
 }
 
 impl AndroidCamera {
-    pub fn new(app: slint::android::AndroidApp, image_sender: Sender<SharedPixelBuffer<Rgba8Pixel>>) -> Self {
+    pub fn new(app: slint::android::AndroidApp, image_sender: FrameMailbox) -> Self {
         Self {
             app,
             camera_device: null_mut(),
@@ -82,6 +138,11 @@ impl AndroidCamera {
             session_output: null_mut(),
             capture_session_output_container: null_mut(),
             image_reader: null_mut(),
+            capture_session: null_mut(),
+            still_reader: null_mut(),
+            still_output: null_mut(),
+            still_target: null_mut(),
+            still_size: None,
             image_formats: vec![],
             camera_id: None,
             image_listener: AImageReader_ImageListener {
@@ -89,16 +150,32 @@ impl AndroidCamera {
                 onImageAvailable: None,
             },
             capture_session_state_callbacks: unsafe { zeroed() },
+            still_capture_callbacks: unsafe { zeroed() },
+            still_listener: AImageReader_ImageListener {
+                context: null_mut(),
+                onImageAvailable: None,
+            },
+            still_images: Mutex::new(None),
+            still_exposures: Mutex::new(None),
             device_state_callbacks: unsafe { zeroed() },
             preview_width: 0,
             preview_height: 0,
             timer: Instant::now(),
             frame_count: 0,
+            sequence: 0,
             decoder_gpu: None,
+            decoder_display: None,
+            display: None,
+            pending_display: Mutex::new(None),
+            gpu_in_flight: VecDeque::new(),
             rgba_buffer: vec![],
+            decode_buffer: vec![],
             image_sender,
+            lut: None,
+            pending_lut: Mutex::new(None),
             lens_facing: 0,
             sensor_orientation: 0,
+            video_recording: Mutex::new(None),
             color_image: None,
         }
     }
@@ -198,6 +275,14 @@ impl AndroidCamera {
 
             info!("image_formats: {:?}", self.image_formats);
 
+            // 拍照使用最大的 JPEG 尺寸
+            self.still_size = AndroidCamera::get_output_sizes(camera_metadata, AIMAGE_FORMATS::AIMAGE_FORMAT_JPEG)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(width, height, _format)| (width, height))
+                .max_by_key(|(width, height)| *width as i64 * *height as i64);
+            info!("still_size: {:?}", self.still_size);
+
             unsafe extern "C" fn on_disconnected(_data: *mut c_void, device: *mut ACameraDevice) {
                 info!("Camera(id: {:?}) is disconnected.", get_cstr(ACameraDevice_getId(device)));
             }
@@ -236,6 +321,85 @@ impl AndroidCamera {
         Ok(())
     }
 
+    /// 获取所有相机的id
+    pub fn camera_id_list() -> Result<Vec<String>> {
+        unsafe {
+            let camera_manager = ACameraManager_create();
+            let mut camera_id_list_raw = null_mut();
+            let camera_status =
+                ACameraManager_getCameraIdList(camera_manager, &mut camera_id_list_raw);
+            if camera_status != camera_status_t::ACAMERA_OK || camera_id_list_raw.is_null() {
+                ACameraManager_delete(camera_manager);
+                return Err(anyhow!(
+                    "Failed to get camera id list (reason: {:?})",
+                    camera_status
+                ));
+            }
+            let camera_id_list = &*camera_id_list_raw;
+            let camera_ids: Vec<String> = if camera_id_list.numCameras < 1 {
+                vec![]
+            } else {
+                slice::from_raw_parts(camera_id_list.cameraIds, camera_id_list.numCameras as usize)
+                    .iter()
+                    .map(|v| CStr::from_ptr(*v).to_str().unwrap_or("").to_string())
+                    .collect()
+            };
+            ACameraManager_deleteCameraIdList(camera_id_list_raw);
+            ACameraManager_delete(camera_manager);
+            Ok(camera_ids)
+        }
+    }
+
+    /// 获取相机的朝向、分辨率、格式和帧率
+    pub fn camera_info(camera_id: &str) -> Result<CameraInfo> {
+        unsafe {
+            let camera_manager = ACameraManager_create();
+            let camera_id_c = CString::new(camera_id)?;
+            let mut camera_metadata = null_mut();
+            let camera_status = ACameraManager_getCameraCharacteristics(
+                camera_manager,
+                camera_id_c.as_ptr(),
+                &mut camera_metadata,
+            );
+            if camera_status != camera_status_t::ACAMERA_OK {
+                ACameraManager_delete(camera_manager);
+                return Err(anyhow!(
+                    "Failed to get camera meta data of id:{camera_id} (reason: {:?})",
+                    camera_status
+                ));
+            }
+
+            let (lens_facing, sensor_orientation) =
+                AndroidCamera::get_sensor_orientation(camera_metadata);
+            let video_sizes = AndroidCamera::get_video_size(camera_metadata);
+            let pixel_formats = AndroidCamera::get_output_formats(camera_metadata);
+            let fps_ranges = AndroidCamera::get_fps_ranges(camera_metadata);
+
+            ACameraMetadata_free(camera_metadata);
+            ACameraManager_delete(camera_manager);
+
+            // ACAMERA_LENS_FACING: 0前置 1后置 2外接
+            let (lens_facing, name) = match lens_facing {
+                0 => (LensFacing::Front, "Front"),
+                1 => (LensFacing::Back, "Back"),
+                2 => (LensFacing::External, "External"),
+                _ => (LensFacing::Unknown, "Unknown"),
+            };
+            Ok(CameraInfo {
+                id: camera_id.to_string(),
+                name: format!("{name} camera {camera_id}"),
+                lens_facing,
+                sensor_orientation,
+                supported_sizes: video_sizes?
+                    .into_iter()
+                    .map(|(width, height, _format)| (width as u32, height as u32))
+                    .collect(),
+                pixel_formats,
+                fps_ranges,
+            })
+        }
+    }
+
     fn get_sensor_orientation(camera_metadata: *mut ACameraMetadata) -> (u8, i32) {
         unsafe {
             let mut lens_facing: ACameraMetadata_const_entry = zeroed();
@@ -265,6 +429,15 @@ impl AndroidCamera {
 
     // 获取相机支持的分辨率
     fn get_video_size(camera_metadata: *mut ACameraMetadata) -> Result<Vec<(i32, i32, i32)>> {
+        let formats = AndroidCamera::get_output_sizes(camera_metadata, AIMAGE_FORMATS::AIMAGE_FORMAT_YUV_420_888)?;
+        for (width, height, _format) in &formats {
+            info!("YUV_420: {width}x{height}");
+        }
+        Ok(formats)
+    }
+
+    // 获取相机 image_format 格式支持输出的分辨率
+    fn get_output_sizes(camera_metadata: *mut ACameraMetadata, image_format: AIMAGE_FORMATS) -> Result<Vec<(i32, i32, i32)>> {
         unsafe {
             let mut available_configs: ACameraMetadata_const_entry = zeroed();
             let camera_status = ACameraMetadata_getConstEntry(
@@ -298,10 +471,9 @@ impl AndroidCamera {
                     continue;
                 }
 
-                if format == AIMAGE_FORMATS::AIMAGE_FORMAT_YUV_420_888.0 as i32 {
+                if format == image_format.0 as i32 {
                     let width = data_i32_list[i * 4 + 1];
                     let height = data_i32_list[i * 4 + 2];
-                    info!("YUV_420: {width}x{height}");
                     formats.push((width, height, format));
                 }
             }
@@ -309,8 +481,84 @@ impl AndroidCamera {
         }
     }
 
+    // 获取相机支持输出的图像格式
+    fn get_output_formats(camera_metadata: *mut ACameraMetadata) -> Vec<String> {
+        let names = [
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_YUV_420_888, "YUV_420_888"),
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_JPEG, "JPEG"),
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_RGBA_8888, "RGBA_8888"),
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_RAW16, "RAW16"),
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_RAW10, "RAW10"),
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_PRIVATE, "PRIVATE"),
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_Y8, "Y8"),
+            (AIMAGE_FORMATS::AIMAGE_FORMAT_HEIC, "HEIC"),
+        ];
+        let mut formats = vec![];
+        unsafe {
+            let mut available_configs: ACameraMetadata_const_entry = zeroed();
+            let camera_status = ACameraMetadata_getConstEntry(
+                camera_metadata,
+                acamera_metadata_tag::ACAMERA_SCALER_AVAILABLE_STREAM_CONFIGURATIONS.0,
+                &mut available_configs,
+            );
+            if camera_status != camera_status_t::ACAMERA_OK {
+                return formats;
+            }
+            // 数据格式: format, width, height, input?
+            let data_i32_list: &[i32] = slice::from_raw_parts(
+                available_configs.data.i32_,
+                available_configs.count as usize,
+            );
+            for config in data_i32_list.chunks_exact(4) {
+                if config[3] != 0 {
+                    continue;
+                }
+                let name = names
+                    .iter()
+                    .find(|(format, _)| format.0 as i32 == config[0])
+                    .map(|(_, name)| name.to_string())
+                    .unwrap_or(format!("0x{:x}", config[0]));
+                if !formats.contains(&name) {
+                    formats.push(name);
+                }
+            }
+        }
+        formats
+    }
+
+    // 获取自动曝光支持的帧率范围
+    fn get_fps_ranges(camera_metadata: *mut ACameraMetadata) -> Vec<(u32, u32)> {
+        unsafe {
+            let mut fps_ranges: ACameraMetadata_const_entry = zeroed();
+            let camera_status = ACameraMetadata_getConstEntry(
+                camera_metadata,
+                acamera_metadata_tag::ACAMERA_CONTROL_AE_AVAILABLE_TARGET_FPS_RANGES.0,
+                &mut fps_ranges,
+            );
+            if camera_status != camera_status_t::ACAMERA_OK {
+                return vec![];
+            }
+            // 数据格式: min, max
+            slice::from_raw_parts(fps_ranges.data.i32_, fps_ranges.count as usize)
+                .chunks_exact(2)
+                .map(|range| (range[0] as u32, range[1] as u32))
+                .collect()
+        }
+    }
+
     pub fn close(&mut self) {
+        let recording = self.video_recording.lock().unwrap().take();
+        if let Some(VideoRecording::Started(recorder)) = recording {
+            if let Err(err) = AndroidCamera::finish_recording(recorder) {
+                error!("停止录像失败: {:?}", err);
+            }
+        }
         unsafe {
+            if !self.capture_session.is_null() {
+                ACameraCaptureSession_close(self.capture_session);
+                self.capture_session = null_mut();
+            }
+
             if !self.image_reader.is_null() {
                 ACaptureRequest_free(self.capture_request);
                 self.capture_request = null_mut();
@@ -339,15 +587,56 @@ impl AndroidCamera {
                 ACaptureSessionOutputContainer_free(self.capture_session_output_container);
                 self.capture_session_output_container = null_mut();
             }
+
+            if !self.still_target.is_null() {
+                ACameraOutputTarget_free(self.still_target);
+                self.still_target = null_mut();
+            }
+
+            if !self.still_output.is_null() {
+                ACaptureSessionOutput_free(self.still_output);
+                self.still_output = null_mut();
+            }
+
+            if !self.still_reader.is_null() {
+                AImageReader_delete(self.still_reader);
+                self.still_reader = null_mut();
+            }
         }
+        self.camera_id = None;
         info!("Close Camera");
     }
 
+    /// 没有可用的 wgpu adapter 时返回 None，使用 CPU 解码
+    fn create_gpu_decoder(&self, width: u32, height: u32) -> Option<YuvGpuDecoder> {
+        let decoder = YuvGpuDecoder::new(width, height, ColorSpace::default())
+            .map_err(|err| error!("创建 GPU 解码器失败，使用 CPU 解码: {:?}", err))
+            .ok()?;
+        Some(self.with_lut(decoder))
+    }
+
+    /// 在界面的设备上创建解码器，设备不满足要求时返回 None，预览帧回读后显示
+    fn create_display_decoder(&self, gpu: &GpuContext, width: u32, height: u32) -> Option<YuvGpuDecoder> {
+        let decoder = YuvGpuDecoder::with_context(gpu, width, height, ColorSpace::default())
+            .map_err(|err| error!("在界面的设备上创建 GPU 解码器失败，预览帧回读后显示: {:?}", err))
+            .ok()?;
+        Some(self.with_lut(decoder))
+    }
+
+    fn with_lut(&self, mut decoder: YuvGpuDecoder) -> YuvGpuDecoder {
+        if let Some(lut) = self.lut.as_ref() {
+            decoder.pipeline_mut().push(FilterStage::lut(lut));
+        }
+        decoder
+    }
+
     pub fn start_preview(&mut self, width: u32, height: u32) -> Result<()> {
         self.preview_width = width;
         self.preview_height = height;
-        self.decoder_gpu = Some(YuvGpuDecoder::new(width, height)?);
+        self.decoder_gpu = self.create_gpu_decoder(width, height);
+        self.gpu_in_flight.clear();
         self.rgba_buffer = vec![0; (width * height * 4) as usize];
+        self.decode_buffer = vec![0; (width * height * 4) as usize];
         self.create_image_reader(width, height, AIMAGE_FORMATS::AIMAGE_FORMAT_YUV_420_888)?;
         unsafe {
             let camera_status = ACameraDevice_createCaptureRequest(
@@ -420,12 +709,18 @@ impl AndroidCamera {
                 session_output,
             );
 
-            let mut capture_session = null_mut();
+            // 拍照的 JPEG 输出需要在创建会话之前加入，失败时只能预览
+            if let Some((still_width, still_height)) = self.still_size {
+                if let Err(err) = self.create_still_output(still_width, still_height) {
+                    error!("创建拍照输出失败: {:?}", err);
+                }
+            }
+
             let camera_status = ACameraDevice_createCaptureSession(
                 self.camera_device,
                 self.capture_session_output_container,
                 &self.capture_session_state_callbacks,
-                &mut capture_session,
+                &mut self.capture_session,
             );
 
             if camera_status != camera_status_t::ACAMERA_OK {
@@ -436,7 +731,7 @@ impl AndroidCamera {
             }
 
             let camera_status = ACameraCaptureSession_setRepeatingRequest(
-                capture_session,
+                self.capture_session,
                 null_mut(),
                 1,
                 &mut self.capture_request,
@@ -453,9 +748,258 @@ impl AndroidCamera {
         Ok(())
     }
 
+    /// 创建拍照用的 JPEG AImageReader，加入会话的输出
+    unsafe fn create_still_output(&mut self, width: i32, height: i32) -> Result<()> {
+        let res = AImageReader_new(
+            width,
+            height,
+            AIMAGE_FORMATS::AIMAGE_FORMAT_JPEG.0 as i32,
+            2,
+            &mut self.still_reader,
+        );
+        if res != media_status_t::AMEDIA_OK {
+            return Err(anyhow!("create still Image Reader error res={:?}.", res));
+        }
+
+        let mut native_window: *mut ANativeWindow = null_mut();
+        let res = AImageReader_getWindow(self.still_reader, &mut native_window);
+        if res != media_status_t::AMEDIA_OK {
+            return Err(anyhow!("AImageReader_getWindow error res={:?}.", res));
+        }
+
+        unsafe extern "C" fn on_still_available(context: *mut c_void, still_reader: *mut AImageReader) {
+            let camera = &*(context as *const AndroidCamera);
+            if let Err(err) = camera.on_still_available(still_reader) {
+                error!("读取照片失败： {:?}", err)
+            }
+        }
+
+        self.still_listener.context = (self as *mut AndroidCamera) as *mut c_void;
+        self.still_listener.onImageAvailable = Some(on_still_available);
+        let res = AImageReader_setImageListener(self.still_reader, &mut self.still_listener);
+        if res != media_status_t::AMEDIA_OK {
+            return Err(anyhow!("set still Image Listener error res={:?}.", res));
+        }
+
+        ACameraOutputTarget_create(native_window, &mut self.still_target);
+        ACaptureSessionOutput_create(native_window, &mut self.still_output);
+        ACaptureSessionOutputContainer_add(self.capture_session_output_container, self.still_output);
+        info!("拍照输出: {width}x{height}");
+        Ok(())
+    }
+
+    /// 取出 JPEG 输出中的所有照片，发送给正在等待的拍照请求，没有请求在等待时丢弃
+    unsafe fn on_still_available(&self, still_reader: *mut AImageReader) -> Result<()> {
+        let mut image = null_mut();
+        while AImageReader_acquireNextImage(still_reader, &mut image) == media_status_t::AMEDIA_OK {
+            let mut data = null_mut();
+            let mut len = 0;
+            let res = AImage_getPlaneData(image, 0, &mut data, &mut len);
+            let jpeg = (res == media_status_t::AMEDIA_OK && !data.is_null())
+                .then(|| slice::from_raw_parts(data, len as usize).to_vec());
+            let mut timestamp_ns = 0;
+            let _ = AImage_getTimestamp(image, &mut timestamp_ns);
+            AImage_delete(image);
+            let jpeg = jpeg.ok_or(anyhow!("AImage_getPlaneData error res={:?}.", res))?;
+            if let Some(sender) = self.still_images.lock().unwrap().as_ref() {
+                let _ = sender.send((jpeg, timestamp_ns));
+            }
+        }
+        Ok(())
+    }
+
+    /// 用 TEMPLATE_STILL_CAPTURE 请求连续拍 count 张最大尺寸的 JPEG
+    ///
+    /// 这里只提交请求，照片在返回的 PendingStill 中等待，解码后按显示方向旋转，每张最多等待 STILL_TIMEOUT
+    pub fn capture_still(&mut self, count: usize) -> Result<PendingStill> {
+        if self.capture_session.is_null() || self.still_reader.is_null() {
+            return Err(anyhow!("preview not started or JPEG output not available"));
+        }
+        // 照片和预览的方向相同，但前置摄像头不镜像
+        let orientation = Orientation::new(self.display_orientation()?.rotation(), false);
+        let camera_id = self.camera_id.clone().unwrap_or_default();
+        // 换成新的通道，之前超时的请求残留的照片不会发给这次请求
+        let (image_sender, images) = channel();
+        let (exposure_sender, exposures) = channel();
+        *self.still_images.lock().unwrap() = Some(image_sender);
+        *self.still_exposures.lock().unwrap() = Some(exposure_sender);
+        unsafe {
+            let mut request = null_mut();
+            let camera_status = ACameraDevice_createCaptureRequest(
+                self.camera_device,
+                ACameraDevice_request_template::TEMPLATE_STILL_CAPTURE,
+                &mut request,
+            );
+            if camera_status != camera_status_t::ACAMERA_OK {
+                return Err(anyhow!(
+                    "Failed to create still capture request (reason: {:?})",
+                    camera_status
+                ));
+            }
+            ACaptureRequest_addTarget(request, self.still_target);
+            // 相机输出最高质量，最终的质量由调用者编码时决定
+            let quality: u8 = 100;
+            ACaptureRequest_setEntry_u8(request, acamera_metadata_tag::ACAMERA_JPEG_QUALITY.0, 1, &quality);
+
+            unsafe extern "C" fn on_capture_completed(
+                context: *mut c_void,
+                _session: *mut ACameraCaptureSession,
+                _request: *mut ACaptureRequest,
+                result: *const ACameraMetadata,
+            ) {
+                let camera = &*(context as *const AndroidCamera);
+                if let Some(sender) = camera.still_exposures.lock().unwrap().as_ref() {
+                    let _ = sender.send(read_exposure(result));
+                }
+            }
+
+            self.still_capture_callbacks.context = (self as *mut _) as *mut c_void;
+            self.still_capture_callbacks.onCaptureCompleted = Some(on_capture_completed);
+            // 同一个请求提交 count 次，按顺序拍摄
+            let mut requests = vec![request; count];
+            let camera_status = ACameraCaptureSession_capture(
+                self.capture_session,
+                &mut self.still_capture_callbacks,
+                count as i32,
+                requests.as_mut_ptr(),
+                null_mut(),
+            );
+            ACaptureRequest_free(request);
+            if camera_status != camera_status_t::ACAMERA_OK {
+                return Err(anyhow!("Failed to capture still (reason: {:?})", camera_status));
+            }
+        }
+
+        Ok(Box::new(move || {
+            (0..count)
+                .map(|_| {
+                    let (jpeg, timestamp_ns) = images.recv_timeout(STILL_TIMEOUT).map_err(|_| anyhow!("still capture timeout"))?;
+                    // 拍摄结果可能比照片晚到
+                    let exposure = exposures.recv_timeout(Duration::from_millis(500)).unwrap_or_else(|_| {
+                        error!("没有收到拍摄结果，EXIF 不包含曝光参数");
+                        Exposure::default()
+                    });
+                    let rgba = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg)?.to_rgba8();
+                    let (width, height) = rgba.dimensions();
+                    let mut data = vec![];
+                    let (width, height) = convert::transform_rgba(&rgba, width, height, orientation, &mut data);
+                    let mut frame = Frame::new(data, PixelFormat::Rgba8, width, height);
+                    frame.timestamp_ns = timestamp_ns;
+                    frame.orientation = orientation;
+                    frame.camera_id = camera_id.clone();
+                    frame.exposure = exposure;
+                    Ok(frame)
+                })
+                .collect()
+        }))
+    }
+
+    /// 开始录像，编码器在下一帧到来时按帧的大小创建
+    pub fn start_recording(&mut self, path: &Path, options: &EncodeOptions) -> Result<()> {
+        if self.capture_session.is_null() {
+            return Err(anyhow!("preview is not started"));
+        }
+        let mut recording = self.video_recording.lock().unwrap();
+        if recording.is_some() {
+            return Err(anyhow!("already recording"));
+        }
+        *recording = Some(VideoRecording::Pending(path.to_path_buf(), *options));
+        Ok(())
+    }
+
+    pub fn pause_recording(&mut self, paused: bool) -> Result<()> {
+        let recording = self.video_recording.lock().unwrap();
+        let Some(VideoRecording::Started(recorder)) = recording.as_ref() else {
+            return Err(anyhow!("not recording"));
+        };
+        if paused {
+            recorder.pause();
+        } else {
+            recorder.resume();
+        }
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<EncodeStats> {
+        // 先从锁中取出，等待编码器写完文件时不阻塞回调线程
+        let recording = self.video_recording.lock().unwrap().take();
+        match recording {
+            Some(VideoRecording::Started(recorder)) => AndroidCamera::finish_recording(recorder),
+            Some(VideoRecording::Pending(..)) => Err(anyhow!("no frame recorded")),
+            None => Err(anyhow!("not recording")),
+        }
+    }
+
+    fn finish_recording(recorder: EncodeRecorder) -> Result<EncodeStats> {
+        let stats = recorder.stop()?;
+        info!("录像结束: {:?}", stats);
+        Ok(stats)
+    }
+
+    /// 创建编码器和 MP4 封装，相机输出的帧没有旋转，由播放器按 orientation hint 旋转
+    ///
+    /// 前置摄像头的录像不镜像，和照片一致
+    fn create_recorder(&self, path: &Path, options: &EncodeOptions, width: u32, height: u32) -> Result<EncodeRecorder> {
+        let encoder = MediaCodecEncoder::new(width, height, options)?;
+        let muxer = MediaMuxer::new(path)?;
+        let rotation = self.display_orientation()?.rotation();
+        info!("开始录像: {path:?} {width}x{height} 旋转{rotation}°");
+        Ok(EncodeRecorder::start(EncodeSession::new(encoder, muxer, rotation)?))
+    }
+
+    /// 把裁剪后的 YUV 平面复制为 I420 帧送入编码器，宽高去掉奇数的最后一列、一行
+    fn record_planes(&mut self, planes: &[Plane], width: u32, height: u32, timestamp_ns: i64) -> Result<()> {
+        let (width, height) = (width & !1, height & !1);
+        {
+            let mut recording = self.video_recording.lock().unwrap();
+            if let Some(VideoRecording::Pending(path, options)) = recording.as_ref() {
+                // 创建失败时放弃这次录像
+                match self.create_recorder(path, options, width, height) {
+                    Ok(recorder) => *recording = Some(VideoRecording::Started(recorder)),
+                    Err(err) => {
+                        *recording = None;
+                        return Err(err);
+                    }
+                }
+            }
+            if !matches!(*recording, Some(VideoRecording::Started(_))) {
+                return Ok(());
+            }
+        }
+        let mut data = vec![];
+        convert::pack_yuv420(planes, width, height, PixelFormat::I420, &mut data)?;
+        let mut frame = Frame::new(data, PixelFormat::I420, width, height);
+        frame.timestamp_ns = timestamp_ns;
+        frame.sequence = self.sequence;
+        frame.camera_id = self.camera_id.clone().unwrap_or_default();
+        // 复制平面时不持有锁，期间停止了录像则丢弃这一帧
+        if let Some(VideoRecording::Started(recorder)) = self.video_recording.lock().unwrap().as_ref() {
+            recorder.send(frame);
+        }
+        Ok(())
+    }
+
+    /// 当前屏幕方向下预览需要的旋转和镜像
+    fn display_orientation(&self) -> Result<Orientation> {
+        let display_rotation = get_display_rotation(&self.app)?;
+        let rotation_degree = if display_rotation == 0{
+            self.sensor_orientation
+        }else if display_rotation == 90{
+            //正向横屏
+            0
+        }else if display_rotation == 180{
+            //反向竖屏(不常见)
+            0
+        }else{
+            //反向横屏
+            self.sensor_orientation + 90
+        };
+        // 前置摄像头(ACAMERA_LENS_FACING=0)像镜子一样显示
+        Ok(Orientation::new(rotation_degree, self.lens_facing == 0))
+    }
+
     fn on_image_available(&mut self) -> Result<()> {
         unsafe {
-            let t = Instant::now();
             let mut image = null_mut();
             let media_status = AImageReader_acquireLatestImage(self.image_reader, &mut image);
             if media_status != media_status_t::AMEDIA_OK {
@@ -470,6 +1014,41 @@ impl AndroidCamera {
                 return Err(anyhow!("{msg}"));
             }
 
+            let res = self.process_image(image);
+            AImage_delete(image);
+            res
+        }
+    }
+
+    /// 在回调线程中应用 set_lut() 设置的 LUT
+    fn apply_pending_lut(&mut self) {
+        let Some(lut) = self.pending_lut.lock().unwrap().take() else {
+            return;
+        };
+        for decoder in [self.decoder_gpu.as_mut(), self.decoder_display.as_mut()].into_iter().flatten() {
+            let pipeline = decoder.pipeline_mut();
+            pipeline.remove("lut");
+            if let Some(lut) = lut.as_ref() {
+                pipeline.push(FilterStage::lut(lut));
+            }
+        }
+        self.lut = lut;
+    }
+
+    /// 在回调线程中应用 set_display_device() 设置的设备，解码器按下一帧的大小创建
+    fn apply_pending_display(&mut self) {
+        let Some(display) = self.pending_display.lock().unwrap().take() else {
+            return;
+        };
+        self.display = display;
+        self.decoder_display = None;
+    }
+
+    /// 解码一帧 YUV_420_888 图像并发送
+    fn process_image(&mut self, image: *mut AImage) -> Result<()> {
+        self.apply_pending_lut();
+        self.apply_pending_display();
+        unsafe {
             let mut format = 0;
             let res = AImageReader_getFormat(self.image_reader, &mut format);
             if res != media_status_t::AMEDIA_OK {
@@ -501,99 +1080,136 @@ impl AndroidCamera {
                 return Err(anyhow!("AImage_getCropRect error res={:?}.", res));
             }
 
-            let mut y_stride = 0;
-            let mut uv_stride = 0;
-            let mut y_pixel = null_mut();
-            let mut u_pixel = null_mut();
-            let mut v_pixel = null_mut();
-            let mut y_len = 0;
-            let mut v_len = 0;
-            let mut u_len = 0;
-            let mut vu_pixel_stride = 0;
-
-            AImage_getPlaneRowStride(image, 0, &mut y_stride);
-            AImage_getPlaneRowStride(image, 1, &mut uv_stride);
-            AImage_getPlaneData(image, 0, &mut y_pixel, &mut y_len);
-            AImage_getPlaneData(image, 1, &mut v_pixel, &mut v_len);
-            AImage_getPlaneData(image, 2, &mut u_pixel, &mut u_len);
-            AImage_getPlanePixelStride(image, 1, &mut vu_pixel_stride);
-
-            // println!("y_stride={y_stride}");
-            // println!("u_stride={uv_stride}");
-
-            // println!("y_len={y_len}");
-            // println!("u_len={u_len}");
-            // println!("v_len={v_len}");
-            // println!("vu_pixel_stride={vu_pixel_stride}");
-
             /*
-            图像宽度:1280
-            图像高度: 960
-            y_stride=1280
-            u_stride=1280
-            v_stride=1280
-            y_len=1228800
-            u_len=614399
-            v_len=614399
-            vu_pixel_stride=2
-
-            V 的指针和 U 的指针，实际上指向的是一块数据，他们之前相差1个像素，所以不等于宽度/2
-            Y 的指针是 Y数据+UV数据块的开头，实际上整个AImage是一个完整的yuv数据块
-
+            不同设备的 YUV_420_888 布局不同:
+            - 行尾可能有填充，row_stride 大于宽度
+            - U、V 可能是交错存放的(pixel_stride=2，NV21/NV12)，也可能是独立的平面(pixel_stride=1，I420)
+            所以按平面描述符读取，不假设整帧是一块连续的数据
              */
+            let planes = image_planes(image)?;
+
+            // 裁剪区域，起点对齐到偶数保证色度和亮度对齐，宽高可以是奇数
+            let left = src_rect.left.clamp(0, width) & !1;
+            let top = src_rect.top.clamp(0, height) & !1;
+            let crop_width = src_rect.right.clamp(left, width) - left;
+            let crop_height = src_rect.bottom.clamp(top, height) - top;
+            let (crop_width, crop_height) = if crop_width == 0 || crop_height == 0 {
+                (width, height)
+            } else {
+                (crop_width, crop_height)
+            };
+            let planes = convert::crop_planes(&planes, PixelFormat::I420, left as u32, top as u32)?;
 
-            let yuv_data =
-                slice::from_raw_parts(y_pixel, ((width * height) + (width * height) / 2) as usize);
+            let (width, height) = (crop_width, crop_height);
+            if self.rgba_buffer.len() != (width * height * 4) as usize {
+                info!("预览帧裁剪后的大小: {width}x{height}");
+                self.rgba_buffer = vec![0; (width * height * 4) as usize];
+                self.decode_buffer = vec![0; (width * height * 4) as usize];
+            }
+            let decoder_size = self.decoder_gpu.as_ref().map(|d| d.size());
+            if decoder_size.is_some() && decoder_size != Some((width as u32, height as u32)) {
+                self.decoder_gpu = self.create_gpu_decoder(width as u32, height as u32);
+                self.gpu_in_flight.clear();
+            }
+            if let Some(gpu) = self.display.as_ref() {
+                if self.decoder_display.as_ref().map(|d| d.size()) != Some((width as u32, height as u32)) {
+                    self.decoder_display = self.create_display_decoder(gpu, width as u32, height as u32);
+                    if self.decoder_display.is_none() {
+                        // 界面的设备不能用于解码，不再尝试
+                        self.display = None;
+                    }
+                }
+            }
 
             let mut timestamp_ns = 0;
             let _ = AImage_getTimestamp(image, &mut timestamp_ns);
 
+            if self.video_recording.lock().unwrap().is_some() {
+                if let Err(err) = self.record_planes(&planes, width as u32, height as u32, timestamp_ns) {
+                    error!("录像失败: {:?}", err);
+                }
+            }
+
             // info!("gpu yuv_data:{}", yuv_data.len());
             let t = Instant::now();
             // info!("start gpu decode...");
-            //GPU转换耗时 6~8毫秒左右，有时会是10ms左右
-            let display_rotation = get_display_rotation(&self.app)?;
-            let rotation_degree = if display_rotation == 0{
-                self.sensor_orientation
-            }else if display_rotation == 90{
-                //正向横屏
-                0
-            }else if display_rotation == 180{
-                //反向竖屏(不常见)
-                0
-            }else{
-                //反向横屏
-                self.sensor_orientation + 90
+            //GPU转换耗时 6~8毫秒左右，有时会是10ms左右，回读和下一帧的上传重叠，预览延迟一帧
+            let orientation = self.display_orientation()?;
+
+            // 帧比窗口大时在 GPU 上缩小到窗口大小，减少回读和复制的数据量
+            let (frame_width, frame_height) = orientation.output_size(width as u32, height as u32);
+            let scale = self
+                .app
+                .native_window()
+                .map(|window| (window.width() as u32, window.height() as u32))
+                .filter(|&(w, h)| w > 0 && h > 0 && (frame_width > w || frame_height > h))
+                .map(|(w, h)| OutputScale::new(w, h, ScaleMode::Fit, ScaleFilter::Area));
+
+            let (output_width, output_height, timestamp_ns, orientation, gpu_frame) = match (self.decoder_display.as_mut(), self.decoder_gpu.as_mut()) {
+                // 界面和解码器共用设备，没有订阅者需要像素数据时不回读
+                (Some(decoder), readback) if !self.image_sender.wants_data() => {
+                    // 丢弃切换之前还没有取回的帧
+                    if let Some(readback) = readback {
+                        while readback.read(&mut self.rgba_buffer)?.is_some() {}
+                    }
+                    self.gpu_in_flight.clear();
+                    decoder.set_output_scale(scale)?;
+                    let gpu_frame = decoder.render(&planes, orientation)?;
+                    let (w, h) = decoder.output_size();
+                    (w as i32, h as i32, timestamp_ns, orientation, Some(gpu_frame))
+                }
+                (_, Some(decoder)) => {
+                    decoder.set_output_scale(scale)?;
+                    decoder.submit(&planes, orientation)?;
+                    self.gpu_in_flight.push_back((timestamp_ns, orientation));
+                    if decoder.in_flight() < READBACK_BUFFERS {
+                        // 回读缓冲区还没有用完，等下一帧再取回
+                        return Ok(());
+                    }
+                    let (w, h) = decoder.read(&mut self.rgba_buffer)?.ok_or(anyhow!("no frame in flight"))?;
+                    let (timestamp_ns, orientation) = self.gpu_in_flight.pop_front().unwrap_or_default();
+                    (w as i32, h as i32, timestamp_ns, orientation, None)
+                }
+                (_, None) => {
+                    // CPU 多线程解码，再旋转、镜像
+                    convert::convert_parallel(
+                        &planes,
+                        PixelFormat::I420,
+                        ColorSpace::default(),
+                        width as u32,
+                        height as u32,
+                        &mut self.decode_buffer,
+                        PixelFormat::Rgba8,
+                    )?;
+                    let (w, h) = convert::transform_rgba(&self.decode_buffer, width as u32, height as u32, orientation, &mut self.rgba_buffer);
+                    if let Some(lut) = self.lut.as_ref() {
+                        lut.apply_rgba(&mut self.rgba_buffer);
+                    }
+                    (w as i32, h as i32, timestamp_ns, orientation, None)
+                }
             };
-
-            self.decoder_gpu.as_mut().unwrap().decode(
-                &yuv_data,
-                &mut self.rgba_buffer,
-                rotation_degree,
-            )?;
-            let (output_width, output_height) = match self
-                .decoder_gpu
-                .as_ref()
-                .unwrap()
-                .rotate_output_size
-                .as_ref()
-            {
-                None => (width, height),
-                Some(o) => (o.width as i32, o.height as i32),
+            let mut frame = match gpu_frame {
+                Some(gpu_frame) => Frame::from_gpu(gpu_frame, output_width as u32, output_height as u32),
+                None => Frame::new(self.rgba_buffer.clone(), PixelFormat::Rgba8, output_width as u32, output_height as u32),
             };
-            let buf = SharedPixelBuffer::clone_from_slice(&self.rgba_buffer, output_width as u32, output_height as u32);
-            self.image_sender.send(buf).map_err(|err| anyhow!("{:?}", err))?;
+            frame.timestamp_ns = timestamp_ns;
+            frame.sequence = self.sequence;
+            frame.orientation = orientation;
+            frame.camera_id = self.camera_id.clone().unwrap_or_default();
+            self.sequence += 1;
+            self.image_sender.send(frame);
             // info!("转码+旋转+Send耗时:{}ms sensor_orientation={} display_rotation={display_rotation}", t.elapsed().as_millis(), self.sensor_orientation);
 
             // 预览回调帧率正常是 30FPS
             self.frame_count += 1;
             if self.timer.elapsed().as_millis() > 1000 {
                 info!("预览 FPS:{}", self.frame_count);
+                if let Some(decoder) = self.decoder_gpu.as_ref() {
+                    info!("GPU 解码耗时: {:?}", decoder.timings());
+                }
                 self.timer = Instant::now();
                 self.frame_count = 0;
             }
-
-            AImage_delete(image);
             Ok(())
         }
     }
@@ -643,593 +1259,133 @@ impl AndroidCamera {
     }
 }
 
-impl Drop for AndroidCamera {
-    fn drop(&mut self) {
-        let _ = self.close();
+impl CameraBackend for AndroidCamera {
+    fn list_cameras(&mut self) -> Result<Vec<CameraInfo>> {
+        AndroidCamera::camera_id_list()?
+            .iter()
+            .map(|camera_id| AndroidCamera::camera_info(camera_id))
+            .collect()
     }
-}
 
-/// android: YUV420SP 转 rgb
-pub fn decode_yuv420sp(data: &[u8], width: i32, height: i32) -> Vec<u8> {
-    let frame_size = width * height;
-    let mut yp = 0;
-    let mut rgba_data = Vec::with_capacity(frame_size as usize * 4);
-    for j in 0..height {
-        let (mut uvp, mut u, mut v) = ((frame_size + (j >> 1) * width) as usize, 0, 0);
-        for i in 0..width {
-            let mut y = (0xff & data[yp] as i32) - 16;
-            if y < 0 {
-                y = 0;
-            }
-            if i & 1 == 0 {
-                v = (0xff & data[uvp] as i32) - 128;
-                uvp += 1;
-                u = (0xff & data[uvp] as i32) - 128;
-                uvp += 1;
-            }
+    fn open(&mut self, camera_id: &str) -> Result<()> {
+        AndroidCamera::open(self, camera_id)
+    }
 
-            let y1192 = 1192 * y;
-            let mut r = y1192 + 1634 * v;
-            let mut g = y1192 - 833 * v - 400 * u;
-            let mut b = y1192 + 2066 * u;
+    fn start_preview(&mut self, width: u32, height: u32) -> Result<()> {
+        AndroidCamera::start_preview(self, width, height)
+    }
 
-            if r < 0 {
-                r = 0;
-            } else if r > 262143 {
-                r = 262143;
-            };
-            if g < 0 {
-                g = 0;
-            } else if g > 262143 {
-                g = 262143;
-            }
-            if b < 0 {
-                b = 0;
-            } else if b > 262143 {
-                b = 262143;
-            }
+    fn stop_preview(&mut self) -> Result<()> {
+        self.close();
+        Ok(())
+    }
 
-            let r = (r >> 10) & 0xff;
-            let g = (g >> 10) & 0xff;
-            let b = (b >> 10) & 0xff;
-            rgba_data.extend_from_slice(&[r as u8, g as u8, b as u8, 255]);
-            yp += 1;
+    fn capabilities(&mut self) -> Result<CameraInfo> {
+        match self.camera_id.as_ref() {
+            Some(camera_id) => AndroidCamera::camera_info(camera_id),
+            None => Err(anyhow!("camera not opened")),
         }
     }
 
-    rgba_data
-}
-
-struct YuvGpuDecoder {
-    device: Device,
-    queue: Queue,
-    width: u32,
-    height: u32,
-    y_texture: Texture,
-    u_texture: Texture,
-    easu_texture: Texture,
-    texture_size: wgpu::Extent3d,
-    u_size: wgpu::Extent3d,
-    compute_pipeline_yuv: ComputePipeline,
-    compute_yuv_bind_group: BindGroup,
-    padded_bytes_per_row: usize,
-    unpadded_bytes_per_row: usize,
-
-    rgba_texture_view: TextureView,
-    rotate_compute_pipeline: ComputePipeline,
-    rotate_bind_group: Option<BindGroup>,
-    rotate_output_texture: Option<Texture>,
-    rotate_output_size: Option<wgpu::Extent3d>,
-    last_rotate_degree: i32,
-}
-
-impl YuvGpuDecoder {
-    pub fn new(width: u32, height: u32) -> Result<Self> {
-        info!("create YuvGpuDecoder {width}x{height}");
-        //------------------------------------------------------
-        // 初始化硬件设备
-        //------------------------------------------------------
-
-        info!("create YuvGpuDecoder instance...");
-
-        let instance = wgpu::Instance::default();
-
-        info!("create YuvGpuDecoder adapter...");
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptionsBase {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: None,
-            })
-            .block_on()
-            .ok_or(anyhow::anyhow!("Couldn't create the adapter"))?;
-
-        info!("create YuvGpuDecoder device,adapter...");
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER,
-                    required_limits: Limits::default(),
-                },
-                None,
-            )
-            .block_on()?;
-
-        //------------------------------------------------------
-        // 创建 pipeline layout、compute pipeline、bind group layout 和 shader module
-        //------------------------------------------------------
-        info!("create YuvGpuDecoder compute_texture_yuv_bind_group_layout...");
-        let compute_texture_yuv_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Sampler(
-                            // SamplerBindingType::Comparison is only for TextureSampleType::Depth
-                            // SamplerBindingType::Filtering if the sample_type of the texture is:
-                            //     TextureSampleType::Float { filterable: true }
-                            // Otherwise you'll get an error.
-                            wgpu::SamplerBindingType::Filtering,
-                        ),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::Rgba8Unorm,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-        info!("create YuvGpuDecoder compute_yuv_pipeline_layout...");
-        let compute_yuv_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&compute_texture_yuv_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-        info!("create YuvGpuDecoder compute_pipeline_yuv...");
-        let compute_pipeline_yuv =
-            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("compute_pipeline"),
-                layout: Some(&compute_yuv_pipeline_layout),
-                module: &device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("compute_shader_module"),
-                    source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("yuv2rgb.wgsl"))),
-                }),
-                entry_point: "main",
-            });
-
-        //------------------------------------------------------
-        // 创建纹理、纹理视图、采样器和缓冲区，并设置它们的相关描述符
-        //------------------------------------------------------
-        info!("create YuvGpuDecoder texture_size...");
-        let texture_size = wgpu::Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        };
+    fn start_recording(&mut self, path: &Path, options: &EncodeOptions) -> Result<()> {
+        AndroidCamera::start_recording(self, path, options)
+    }
 
-        let u_size = wgpu::Extent3d {
-            width: width / 2,
-            height: height / 2,
-            depth_or_array_layers: 1,
-        };
-        info!("create YuvGpuDecoder y_texture...");
-        let y_texture = device.create_texture(&wgpu::TextureDescriptor {
-            // All textures are stored as 3D, we represent our 2D texture
-            // by setting depth to 1.
-            size: texture_size,
-            mip_level_count: 1, // We'll talk about this a little later
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            // Most images are stored using sRGB so we need to reflect that here.
-            format: wgpu::TextureFormat::R8Unorm,
-            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
-            // COPY_DST means that we want to copy data to this texture
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("y_texture"),
-            view_formats: &[],
-        });
-
-        info!("create YuvGpuDecoder u_texture...");
-        let u_texture = device.create_texture(&wgpu::TextureDescriptor {
-            // All textures are stored as 3D, we represent our 2D texture
-            // by setting depth to 1.
-            size: u_size,
-            mip_level_count: 1, // We'll talk about this a little later
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            // Most images are stored using sRGB so we need to reflect that here.
-            format: wgpu::TextureFormat::Rg8Unorm,
-            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
-            // COPY_DST means that we want to copy data to this texture
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("uv_texture"),
-            view_formats: &[],
-        });
-
-        info!("create YuvGpuDecoder easu_texture...");
-        let easu_texture = device.create_texture(&wgpu::TextureDescriptor {
-            // All textures are stored as 3D, we represent our 2D texture
-            // by setting depth to 1.
-            size: texture_size,
-            mip_level_count: 1, // We'll talk about this a little later
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            // Most images are stored using sRGB so we need to reflect that here.
-            // format: wgpu::TextureFormat::Rgba8Unorm,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
-            // COPY_DST means that we want to copy data to this texture
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::STORAGE_BINDING,
-            label: Some("diffuse_texture"),
-            view_formats: &[],
-        });
-
-        info!("create YuvGpuDecoder y_texture_view...");
-        let y_texture_view = y_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let u_texture_view = u_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let easu_texture_view = easu_texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let uv_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToBorder,
-            address_mode_v: wgpu::AddressMode::ClampToBorder,
-            address_mode_w: wgpu::AddressMode::ClampToBorder,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
-        info!("create YuvGpuDecoder compute_yuv_bind_group...");
-        let compute_yuv_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &compute_texture_yuv_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&y_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&u_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&uv_sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::TextureView(&easu_texture_view),
-                },
-            ],
-            label: Some("yuv_bind_group2"),
-        });
-
-        let padded_bytes_per_row = YuvGpuDecoder::padded_bytes_per_row(width);
-        let unpadded_bytes_per_row = width as usize * 4;
-
-        let rotate_compute_pipeline =
-            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("compute_pipeline"),
-                layout: None,
-                module: &device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("compute_shader_module"),
-                    source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("rotate.wgsl"))),
-                }),
-                entry_point: "main",
-            });
-
-        Ok(Self {
-            device,
-            queue,
-            width,
-            height,
-            y_texture,
-            u_texture,
-            easu_texture,
-            texture_size,
-            u_size,
-            compute_pipeline_yuv,
-            compute_yuv_bind_group,
-            padded_bytes_per_row,
-            unpadded_bytes_per_row,
-            rotate_compute_pipeline,
-            rgba_texture_view: easu_texture_view,
-            rotate_bind_group: None,
-            rotate_output_texture: None,
-            rotate_output_size: None,
-            last_rotate_degree: 0,
-        })
+    fn pause_recording(&mut self, paused: bool) -> Result<()> {
+        AndroidCamera::pause_recording(self, paused)
     }
 
-    fn decode(&mut self, data: &[u8], output: &mut [u8], rotate_degree: i32) -> Result<()> {
-        // let t = Instant::now();
-        //------------------------------------------------------
-        // YUV数据写入纹理中
-        //------------------------------------------------------
-
-        let y_data = &data[..(self.width * self.height) as usize];
-        let uv_data = &data[(self.width * self.height) as usize..];
-
-        self.queue.write_texture(
-            wgpu::ImageCopyTextureBase {
-                texture: &self.y_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &y_data,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(self.width),
-                rows_per_image: Some(self.height),
-            },
-            self.texture_size,
-        );
+    fn stop_recording(&mut self) -> Result<EncodeStats> {
+        AndroidCamera::stop_recording(self)
+    }
 
-        self.queue.write_texture(
-            wgpu::ImageCopyTextureBase {
-                texture: &self.u_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &uv_data,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(self.width),
-                rows_per_image: Some(self.height),
-            },
-            self.u_size,
-        );
+    fn capture_still(&mut self, count: usize) -> Result<PendingStill> {
+        AndroidCamera::capture_still(self, count)
+    }
 
-        //------------------------------------------------------
-        // 开始新的计算 pass
-        //------------------------------------------------------
+    /// 回调线程正在使用解码器，这里只保存，处理下一帧时再应用
+    fn set_display_device(&mut self, gpu: Option<GpuContext>) -> Result<bool> {
+        *self.pending_display.lock().unwrap() = Some(gpu);
+        Ok(true)
+    }
 
-        //是否需要旋转
-        let need_rotate = rotate_degree >= 90 && rotate_degree <= 270;
-        let need_init_rotate = self.last_rotate_degree != rotate_degree;
+    /// 回调线程正在使用 decoder_gpu，这里只保存，处理下一帧时再应用
+    fn set_lut(&mut self, lut: Option<Lut3d>) -> Result<bool> {
+        *self.pending_lut.lock().unwrap() = Some(lut);
+        Ok(true)
+    }
+}
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+impl Drop for AndroidCamera {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
 
-        {
-            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
-            cpass.set_pipeline(&self.compute_pipeline_yuv);
-            cpass.set_bind_group(0, &self.compute_yuv_bind_group, &[]);
-            cpass.dispatch_workgroups(self.width / 8, self.height / 8, 1);
+/// 读取 YUV_420_888 图像的 Y、U、V 三个平面
+///
+/// 返回的平面引用 image 内部的数据，只能在 AImage_delete 之前使用
+unsafe fn image_planes<'a>(image: *const AImage) -> Result<Vec<Plane<'a>>> {
+    let mut planes = vec![];
+    for index in 0..3 {
+        let mut data = null_mut();
+        let mut len = 0;
+        let mut row_stride = 0;
+        let mut pixel_stride = 0;
+        let res = AImage_getPlaneData(image, index, &mut data, &mut len);
+        if res != media_status_t::AMEDIA_OK || data.is_null() {
+            return Err(anyhow!("AImage_getPlaneData({index}) error res={:?}.", res));
         }
-
-        let mut rgba_output_buffer = None;
-
-        if !need_rotate {
-            //如果不需要旋转，复制处理完成的 rgba纹理到缓冲区
-            let output_buffer_size = self.padded_bytes_per_row as u64
-                * self.height as u64
-                * std::mem::size_of::<u8>() as u64;
-
-            let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                size: output_buffer_size,
-                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            });
-
-            encoder.copy_texture_to_buffer(
-                wgpu::ImageCopyTexture {
-                    aspect: wgpu::TextureAspect::All,
-                    texture: &self.easu_texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
-                },
-                wgpu::ImageCopyBuffer {
-                    buffer: &output_buffer,
-                    layout: wgpu::ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row: Some(self.padded_bytes_per_row as u32),
-                        rows_per_image: Some(self.height),
-                    },
-                },
-                self.texture_size,
-            );
-
-            rgba_output_buffer = Some(output_buffer);
+        let res = AImage_getPlaneRowStride(image, index, &mut row_stride);
+        if res != media_status_t::AMEDIA_OK {
+            return Err(anyhow!("AImage_getPlaneRowStride({index}) error res={:?}.", res));
         }
-
-        self.queue.submit(Some(encoder.finish()));
-
-        // info!("转换完成 耗时:{}ms", t.elapsed().as_millis());
-
-        //开始旋转
-        if need_rotate || need_init_rotate {
-            // let t = Instant::now();
-            if self.rotate_bind_group.is_none() || need_init_rotate {
-                self.rotate_init(rotate_degree);
-            }
-
-            //rgba图像转换完成之后，直接使用rgba_texture_view再次处理旋转
-            let mut encoder = self
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-
-            {
-                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
-                cpass.set_pipeline(&self.rotate_compute_pipeline);
-                cpass.set_bind_group(0, self.rotate_bind_group.as_ref().unwrap(), &[]);
-                let workgroup_count_x = (self.texture_size.width + 16 - 1) / 16;
-                let workgroup_count_y = (self.texture_size.height + 16 - 1) / 16;
-                cpass.dispatch_workgroups(workgroup_count_x, workgroup_count_y, 1);
-            }
-
-            let rotate_output_size = self.rotate_output_size.clone().unwrap();
-
-            let padded_bytes_per_row = Self::padded_bytes_per_row(rotate_output_size.width);
-            let unpadded_bytes_per_row = rotate_output_size.width as usize * 4;
-
-            let output_buffer_size = padded_bytes_per_row as u64
-                * rotate_output_size.height as u64
-                * std::mem::size_of::<u8>() as u64;
-            let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                size: output_buffer_size,
-                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            });
-
-            encoder.copy_texture_to_buffer(
-                wgpu::ImageCopyTexture {
-                    aspect: wgpu::TextureAspect::All,
-                    texture: self.rotate_output_texture.as_ref().unwrap(),
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
-                },
-                wgpu::ImageCopyBuffer {
-                    buffer: &output_buffer,
-                    layout: wgpu::ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row: Some(padded_bytes_per_row as u32),
-                        rows_per_image: Some(rotate_output_size.height),
-                    },
-                },
-                rotate_output_size,
-            );
-
-            self.queue.submit(Some(encoder.finish()));
-
-            let buffer_slice = output_buffer.slice(..);
-            buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
-
-            self.device.poll(wgpu::Maintain::Wait);
-
-            let padded_data = buffer_slice.get_mapped_range();
-
-            for (padded, pixels) in padded_data
-                .chunks_exact(padded_bytes_per_row)
-                .zip(output.chunks_exact_mut(unpadded_bytes_per_row))
-            {
-                pixels.copy_from_slice(&padded[..unpadded_bytes_per_row]);
-            }
-            // info!("旋转完成 耗时:{}ms", t.elapsed().as_millis());
-        } else {
-            let output_buffer = rgba_output_buffer.as_ref().unwrap();
-            let buffer_slice = output_buffer.slice(..);
-            buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
-
-            self.device.poll(wgpu::Maintain::Wait);
-
-            let padded_data = buffer_slice.get_mapped_range();
-
-            for (padded, pixels) in padded_data
-                .chunks_exact(self.padded_bytes_per_row)
-                .zip(output.chunks_exact_mut(self.unpadded_bytes_per_row))
-            {
-                pixels.copy_from_slice(&padded[..self.unpadded_bytes_per_row]);
-            }
-        };
-
-        Ok(())
+        let res = AImage_getPlanePixelStride(image, index, &mut pixel_stride);
+        if res != media_status_t::AMEDIA_OK {
+            return Err(anyhow!("AImage_getPlanePixelStride({index}) error res={:?}.", res));
+        }
+        planes.push(Plane::new(
+            slice::from_raw_parts(data, len.max(0) as usize),
+            row_stride.max(0) as usize,
+            pixel_stride.max(1) as usize,
+        ));
     }
+    Ok(planes)
+}
 
-    fn rotate_init(&mut self, rotate_degree: i32) {
-        self.last_rotate_degree = rotate_degree;
-        //创建旋转缓冲区
-        let (rotate_output_width, rotate_output_height) = if (rotate_degree / 90) % 2 == 0 {
-            (self.texture_size.width, self.texture_size.height)
-        } else {
-            (self.texture_size.height, self.texture_size.width)
-        };
-        let rotate_output_size = wgpu::Extent3d {
-            width: rotate_output_width,
-            height: rotate_output_height,
-            depth_or_array_layers: 1,
-        };
-
-        // 输出图像
-        let rotate_output_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("output texture"),
-            size: rotate_output_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
-        });
-
-        // 旋转参数
-        let config_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            usage: wgpu::BufferUsages::STORAGE,
-            contents: bytemuck::cast_slice(&[rotate_degree]),
-        });
-
-        let rotate_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.rotate_compute_pipeline.get_bind_group_layout(0),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.rgba_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(
-                        &rotate_output_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: config_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("bind_group"),
-        });
-
-        self.rotate_bind_group = Some(rotate_bind_group);
-        self.rotate_output_texture = Some(rotate_output_texture);
-        self.rotate_output_size = Some(rotate_output_size);
+/// 从拍摄结果中读取曝光时间、感光度、焦距和光圈
+unsafe fn read_exposure(result: *const ACameraMetadata) -> Exposure {
+    let entry = |tag: acamera_metadata_tag| {
+        let mut entry: ACameraMetadata_const_entry = zeroed();
+        let camera_status = ACameraMetadata_getConstEntry(result, tag.0, &mut entry);
+        (camera_status == camera_status_t::ACAMERA_OK && entry.count > 0).then_some(entry)
+    };
+    Exposure {
+        exposure_time_ns: entry(acamera_metadata_tag::ACAMERA_SENSOR_EXPOSURE_TIME).map(|entry| *entry.data.i64_),
+        iso: entry(acamera_metadata_tag::ACAMERA_SENSOR_SENSITIVITY).map(|entry| *entry.data.i32_ as u32),
+        focal_length: entry(acamera_metadata_tag::ACAMERA_LENS_FOCAL_LENGTH).map(|entry| *entry.data.f),
+        aperture: entry(acamera_metadata_tag::ACAMERA_LENS_APERTURE).map(|entry| *entry.data.f),
     }
+}
 
-    /// Compute the next multiple of 256 for texture retrieval padding.
-    pub fn padded_bytes_per_row(width: u32) -> usize {
-        let bytes_per_row = width as usize * 4;
-        let padding = (256 - bytes_per_row % 256) % 256;
-        bytes_per_row + padding
+/// 系统属性，如 ro.product.model，不存在时为空字符串
+fn system_property(name: &str) -> String {
+    let Ok(name) = CString::new(name) else {
+        return String::new();
+    };
+    // PROP_VALUE_MAX = 92
+    let mut value = [0 as c_char; 92];
+    unsafe {
+        __system_property_get(name.as_ptr(), value.as_mut_ptr());
+        CStr::from_ptr(value.as_ptr()).to_string_lossy().into_owned()
     }
 }
 
+/// 设备厂商和型号，即 Build.MANUFACTURER、Build.MODEL
+pub fn device_make_model() -> (String, String) {
+    (system_property("ro.product.manufacturer"), system_property("ro.product.model"))
+}
+
 pub fn sdk_version(app: &slint::android::AndroidApp) -> Result<i32> {
     unsafe {
         let vm = JavaVM::from_raw(app.vm_as_ptr() as *mut *const JNIInvokeInterface_)?;
@@ -0,0 +1,340 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use anyhow::{anyhow, Result};
+use image::{
+    codecs::{gif::{GifEncoder, Repeat}, webp::WebPEncoder},
+    imageops, Delay, ImageEncoder, RgbaImage,
+};
+
+use super::{worker::{FrameHandler, FrameWorker}, Frame, FrameMailbox, PixelFormat};
+
+/// 动图的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipFormat{
+    /// 256 色调色板，兼容性最好
+    #[default]
+    Gif,
+    /// 无损的动画 WebP，文件较大，颜色不失真
+    WebP,
+}
+
+impl ClipFormat{
+    pub fn extension(&self) -> &'static str{
+        match self{
+            ClipFormat::Gif => "gif",
+            ClipFormat::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClipOptions{
+    /// 保留最近多长时间的帧
+    pub duration: Duration,
+    /// 缩小后的最长边
+    pub max_size: u32,
+    /// 最高帧率，间隔更短的帧丢弃
+    pub fps: u32,
+}
+
+impl Default for ClipOptions{
+    fn default() -> Self{
+        Self { duration: Duration::from_secs(3), max_size: 320, fps: 10 }
+    }
+}
+
+/// 缓存中缩小后的一帧
+#[derive(Debug, Clone)]
+pub struct ClipFrame{
+    pub image: RgbaImage,
+    pub timestamp_ns: i64,
+}
+
+/// 最近一段时间缩小后的帧，可以在任何线程导出
+#[derive(Clone)]
+pub struct ClipBuffer{
+    options: ClipOptions,
+    frames: Arc<Mutex<VecDeque<ClipFrame>>>,
+}
+
+impl ClipBuffer{
+    pub fn new(options: ClipOptions) -> Self{
+        Self { options, frames: Arc::new(Mutex::new(VecDeque::new())) }
+    }
+
+    /// 缩小后加入缓存，丢弃超出时长的旧帧，返回 false 表示帧率超出而丢弃
+    pub fn push(&self, frame: &Frame, timestamp_ns: i64) -> Result<bool>{
+        // 留 10% 余量，30fps 的相机可以得到 10fps
+        let min_interval_ns = 1_000_000_000 / self.options.fps.max(1) as i64 * 9 / 10;
+        let last = self.frames.lock().unwrap().back().map(|last| last.timestamp_ns);
+        // 时间戳倒退(换了相机)时重新开始
+        if last.is_some_and(|last| timestamp_ns >= last && timestamp_ns - last < min_interval_ns){
+            return Ok(false);
+        }
+        let rgba = frame.convert(PixelFormat::Rgba8)?;
+        let image = RgbaImage::from_raw(frame.width, frame.height, rgba).ok_or(anyhow!("invalid frame size"))?;
+        let scale = (self.options.max_size as f32 / frame.width.max(frame.height) as f32).min(1.);
+        let (width, height) = (((frame.width as f32 * scale) as u32).max(1), ((frame.height as f32 * scale) as u32).max(1));
+        let image = match scale < 1.{
+            true => imageops::thumbnail(&image, width, height),
+            false => image,
+        };
+
+        let mut frames = self.frames.lock().unwrap();
+        if frames.back().is_some_and(|last| last.image.dimensions() != image.dimensions() || timestamp_ns < last.timestamp_ns){
+            frames.clear();
+        }
+        frames.push_back(ClipFrame { image, timestamp_ns });
+        let keep_ns = self.options.duration.as_nanos() as i64;
+        while frames.front().is_some_and(|first| timestamp_ns - first.timestamp_ns > keep_ns){
+            frames.pop_front();
+        }
+        Ok(true)
+    }
+
+    /// 当前缓存中的帧
+    pub fn frames(&self) -> Vec<ClipFrame>{
+        self.frames.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self){
+        self.frames.lock().unwrap().clear();
+    }
+
+    /// 把当前缓存的帧编码为动图，按时间戳计算每一帧的显示时间，无限循环
+    pub fn export(&self, format: ClipFormat) -> Result<Vec<u8>>{
+        let frames = self.frames();
+        if frames.is_empty(){
+            return Err(anyhow!("no frame in clip buffer"));
+        }
+        match format{
+            ClipFormat::Gif => encode_gif(&frames),
+            ClipFormat::WebP => encode_webp(&frames),
+        }
+    }
+
+    /// 导出并写入文件
+    pub fn save(&self, format: ClipFormat, path: &Path) -> Result<usize>{
+        let data = self.export(format)?;
+        std::fs::write(path, &data).map_err(|err| anyhow!("write {path:?} failed: {err}"))?;
+        Ok(data.len())
+    }
+}
+
+/// 订阅 FrameMailbox，在单独的线程中把帧缩小后放入 ClipBuffer
+pub struct ClipRecorder{
+    buffer: ClipBuffer,
+    _worker: FrameWorker<()>,
+}
+
+impl ClipRecorder{
+    pub fn start(mailbox: &FrameMailbox, options: ClipOptions) -> Self{
+        let buffer = ClipBuffer::new(options);
+        let worker = FrameWorker::start(mailbox.subscribe(2), buffer.clone());
+        Self { buffer, _worker: worker }
+    }
+
+    pub fn buffer(&self) -> &ClipBuffer{
+        &self.buffer
+    }
+}
+
+impl FrameHandler for ClipBuffer{
+    type Output = ();
+
+    /// 出错的帧跳过，不结束线程
+    fn on_frame(&mut self, frame: Frame) -> Result<()>{
+        if let Err(err) = self.push(&frame, frame.timestamp_ns){
+            log::error!("clip buffer: {:?}", err);
+        }
+        Ok(())
+    }
+
+    fn on_stop(self) -> Result<()>{
+        Ok(())
+    }
+}
+
+/// 每一帧的显示时间(毫秒)，按时间戳累计后取整到 unit_ms，取整误差不会累积
+///
+/// 最后一帧使用平均帧间隔
+fn frame_delays(frames: &[ClipFrame], unit_ms: u32, min_ms: u32) -> Vec<u32>{
+    let first = frames.first().map(|frame| frame.timestamp_ns).unwrap_or_default();
+    let span_ns = frames.last().map(|frame| frame.timestamp_ns - first).unwrap_or_default();
+    let average_ns = match frames.len(){
+        0 | 1 => 100_000_000,
+        n => span_ns / (n as i64 - 1),
+    };
+    let mut shown_ms = 0;
+    (0..frames.len())
+        .map(|i| {
+            let end_ns = frames.get(i + 1).map(|next| next.timestamp_ns - first).unwrap_or(span_ns + average_ns);
+            let end_ms = ((end_ns / 1_000_000) as u32 + unit_ms / 2) / unit_ms * unit_ms;
+            let delay = end_ms.saturating_sub(shown_ms).max(min_ms);
+            shown_ms += delay;
+            delay
+        })
+        .collect()
+}
+
+fn encode_gif(frames: &[ClipFrame]) -> Result<Vec<u8>>{
+    let mut data = vec![];
+    {
+        // speed 10: NeuQuant 调色板质量和速度的折中
+        let mut encoder = GifEncoder::new_with_speed(&mut data, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        // GIF 的时间单位为 10ms，小于 20ms 的延迟很多浏览器按 100ms 显示
+        let delays = frame_delays(frames, 10, 20);
+        encoder.encode_frames(frames.iter().zip(delays).map(|(frame, delay)| {
+            image::Frame::from_parts(frame.image.clone(), 0, 0, Delay::from_numer_denom_ms(delay, 1))
+        }))?;
+    }
+    Ok(data)
+}
+
+/// 动画 WebP: VP8X + ANIM + 每帧一个 ANMF，帧数据是 image 无损编码的 VP8L
+fn encode_webp(frames: &[ClipFrame]) -> Result<Vec<u8>>{
+    let (width, height) = frames[0].image.dimensions();
+    let u24 = |value: u32| value.to_le_bytes()[..3].to_vec();
+    let mut body = b"WEBP".to_vec();
+    // 只有动画标志，帧不透明
+    write_chunk(&mut body, b"VP8X", &[vec![0x02, 0, 0, 0], u24(width - 1), u24(height - 1)].concat());
+    // 背景色 BGRA，循环次数 0 为无限
+    write_chunk(&mut body, b"ANIM", &[0, 0, 0, 0, 0, 0]);
+    for (frame, delay) in frames.iter().zip(frame_delays(frames, 1, 1)){
+        let mut still = vec![];
+        let rgb: Vec<u8> = frame.image.pixels().flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+        WebPEncoder::new_lossless(&mut still).write_image(&rgb, width, height, image::ColorType::Rgb8)?;
+        let vp8l = find_chunk(&still, b"VP8L").ok_or(anyhow!("no VP8L chunk in encoded WebP"))?;
+        // 帧的位置(x/2, y/2)、宽高减 1、显示时间，不混合、不清除
+        let mut anmf = [u24(0), u24(0), u24(width - 1), u24(height - 1), u24(delay.min(0xFFFFFF)), vec![0x02]].concat();
+        write_chunk(&mut anmf, b"VP8L", vp8l);
+        write_chunk(&mut body, b"ANMF", &anmf);
+    }
+    let mut data = b"RIFF".to_vec();
+    data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    data.extend(body);
+    Ok(data)
+}
+
+/// RIFF 块，数据按偶数长度补齐
+fn write_chunk(output: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]){
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1{
+        output.push(0);
+    }
+}
+
+/// 在 WebP 文件中查找块的数据
+fn find_chunk<'a>(webp: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]>{
+    let mut pos = 12;
+    while pos + 8 <= webp.len(){
+        let len = u32::from_le_bytes(webp[pos + 4..pos + 8].try_into().ok()?) as usize;
+        let data = webp.get(pos + 8..pos + 8 + len)?;
+        if &webp[pos..pos + 4] == fourcc{
+            return Some(data);
+        }
+        pos += 8 + len + len % 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests{
+    use image::{codecs::{gif::GifDecoder, webp::WebPDecoder}, AnimationDecoder, Rgba};
+    use super::*;
+
+    const MS: i64 = 1_000_000;
+
+    fn clip_frames(width: u32, height: u32, timestamps_ms: &[i64]) -> Vec<ClipFrame>{
+        timestamps_ms.iter().enumerate().map(|(i, ms)| {
+            let image = RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 40) as u8, (y * 60) as u8, (i * 80) as u8, 255]));
+            ClipFrame { image, timestamp_ns: ms * MS }
+        }).collect()
+    }
+
+    fn u24(data: &[u8]) -> u32{
+        u32::from_le_bytes([data[0], data[1], data[2], 0])
+    }
+
+    /// RIFF 中的块: (fourcc, 数据)
+    fn read_chunks(data: &[u8]) -> Vec<(&[u8], &[u8])>{
+        let mut chunks = vec![];
+        let mut pos = 0;
+        while pos < data.len(){
+            let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            chunks.push((&data[pos..pos + 4], &data[pos + 8..pos + 8 + len]));
+            pos += 8 + len + len % 2;
+        }
+        assert_eq!(pos, data.len());
+        chunks
+    }
+
+    #[test]
+    fn delays_follow_timestamps(){
+        let frames = clip_frames(2, 2, &[0, 33, 70, 140]);
+        let delays = frame_delays(&frames, 10, 20);
+        // 取整到 10ms，误差不累积，最后一帧使用平均间隔
+        assert_eq!(delays, [30, 40, 70, 50]);
+        assert_eq!(delays[..3].iter().sum::<u32>(), 140);
+        assert_eq!(frame_delays(&frames, 1, 1)[..3].iter().sum::<u32>(), 140);
+
+        // 太短的间隔延长到 min_ms，后面的帧补回来
+        let delays = frame_delays(&clip_frames(2, 2, &[0, 5, 10, 100]), 10, 20);
+        assert!(delays.iter().all(|delay| *delay >= 20), "{delays:?}");
+        assert_eq!(delays[..3].iter().sum::<u32>(), 100);
+
+        assert_eq!(frame_delays(&clip_frames(2, 2, &[500]), 10, 20), [100]);
+    }
+
+    #[test]
+    fn webp_parses_back(){
+        for (width, height) in [(6, 4), (5, 3)]{
+            let frames = clip_frames(width, height, &[0, 100, 250]);
+            let data = encode_webp(&frames).unwrap();
+            assert_eq!(&data[..4], b"RIFF");
+            assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize, data.len() - 8);
+            assert_eq!(&data[8..12], b"WEBP");
+
+            let chunks = read_chunks(&data[12..]);
+            assert_eq!(chunks[0].0, b"VP8X");
+            let vp8x = chunks[0].1;
+            assert_eq!((u24(&vp8x[4..]) + 1, u24(&vp8x[7..]) + 1), (width, height));
+            assert_eq!(chunks[1].0, b"ANIM");
+            let anmf: Vec<&[u8]> = chunks[2..].iter().map(|(fourcc, data)| {
+                assert_eq!(*fourcc, b"ANMF");
+                *data
+            }).collect();
+            assert_eq!(anmf.len(), frames.len());
+            let delays: Vec<u32> = anmf.iter().map(|anmf| {
+                assert_eq!((u24(&anmf[6..]) + 1, u24(&anmf[9..]) + 1), (width, height));
+                assert_eq!(read_chunks(&anmf[16..])[0].0, b"VP8L");
+                u24(&anmf[12..])
+            }).collect();
+            assert_eq!(delays, [100, 150, 125]);
+            // find_chunk 跳过前面的块
+            assert_eq!(find_chunk(&data, b"ANIM"), Some(chunks[1].1));
+            assert_eq!(find_chunk(&data, b"VP8 "), None);
+
+            let decoded = WebPDecoder::new(std::io::Cursor::new(&data)).unwrap().into_frames().collect_frames().unwrap();
+            assert_eq!(decoded.len(), frames.len());
+            assert_eq!(decoded[2].buffer().get_pixel(1, 1), frames[2].image.get_pixel(1, 1));
+        }
+    }
+
+    #[test]
+    fn gif_frame_count(){
+        let frames = clip_frames(8, 6, &[0, 100, 200, 300, 400]);
+        let data = encode_gif(&frames).unwrap();
+        let decoded = GifDecoder::new(std::io::Cursor::new(&data)).unwrap().into_frames().collect_frames().unwrap();
+        assert_eq!(decoded.len(), frames.len());
+        assert!(decoded.iter().all(|frame| frame.buffer().dimensions() == (8, 6)));
+        assert_eq!(decoded[0].delay().numer_denom_ms(), (100, 1));
+    }
+}
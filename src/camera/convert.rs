@@ -0,0 +1,968 @@
+//! 常见相机像素格式之间的转换，输出 RGBA/RGB/Gray
+
+use anyhow::{anyhow, Result};
+
+/// 像素格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat{
+    /// R,G,B,A 每像素4字节
+    Rgba8,
+    /// R,G,B 每像素3字节
+    Rgb8,
+    /// 灰度 每像素1字节
+    Gray8,
+    /// B,G,R,A 每像素4字节，Windows 相机常用
+    Bgra8,
+    /// 小端 16位 R5G6B5
+    Rgb565,
+    /// YUV422 打包格式 Y0,U,Y1,V
+    Yuyv,
+    /// YUV422 打包格式 U,Y0,V,Y1
+    Uyvy,
+    /// YUV420SP Y平面 + UV交错平面
+    Nv12,
+    /// YUV420SP Y平面 + VU交错平面，android 相机常用
+    Nv21,
+    /// YUV420P Y平面 + U平面 + V平面
+    I420,
+}
+
+impl PixelFormat{
+    /// 打包格式每个像素的字节数，YUV420 格式为 Y 平面每个像素的字节数
+    pub fn bytes_per_pixel(&self) -> usize{
+        match self{
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 => 4,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgb565 | PixelFormat::Yuyv | PixelFormat::Uyvy => 2,
+            PixelFormat::Gray8 | PixelFormat::Nv12 | PixelFormat::Nv21 | PixelFormat::I420 => 1,
+        }
+    }
+
+    pub fn is_yuv420(&self) -> bool{
+        matches!(self, PixelFormat::Nv12 | PixelFormat::Nv21 | PixelFormat::I420)
+    }
+
+    /// 紧密排列时每个平面一行的字节数
+    pub fn default_strides(&self, width: u32) -> Vec<usize>{
+        let width = width as usize;
+        let chroma_width = width.div_ceil(2);
+        match self{
+            PixelFormat::Nv12 | PixelFormat::Nv21 => vec![width, chroma_width * 2],
+            PixelFormat::I420 => vec![width, chroma_width, chroma_width],
+            _ => vec![width * self.bytes_per_pixel()],
+        }
+    }
+
+    /// 紧密排列时一帧的字节数
+    pub fn frame_size(&self, width: u32, height: u32) -> usize{
+        let height = height as usize;
+        let chroma_height = height.div_ceil(2);
+        let strides = self.default_strides(width);
+        match self{
+            PixelFormat::Nv12 | PixelFormat::Nv21 => strides[0] * height + strides[1] * chroma_height,
+            PixelFormat::I420 => strides[0] * height + (strides[1] + strides[2]) * chroma_height,
+            _ => strides[0] * height,
+        }
+    }
+}
+
+/// 一个图像平面，第 y 行第 x 个像素位于 data[y * row_stride + x * pixel_stride]
+#[derive(Debug, Clone, Copy)]
+pub struct Plane<'a>{
+    pub data: &'a [u8],
+    pub row_stride: usize,
+    pub pixel_stride: usize,
+}
+
+impl<'a> Plane<'a>{
+    pub fn new(data: &'a [u8], row_stride: usize, pixel_stride: usize) -> Self{
+        Self { data, row_stride, pixel_stride }
+    }
+
+    /// 从第 y 行第 x 个像素开始的平面
+    pub fn offset(&self, x: usize, y: usize) -> Result<Plane<'a>>{
+        let offset = y * self.row_stride + x * self.pixel_stride;
+        let data = self.data.get(offset..).ok_or(anyhow!("plane offset out of range: {offset} > {}", self.data.len()))?;
+        Ok(Plane { data, ..*self })
+    }
+
+    /// 检查平面数据能否容纳 cols x rows 个像素(每个像素 bytes 字节)
+    pub fn check(&self, cols: usize, rows: usize, bytes: usize, name: &str) -> Result<()>{
+        if cols == 0 || rows == 0{
+            return Ok(());
+        }
+        let needed = (rows - 1) * self.row_stride + (cols - 1) * self.pixel_stride + bytes;
+        if self.data.len() < needed{
+            return Err(anyhow!("{name} plane too small: {} < {needed}", self.data.len()));
+        }
+        Ok(())
+    }
+}
+
+/// 把连续存放的一帧数据拆分为平面，YUV420 格式统一拆成 Y、U、V 三个平面
+///
+/// strides 为空时按紧密排列处理
+pub fn planes<'a>(data: &'a [u8], format: PixelFormat, width: u32, height: u32, strides: &[usize]) -> Result<Vec<Plane<'a>>>{
+    let default_strides = format.default_strides(width);
+    let stride = |i: usize| match strides.get(i){
+        Some(stride) if *stride > 0 => *stride,
+        _ => default_strides[i],
+    };
+    let split = |offset: usize| data.get(offset..).ok_or(anyhow!("frame data too small: {} < {offset}", data.len()));
+    let y_size = stride(0) * height as usize;
+    let chroma_height = (height as usize).div_ceil(2);
+    Ok(match format{
+        PixelFormat::Nv12 | PixelFormat::Nv21 => {
+            let uv = split(y_size)?;
+            let (u, v) = if format == PixelFormat::Nv12 { (uv, uv.get(1..).unwrap_or(&[])) } else { (uv.get(1..).unwrap_or(&[]), uv) };
+            vec![
+                Plane::new(data, stride(0), 1),
+                Plane::new(u, stride(1), 2),
+                Plane::new(v, stride(1), 2),
+            ]
+        }
+        PixelFormat::I420 => {
+            let u_offset = y_size;
+            let v_offset = u_offset + stride(1) * chroma_height;
+            vec![
+                Plane::new(data, stride(0), 1),
+                Plane::new(split(u_offset)?, stride(1), 1),
+                Plane::new(split(v_offset)?, stride(2), 1),
+            ]
+        }
+        _ => vec![Plane::new(data, stride(0), format.bytes_per_pixel())],
+    })
+}
+
+/// 裁剪掉左边 left 列、上边 top 行，YUV420 的色度平面按一半裁剪，left 和 top 应为偶数
+///
+/// 只移动平面的起点，裁剪后的宽高由调用者传给 convert
+pub fn crop_planes<'a>(planes: &[Plane<'a>], format: PixelFormat, left: u32, top: u32) -> Result<Vec<Plane<'a>>>{
+    let (left, top) = (left as usize, top as usize);
+    planes.iter().enumerate().map(|(i, plane)| {
+        match format{
+            _ if format.is_yuv420() && i > 0 => plane.offset(left / 2, top / 2),
+            // 打包的 YUV422 两个像素共用一组 UV，只能按偶数列裁剪
+            PixelFormat::Yuyv | PixelFormat::Uyvy => plane.offset(left & !1, top),
+            _ => plane.offset(left, top),
+        }
+    }).collect()
+}
+
+/// YUV 转 RGB 使用的矩阵
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMatrix{
+    /// 标清，大部分 USB 摄像头和 JPEG
+    #[default]
+    Bt601,
+    /// 高清
+    Bt709,
+    /// 超高清
+    Bt2020,
+}
+
+/// YUV 的取值范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorRange{
+    /// Y: 16~235, UV: 16~240
+    #[default]
+    Limited,
+    /// 0~255
+    Full,
+}
+
+/// YUV 数据的色彩空间，默认为 BT.601 limited range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ColorSpace{
+    pub matrix: ColorMatrix,
+    pub range: ColorRange,
+}
+
+impl ColorSpace{
+    pub fn new(matrix: ColorMatrix, range: ColorRange) -> Self{
+        Self { matrix, range }
+    }
+
+    /// YUV 取值为 0~1 时的转换系数: [y_offset, y_gain, r_v, g_u, g_v, b_u]
+    ///
+    /// y' = max(y - y_offset, 0) * y_gain, u' = u - 128/255, v' = v - 128/255
+    /// r = y' + r_v * v', g = y' - g_u * u' - g_v * v', b = y' + b_u * u'
+    pub fn coefficients(&self) -> [f32; 6]{
+        let (kr, kb) = match self.matrix{
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+            ColorMatrix::Bt2020 => (0.2627, 0.0593),
+        };
+        let kg = 1. - kr - kb;
+        let (y_offset, y_gain, c_gain) = match self.range{
+            ColorRange::Limited => (16. / 255., 255. / 219., 255. / 224.),
+            ColorRange::Full => (0., 1., 1.),
+        };
+        [
+            y_offset,
+            y_gain,
+            2. * (1. - kr) * c_gain,
+            2. * (1. - kb) * kb / kg * c_gain,
+            2. * (1. - kr) * kr / kg * c_gain,
+            2. * (1. - kb) * c_gain,
+        ]
+    }
+}
+
+/// CPU 解码用的查找表，表中的值放大了 1024 倍，亮度表包含了取整用的 0.5
+pub struct YuvTable{
+    y: [i32; 256],
+    r_v: [i32; 256],
+    g_u: [i32; 256],
+    g_v: [i32; 256],
+    b_u: [i32; 256],
+}
+
+impl YuvTable{
+    pub fn new(color_space: ColorSpace) -> Self{
+        let [y_offset, y_gain, r_v, g_u, g_v, b_u] = color_space.coefficients();
+        let fixed = |v: f32| (v * 1024.).round() as i32;
+        let y_offset = (y_offset * 255.).round() as i32;
+        let chroma = |gain: f32| std::array::from_fn(|i| fixed(gain) * (i as i32 - 128));
+        Self {
+            y: std::array::from_fn(|i| fixed(y_gain) * (i as i32 - y_offset).max(0) + 512),
+            r_v: chroma(r_v),
+            g_u: chroma(g_u),
+            g_v: chroma(g_v),
+            b_u: chroma(b_u),
+        }
+    }
+
+    #[inline(always)]
+    pub fn rgb(&self, y: u8, u: u8, v: u8) -> [u8; 3]{
+        self.rgb_with_chroma(y, self.chroma(u, v))
+    }
+
+    /// UV 对 r、g、b 的贡献，多个像素共用一组 UV 时只需要计算一次
+    #[inline(always)]
+    pub fn chroma(&self, u: u8, v: u8) -> [i32; 3]{
+        let (u, v) = (u as usize, v as usize);
+        [self.r_v[v], -self.g_u[u] - self.g_v[v], self.b_u[u]]
+    }
+
+    #[inline(always)]
+    pub fn rgb_with_chroma(&self, y: u8, chroma: [i32; 3]) -> [u8; 3]{
+        let y = self.y[y as usize];
+        let [r, g, b] = chroma.map(|c| ((y + c).clamp(0, 262143) >> 10) as u8);
+        [r, g, b]
+    }
+
+    /// Y 对应的灰度
+    #[inline(always)]
+    pub fn gray(&self, y: u8) -> u8{
+        (self.y[y as usize] >> 10).min(255) as u8
+    }
+}
+
+/// BT.601 亮度
+#[inline(always)]
+fn luma(r: u8, g: u8, b: u8) -> u8{
+    ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8) as u8
+}
+
+/// 写入输出的第 x 个像素，gray 为 YUV 数据中 Y 对应的灰度
+#[inline(always)]
+fn store(out: &mut [u8], x: usize, format: PixelFormat, rgba: [u8; 4], gray: Option<u8>){
+    match format{
+        PixelFormat::Rgba8 => out[x * 4..x * 4 + 4].copy_from_slice(&rgba),
+        PixelFormat::Rgb8 => out[x * 3..x * 3 + 3].copy_from_slice(&rgba[..3]),
+        _ => out[x] = gray.unwrap_or_else(|| luma(rgba[0], rgba[1], rgba[2])),
+    }
+}
+
+/// 把 planes 描述的图像转换为 dst_format，dst_format 只能是 Rgba8、Rgb8 或 Gray8
+///
+/// color_space 只对 YUV 格式有效
+pub fn convert(planes: &[Plane], format: PixelFormat, color_space: ColorSpace, width: u32, height: u32, dst: &mut [u8], dst_format: PixelFormat) -> Result<()>{
+    if !matches!(dst_format, PixelFormat::Rgba8 | PixelFormat::Rgb8 | PixelFormat::Gray8){
+        return Err(anyhow!("unsupported output format: {:?}", dst_format));
+    }
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0{
+        return Ok(());
+    }
+    let dst_row = width * dst_format.bytes_per_pixel();
+    if dst.len() < dst_row * height{
+        return Err(anyhow!("output buffer too small: {} < {}", dst.len(), dst_row * height));
+    }
+    let plane_count = if format.is_yuv420() { 3 } else { 1 };
+    if planes.len() < plane_count{
+        return Err(anyhow!("{:?} needs {plane_count} planes, got {}", format, planes.len()));
+    }
+    if format.is_yuv420(){
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        planes[0].check(width, height, 1, "Y")?;
+        planes[1].check(chroma_width, chroma_height, 1, "U")?;
+        planes[2].check(chroma_width, chroma_height, 1, "V")?;
+    }else{
+        match format{
+            // 打包的 YUV422 每4字节两个像素
+            PixelFormat::Yuyv | PixelFormat::Uyvy => Plane{ pixel_stride: 4, ..planes[0] }.check(width.div_ceil(2), height, 4, "packed")?,
+            _ => planes[0].check(width, height, format.bytes_per_pixel(), "packed")?,
+        }
+    }
+
+    let table = YuvTable::new(color_space);
+    for (y, out) in dst.chunks_exact_mut(dst_row).take(height).enumerate(){
+        let row = &planes[0].data[y * planes[0].row_stride..];
+        let step = planes[0].pixel_stride;
+        match format{
+            PixelFormat::Nv12 | PixelFormat::Nv21 | PixelFormat::I420 => {
+                let (u_plane, v_plane) = (&planes[1], &planes[2]);
+                let u_row = &u_plane.data[y / 2 * u_plane.row_stride..];
+                let v_row = &v_plane.data[y / 2 * v_plane.row_stride..];
+                if dst_format == PixelFormat::Rgba8{
+                    // 常用的 rgba 输出，每次处理共用一组 UV 的两个像素
+                    let (us, vs) = (u_plane.pixel_stride, v_plane.pixel_stride);
+                    for (i, (pair, luma)) in out.chunks_mut(8).zip(row.chunks(step * 2)).enumerate(){
+                        let chroma = table.chroma(u_row[i * us], v_row[i * vs]);
+                        for (pixel, luma) in pair.chunks_exact_mut(4).zip(luma.iter().step_by(step)){
+                            let [r, g, b] = table.rgb_with_chroma(*luma, chroma);
+                            pixel.copy_from_slice(&[r, g, b, 255]);
+                        }
+                    }
+                    continue;
+                }
+                // 相邻两个像素共用一组 UV
+                for x0 in (0..width).step_by(2){
+                    let chroma = table.chroma(u_row[x0 / 2 * u_plane.pixel_stride], v_row[x0 / 2 * v_plane.pixel_stride]);
+                    for x in x0..(x0 + 2).min(width){
+                        let luma = row[x * step];
+                        let [r, g, b] = table.rgb_with_chroma(luma, chroma);
+                        store(out, x, dst_format, [r, g, b, 255], Some(table.gray(luma)));
+                    }
+                }
+            }
+            PixelFormat::Yuyv | PixelFormat::Uyvy => {
+                // 每4字节两个像素，Y 和 UV 的位置
+                let (y0, u, y1, v) = if format == PixelFormat::Yuyv { (0, 1, 2, 3) } else { (1, 0, 3, 2) };
+                for x in 0..width{
+                    let pair = &row[x / 2 * 4..x / 2 * 4 + 4];
+                    let luma = pair[if x % 2 == 0 { y0 } else { y1 }];
+                    let [r, g, b] = table.rgb(luma, pair[u], pair[v]);
+                    store(out, x, dst_format, [r, g, b, 255], Some(table.gray(luma)));
+                }
+            }
+            PixelFormat::Rgba8 => {
+                for x in 0..width{
+                    let p = &row[x * step..x * step + 4];
+                    store(out, x, dst_format, [p[0], p[1], p[2], p[3]], None);
+                }
+            }
+            PixelFormat::Bgra8 => {
+                for x in 0..width{
+                    let p = &row[x * step..x * step + 4];
+                    store(out, x, dst_format, [p[2], p[1], p[0], p[3]], None);
+                }
+            }
+            PixelFormat::Rgb8 => {
+                for x in 0..width{
+                    let p = &row[x * step..x * step + 3];
+                    store(out, x, dst_format, [p[0], p[1], p[2], 255], None);
+                }
+            }
+            PixelFormat::Gray8 => {
+                for x in 0..width{
+                    let l = row[x * step];
+                    store(out, x, dst_format, [l, l, l, 255], Some(l));
+                }
+            }
+            PixelFormat::Rgb565 => {
+                for x in 0..width{
+                    let p = u16::from_le_bytes([row[x * step], row[x * step + 1]]);
+                    let (r, g, b) = ((p >> 11) as u8 & 0x1f, (p >> 5) as u8 & 0x3f, p as u8 & 0x1f);
+                    // 低位补高位，保证 0x1f 对应 255
+                    let rgba = [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255];
+                    store(out, x, dst_format, rgba, None);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 多线程版本的 convert，按行分块后每个 CPU 核心处理一块
+pub fn convert_parallel(planes: &[Plane], format: PixelFormat, color_space: ColorSpace, width: u32, height: u32, dst: &mut [u8], dst_format: PixelFormat) -> Result<()>{
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    convert_threads(planes, format, color_space, width, height, dst, dst_format, threads)
+}
+
+/// 分成 threads 块转换
+#[allow(clippy::too_many_arguments)]
+fn convert_threads(planes: &[Plane], format: PixelFormat, color_space: ColorSpace, width: u32, height: u32, dst: &mut [u8], dst_format: PixelFormat, threads: usize) -> Result<()>{
+    // YUV420 两行共用一行 UV，分块的行数需要是偶数
+    let rows = (height as usize).div_ceil(threads).next_multiple_of(2).max(2);
+    if threads <= 1 || rows >= height as usize{
+        return convert(planes, format, color_space, width, height, dst, dst_format);
+    }
+    let dst_row = width as usize * dst_format.bytes_per_pixel();
+    let dst_len = (dst_row * height as usize).min(dst.len());
+    std::thread::scope(|scope| {
+        let tasks: Vec<_> = dst[..dst_len].chunks_mut(dst_row * rows).enumerate().map(|(i, dst)| {
+            scope.spawn(move || {
+                let top = (i * rows) as u32;
+                let planes = crop_planes(planes, format, 0, top)?;
+                convert(&planes, format, color_space, width, rows.min(height as usize - top as usize) as u32, dst, dst_format)
+            })
+        }).collect();
+        tasks.into_iter().try_for_each(|task| task.join().map_err(|err| anyhow!("{:?}", err))?)
+    })
+}
+
+/// 图像方向变换: 先顺时针旋转，再水平镜像，8 种组合覆盖所有 90° 旋转和翻转
+///
+/// 只能通过 new() 等构造函数创建，rotation 总是 0、90、180、270 之一
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Orientation{
+    /// 顺时针旋转角度: 0、90、180、270
+    rotation: i32,
+    /// 旋转之后水平镜像，前置摄像头预览使用
+    mirror: bool,
+}
+
+impl Orientation{
+    /// rotation 取整到 90 的倍数
+    pub fn new(rotation: i32, mirror: bool) -> Self{
+        Self { rotation: rotation.rem_euclid(360) / 90 * 90, mirror }
+    }
+
+    pub fn rotate(rotation: i32) -> Self{
+        Self::new(rotation, false)
+    }
+
+    pub fn flip_horizontal() -> Self{
+        Self::new(0, true)
+    }
+
+    pub fn flip_vertical() -> Self{
+        Self::new(180, true)
+    }
+
+    /// 顺时针旋转角度: 0、90、180、270
+    pub fn rotation(&self) -> i32{
+        self.rotation
+    }
+
+    /// 旋转之后是否水平镜像
+    pub fn mirror(&self) -> bool{
+        self.mirror
+    }
+
+    pub fn is_identity(&self) -> bool{
+        *self == Self::default()
+    }
+
+    /// 变换后的宽高
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32){
+        if self.rotation % 180 == 0 { (width, height) } else { (height, width) }
+    }
+
+    /// 源图像坐标到输出坐标的整数仿射变换 [a, b, c, d, tx, ty]:
+    /// out_x = a*x + b*y + tx, out_y = c*x + d*y + ty
+    ///
+    /// GPU 的 rotate.wgsl 使用同一个矩阵
+    pub fn matrix(&self, width: u32, height: u32) -> [i32; 6]{
+        let (w, h) = (width as i32, height as i32);
+        let [a, b, c, d, tx, ty] = match self.rotation{
+            90 => [0, -1, 1, 0, h - 1, 0],
+            180 => [-1, 0, 0, -1, w - 1, h - 1],
+            270 => [0, 1, -1, 0, 0, w - 1],
+            _ => [1, 0, 0, 1, 0, 0],
+        };
+        if self.mirror{
+            // out_x => output_width - 1 - out_x
+            let (out_w, _) = self.output_size(width, height);
+            [-a, -b, c, d, out_w as i32 - 1 - tx, ty]
+        }else{
+            [a, b, c, d, tx, ty]
+        }
+    }
+
+    /// 源图像 (x, y) 在输出图像中的坐标
+    pub fn map(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32){
+        let [a, b, c, d, tx, ty] = self.matrix(width, height);
+        let (x, y) = (x as i32, y as i32);
+        ((a * x + b * y + tx) as u32, (c * x + d * y + ty) as u32)
+    }
+}
+
+/// 对 rgba 图像应用方向变换，返回变换后的宽高
+pub fn transform_rgba(src: &[u8], width: u32, height: u32, orientation: Orientation, dst: &mut Vec<u8>) -> (u32, u32){
+    let (w, h) = (width as usize, height as usize);
+    dst.resize(w * h * 4, 0);
+    let (out_w, out_h) = orientation.output_size(width, height);
+    let [a, b, c, d, tx, ty] = orientation.matrix(width, height);
+    for (y, row) in src.chunks_exact(w * 4).take(h).enumerate(){
+        for (x, pixel) in row.chunks_exact(4).enumerate(){
+            let (x, y) = (x as i32, y as i32);
+            let (ox, oy) = ((a * x + b * y + tx) as usize, (c * x + d * y + ty) as usize);
+            let i = (oy * out_w as usize + ox) * 4;
+            dst[i..i + 4].copy_from_slice(pixel);
+        }
+    }
+    (out_w, out_h)
+}
+
+/// 转换连续存放的一帧数据，strides 为空时按紧密排列处理
+pub fn convert_buffer(data: &[u8], format: PixelFormat, color_space: ColorSpace, width: u32, height: u32, strides: &[usize], dst_format: PixelFormat) -> Result<Vec<u8>>{
+    let mut dst = vec![0; width as usize * height as usize * dst_format.bytes_per_pixel()];
+    convert(&planes(data, format, width, height, strides)?, format, color_space, width, height, &mut dst, dst_format)?;
+    Ok(dst)
+}
+
+/// Rgb8 转换为 I420(BT.601 limited range)，奇数的宽高会去掉最后一列、一行，返回输出的宽高
+pub fn rgb_to_i420(rgb: &[u8], width: u32, height: u32, dst: &mut Vec<u8>) -> (u32, u32){
+    let (src_w, w, h) = (width as usize, width as usize & !1, height as usize & !1);
+    dst.resize(w * h * 3 / 2, 0);
+    let (y_plane, uv) = dst.split_at_mut(w * h);
+    let (u_plane, v_plane) = uv.split_at_mut(w * h / 4);
+    let luma = |p: &[u8]| (16 + ((66 * p[0] as i32 + 129 * p[1] as i32 + 25 * p[2] as i32 + 128) >> 8)) as u8;
+    for y in 0..h{
+        for x in 0..w{
+            y_plane[y * w + x] = luma(&rgb[(y * src_w + x) * 3..]);
+        }
+    }
+    // 2x2 像素的平均值计算色度
+    for y in 0..h / 2{
+        for x in 0..w / 2{
+            let mut sum = [0i32; 3];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)]{
+                let i = ((y * 2 + dy) * src_w + x * 2 + dx) * 3;
+                for (sum, value) in sum.iter_mut().zip(&rgb[i..i + 3]){
+                    *sum += *value as i32;
+                }
+            }
+            let [r, g, b] = sum.map(|v| (v + 2) / 4);
+            u_plane[y * w / 2 + x] = (128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8)) as u8;
+            v_plane[y * w / 2 + x] = (128 + ((112 * r - 94 * g - 18 * b + 128) >> 8)) as u8;
+        }
+    }
+    (w as u32, h as u32)
+}
+
+/// 把任意布局的 YUV420 平面(Y、U、V)复制为紧密排列的 I420 或 NV12，视频编码器的输入使用
+pub fn pack_yuv420(planes: &[Plane], width: u32, height: u32, dst_format: PixelFormat, dst: &mut Vec<u8>) -> Result<()>{
+    pack_yuv420_padded(planes, width, height, dst_format, width as usize, height as usize, dst)
+}
+
+/// 同 pack_yuv420，按 MediaCodec 输入格式的 stride、slice-height 排列，填充的字节为 0
+///
+/// Y 每行 stride 字节共 slice_height 行，I420 的 U、V 每行 stride/2 字节共 slice_height/2 行，NV12 的 UV 每行 stride 字节
+pub fn pack_yuv420_padded(planes: &[Plane], width: u32, height: u32, dst_format: PixelFormat, stride: usize, slice_height: usize, dst: &mut Vec<u8>) -> Result<()>{
+    let [y_plane, u_plane, v_plane] = planes else {
+        return Err(anyhow!("expected 3 YUV420 planes, got {}", planes.len()));
+    };
+    let (w, h) = (width as usize, height as usize);
+    let (chroma_w, chroma_h) = (w.div_ceil(2), h.div_ceil(2));
+    if stride < w || slice_height < h{
+        return Err(anyhow!("stride {stride} / slice height {slice_height} is smaller than {w}x{h}"));
+    }
+    y_plane.check(w, h, 1, "Y")?;
+    u_plane.check(chroma_w, chroma_h, 1, "U")?;
+    v_plane.check(chroma_w, chroma_h, 1, "V")?;
+    // 色度平面每行的字节数，NV12 宽度为奇数时 UV 比 Y 多一个字节
+    let (chroma_stride, chroma_planes) = match dst_format{
+        PixelFormat::I420 => (stride.div_ceil(2), 2),
+        PixelFormat::Nv12 => (stride.div_ceil(2) * 2, 1),
+        _ => return Err(anyhow!("{dst_format:?} is not I420 or NV12")),
+    };
+    let chroma_size = chroma_stride * slice_height.div_ceil(2);
+    let at = |plane: &Plane, x: usize, y: usize| plane.data[y * plane.row_stride + x * plane.pixel_stride];
+    dst.clear();
+    dst.resize(stride * slice_height + chroma_size * chroma_planes, 0);
+    let (luma, chroma) = dst.split_at_mut(stride * slice_height);
+    for (y, row) in luma.chunks_exact_mut(stride).take(h).enumerate(){
+        match y_plane.pixel_stride{
+            1 => row[..w].copy_from_slice(&y_plane.data[y * y_plane.row_stride..][..w]),
+            _ => row[..w].iter_mut().enumerate().for_each(|(x, out)| *out = at(y_plane, x, y)),
+        }
+    }
+    match dst_format{
+        PixelFormat::I420 => {
+            for (plane, output) in [u_plane, v_plane].into_iter().zip(chroma.chunks_exact_mut(chroma_size)){
+                for (y, row) in output.chunks_exact_mut(chroma_stride).take(chroma_h).enumerate(){
+                    row[..chroma_w].iter_mut().enumerate().for_each(|(x, out)| *out = at(plane, x, y));
+                }
+            }
+        }
+        _ => {
+            for (y, row) in chroma.chunks_exact_mut(chroma_stride).take(chroma_h).enumerate(){
+                for (x, uv) in row[..chroma_w * 2].chunks_exact_mut(2).enumerate(){
+                    uv.copy_from_slice(&[at(u_plane, x, y), at(v_plane, x, y)]);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn y_at(x: usize, y: usize) -> u8{ (16 + (x * 37 + y * 11) % 200) as u8 }
+    fn u_at(x: usize, y: usize) -> u8{ (30 + (x * 13 + y * 29) % 190) as u8 }
+    fn v_at(x: usize, y: usize) -> u8{ (60 + (x * 23 + y * 5) % 170) as u8 }
+
+    /// 按指定布局生成 YUV420 数据: Y 每行 y_stride 字节，色度每行 c_stride 字节，填充字节为 0xEE
+    ///
+    /// format 为 I420 时 U、V 是独立的平面，Nv12/Nv21 时交错存放
+    fn yuv420(format: PixelFormat, width: usize, height: usize, y_stride: usize, c_stride: usize) -> (Vec<u8>, Vec<usize>){
+        let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+        let mut data = vec![0xEE; y_stride * height];
+        for y in 0..height{
+            for x in 0..width{
+                data[y * y_stride + x] = y_at(x, y);
+            }
+        }
+        match format{
+            PixelFormat::I420 => {
+                for sample in [u_at, v_at]{
+                    let mut plane = vec![0xEE; c_stride * ch];
+                    for y in 0..ch{
+                        for x in 0..cw{
+                            plane[y * c_stride + x] = sample(x, y);
+                        }
+                    }
+                    data.extend(plane);
+                }
+                (data, vec![y_stride, c_stride, c_stride])
+            }
+            _ => {
+                let mut plane = vec![0xEE; c_stride * ch];
+                for y in 0..ch{
+                    for x in 0..cw{
+                        let (u, v) = (u_at(x, y), v_at(x, y));
+                        let pair = if format == PixelFormat::Nv12 { [u, v] } else { [v, u] };
+                        plane[y * c_stride + x * 2..][..2].copy_from_slice(&pair);
+                    }
+                }
+                data.extend(plane);
+                (data, vec![y_stride, c_stride])
+            }
+        }
+    }
+
+    /// 逐像素按定义计算的期望输出
+    fn expected_rgba(width: usize, height: usize, left: usize, top: usize) -> Vec<u8>{
+        let table = YuvTable::new(ColorSpace::default());
+        let mut rgba = vec![];
+        for y in top..top + height{
+            for x in left..left + width{
+                let [r, g, b] = table.rgb(y_at(x, y), u_at(x / 2, y / 2), v_at(x / 2, y / 2));
+                rgba.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        rgba
+    }
+
+    fn convert_rgba(planes: &[Plane], format: PixelFormat, width: usize, height: usize) -> Vec<u8>{
+        let mut rgba = vec![0; width * height * 4];
+        convert(planes, format, ColorSpace::default(), width as u32, height as u32, &mut rgba, PixelFormat::Rgba8).unwrap();
+        rgba
+    }
+
+    #[test]
+    fn padded_yuv420_layouts(){
+        for (width, height) in [(16, 8), (13, 7)]{
+            let expected = expected_rgba(width, height, 0, 0);
+            let chroma_width = width.div_ceil(2);
+            for format in [PixelFormat::I420, PixelFormat::Nv12, PixelFormat::Nv21]{
+                let c_bytes = if format == PixelFormat::I420 { chroma_width } else { chroma_width * 2 };
+                // 紧密排列，以及行尾有填充(row_stride > width)
+                for (y_stride, c_stride) in [(width, c_bytes), (width + 10, c_bytes + 6), (64, 64)]{
+                    let (data, strides) = yuv420(format, width, height, y_stride, c_stride);
+                    let planes = planes(&data, format, width as u32, height as u32, &strides).unwrap();
+                    let pixel_stride = if format == PixelFormat::I420 { 1 } else { 2 };
+                    assert_eq!((planes[1].pixel_stride, planes[2].pixel_stride), (pixel_stride, pixel_stride));
+                    assert_eq!(convert_rgba(&planes, format, width, height), expected, "{format:?} {width}x{height} stride {y_stride}/{c_stride}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crop_odd_rect(){
+        let (width, height) = (20, 12);
+        // 起点为偶数，宽高为奇数
+        let (left, top, crop_width, crop_height) = (4, 2, 11, 7);
+        let expected = expected_rgba(crop_width, crop_height, left, top);
+        for format in [PixelFormat::I420, PixelFormat::Nv12, PixelFormat::Nv21]{
+            let c_stride = if format == PixelFormat::I420 { 16 } else { 24 };
+            let (data, strides) = yuv420(format, width, height, 32, c_stride);
+            let planes = planes(&data, format, width as u32, height as u32, &strides).unwrap();
+            let cropped = crop_planes(&planes, format, left as u32, top as u32).unwrap();
+            assert_eq!(convert_rgba(&cropped, format, crop_width, crop_height), expected, "{format:?}");
+        }
+
+        // 打包格式按像素裁剪
+        let rgba = expected_rgba(width, height, 0, 0);
+        let planes = planes(&rgba, PixelFormat::Rgba8, width as u32, height as u32, &[]).unwrap();
+        let cropped = crop_planes(&planes, PixelFormat::Rgba8, 3, 5).unwrap();
+        let mut expected = vec![];
+        for y in 5..5 + crop_height{
+            expected.extend_from_slice(&rgba[(y * width + 3) * 4..(y * width + 3 + crop_width) * 4]);
+        }
+        assert_eq!(convert_rgba(&cropped, PixelFormat::Rgba8, crop_width, crop_height), expected);
+    }
+
+    /// 按给定的平面转换 width x height 个像素
+    fn convert_to(planes: &[Plane], format: PixelFormat, width: u32, height: u32, dst_format: PixelFormat) -> Vec<u8>{
+        let mut dst = vec![0; (width * height) as usize * dst_format.bytes_per_pixel()];
+        convert(planes, format, ColorSpace::default(), width, height, &mut dst, dst_format).unwrap();
+        dst
+    }
+
+    fn assert_close(actual: &[u8], expected: &[u8], tolerance: u8){
+        assert_eq!(actual.len(), expected.len());
+        let close = actual.iter().zip(expected).all(|(a, b)| a.abs_diff(*b) <= tolerance);
+        assert!(close, "{actual:?} != {expected:?}");
+    }
+
+    // BT.601 limited range: Y=16 黑、Y=235 白、(81, 90, 240) 红
+    const BLACK: [u8; 3] = [16, 128, 128];
+    const WHITE: [u8; 3] = [235, 128, 128];
+    const RED: [u8; 3] = [81, 90, 240];
+
+    #[test]
+    fn rgb_formats(){
+        let rgba = [1, 2, 3, 4, 5, 6, 7, 8];
+        let plane = |data, format: PixelFormat| Plane::new(data, 2 * format.bytes_per_pixel(), format.bytes_per_pixel());
+        assert_eq!(convert_to(&[plane(&rgba[..], PixelFormat::Rgba8)], PixelFormat::Rgba8, 2, 1, PixelFormat::Rgba8), rgba);
+        assert_eq!(convert_to(&[plane(&rgba[..], PixelFormat::Rgba8)], PixelFormat::Rgba8, 2, 1, PixelFormat::Rgb8), [1, 2, 3, 5, 6, 7]);
+        let rgb = [10, 20, 30, 200, 100, 50];
+        assert_eq!(convert_to(&[plane(&rgb[..], PixelFormat::Rgb8)], PixelFormat::Rgb8, 2, 1, PixelFormat::Rgba8), [10, 20, 30, 255, 200, 100, 50, 255]);
+        // 灰度: (r*77 + g*150 + b*29) >> 8
+        assert_eq!(convert_to(&[plane(&rgb[..], PixelFormat::Rgb8)], PixelFormat::Rgb8, 2, 1, PixelFormat::Gray8), [18, 124]);
+        let gray = [0, 128];
+        assert_eq!(convert_to(&[plane(&gray[..], PixelFormat::Gray8)], PixelFormat::Gray8, 2, 1, PixelFormat::Rgba8), [0, 0, 0, 255, 128, 128, 128, 255]);
+        assert_eq!(convert_to(&[plane(&gray[..], PixelFormat::Gray8)], PixelFormat::Gray8, 2, 1, PixelFormat::Gray8), gray);
+
+        // 只支持输出 Rgba8、Rgb8、Gray8
+        let mut dst = vec![0; 8];
+        assert!(convert(&[plane(&rgba[..], PixelFormat::Rgba8)], PixelFormat::Rgba8, ColorSpace::default(), 2, 1, &mut dst, PixelFormat::Bgra8).is_err());
+    }
+
+    #[test]
+    fn bgra_strides(){
+        // 2x2，每行 20 字节，pixel_stride 8: 每隔一个像素取一个
+        let mut data = vec![0xEE; 40];
+        for (i, bgra) in [[30, 20, 10, 40], [3, 2, 1, 4], [0, 0, 255, 255], [255, 0, 0, 128]].iter().enumerate(){
+            let offset = i / 2 * 20 + i % 2 * 8;
+            data[offset..offset + 4].copy_from_slice(bgra);
+        }
+        let planes = [Plane::new(&data, 20, 8)];
+        assert_eq!(
+            convert_to(&planes, PixelFormat::Bgra8, 2, 2, PixelFormat::Rgba8),
+            [10, 20, 30, 40, 1, 2, 3, 4, 255, 0, 0, 255, 0, 0, 255, 128]
+        );
+        assert_eq!(convert_to(&planes, PixelFormat::Bgra8, 2, 2, PixelFormat::Rgb8), [10, 20, 30, 1, 2, 3, 255, 0, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rgb565_strides(){
+        // 每行两个像素 4 字节，row_stride 6
+        let pixels: [u16; 4] = [0xF800, 0x07E0, 0x001F, 0x8410];
+        let mut data = vec![];
+        for row in pixels.chunks(2){
+            data.extend(row.iter().flat_map(|p| p.to_le_bytes()));
+            data.extend_from_slice(&[0xEE; 2]);
+        }
+        let planes = planes(&data, PixelFormat::Rgb565, 2, 2, &[6]).unwrap();
+        assert_eq!(
+            convert_to(&planes, PixelFormat::Rgb565, 2, 2, PixelFormat::Rgba8),
+            [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 132, 130, 132, 255]
+        );
+    }
+
+    #[test]
+    fn packed_yuv422_strides(){
+        // 第一行: 黑、白共用中性色度，红、红；第二行从 row_stride 12 开始
+        for format in [PixelFormat::Yuyv, PixelFormat::Uyvy]{
+            let pack = |y0: u8, y1: u8, u: u8, v: u8| match format{
+                PixelFormat::Yuyv => [y0, u, y1, v],
+                _ => [u, y0, v, y1],
+            };
+            let row = |first: [u8; 4], second: [u8; 4]| [&first[..], &second[..], &[0xEE; 4][..]].concat();
+            let data = [
+                row(pack(BLACK[0], WHITE[0], 128, 128), pack(RED[0], RED[0], RED[1], RED[2])),
+                row(pack(WHITE[0], WHITE[0], 128, 128), pack(BLACK[0], BLACK[0], 128, 128)),
+            ].concat();
+            let planes = planes(&data, format, 4, 2, &[12]).unwrap();
+            assert_close(
+                &convert_to(&planes, format, 4, 2, PixelFormat::Rgb8),
+                &[0, 0, 0, 255, 255, 255, 255, 0, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0],
+                1,
+            );
+            // 灰度直接由 Y 计算
+            assert_eq!(convert_to(&planes, format, 4, 2, PixelFormat::Gray8)[..2], [0, 255]);
+            // 宽为奇数时最后一组只使用 Y0
+            assert_close(&convert_to(&planes, format, 3, 1, PixelFormat::Rgb8), &[0, 0, 0, 255, 255, 255, 255, 0, 0], 1);
+        }
+    }
+
+    #[test]
+    fn yuv420_formats(){
+        // 2x2: 左上黑、右上白，下面两个像素为红，共用红色的色度时只有亮度不同
+        for format in [PixelFormat::Nv12, PixelFormat::Nv21, PixelFormat::I420]{
+            let mut data = vec![RED[0], RED[0], RED[0], RED[0]];
+            data.extend_from_slice(&match format{
+                PixelFormat::Nv12 => vec![RED[1], RED[2]],
+                PixelFormat::Nv21 => vec![RED[2], RED[1]],
+                _ => vec![RED[1], RED[2]],
+            });
+            let rgb = convert_buffer(&data, format, ColorSpace::default(), 2, 2, &[], PixelFormat::Rgb8).unwrap();
+            assert_close(&rgb, &[255, 0, 0].repeat(4), 1);
+            let rgba = convert_buffer(&data, format, ColorSpace::default(), 2, 2, &[], PixelFormat::Rgba8).unwrap();
+            assert_close(&rgba, &[255, 0, 0, 255].repeat(4), 1);
+
+            data[..4].copy_from_slice(&[BLACK[0], WHITE[0], 126, 126]);
+            let gray = convert_buffer(&data, format, ColorSpace::default(), 2, 2, &[], PixelFormat::Gray8).unwrap();
+            assert_eq!(gray, [0, 255, 128, 128]);
+        }
+
+        // full range 的 Y 不需要缩放
+        let data = [0, 128, 255, 255, 128, 128];
+        let full = ColorSpace::new(ColorMatrix::Bt709, ColorRange::Full);
+        let gray = convert_buffer(&data, PixelFormat::Nv12, full, 2, 2, &[], PixelFormat::Gray8).unwrap();
+        assert_eq!(gray, [0, 128, 255, 255]);
+    }
+
+    #[test]
+    fn parallel_matches_single_thread(){
+        for (width, height) in [(1usize, 1usize), (3, 5), (97, 61), (321, 239)]{
+            for format in [PixelFormat::I420, PixelFormat::Nv21, PixelFormat::Nv12]{
+                let chroma_bytes = if format == PixelFormat::I420 { width.div_ceil(2) } else { width.div_ceil(2) * 2 };
+                let (data, strides) = yuv420(format, width, height, width + 3, chroma_bytes + 1);
+                let planes = planes(&data, format, width as u32, height as u32, &strides).unwrap();
+                for dst_format in [PixelFormat::Rgba8, PixelFormat::Rgb8, PixelFormat::Gray8]{
+                    let size = width * height * dst_format.bytes_per_pixel();
+                    let mut single = vec![0; size];
+                    convert(&planes, format, ColorSpace::default(), width as u32, height as u32, &mut single, dst_format).unwrap();
+                    // 分块数不同时最后一块的行数不同，可能是奇数
+                    for threads in 2..=7{
+                        let mut parallel = vec![1; size];
+                        convert_threads(&planes, format, ColorSpace::default(), width as u32, height as u32, &mut parallel, dst_format, threads).unwrap();
+                        assert!(single == parallel, "{format:?} -> {dst_format:?} {width}x{height} {threads} threads");
+                    }
+                    let mut parallel = vec![1; size];
+                    convert_parallel(&planes, format, ColorSpace::default(), width as u32, height as u32, &mut parallel, dst_format).unwrap();
+                    assert!(single == parallel, "{format:?} -> {dst_format:?} {width}x{height}");
+                }
+            }
+            let rgba = expected_rgba(width, height, 0, 0);
+            for format in [PixelFormat::Yuyv, PixelFormat::Rgba8]{
+                // YUYV 每两个像素一组，宽为奇数时最后一组只用一个像素
+                let stride_width = if format == PixelFormat::Yuyv { width.next_multiple_of(2) } else { width };
+                let planes = planes(&rgba, format, stride_width as u32, height as u32, &[]).unwrap();
+                let mut single = vec![0; width * height * 4];
+                convert(&planes, format, ColorSpace::default(), width as u32, height as u32, &mut single, PixelFormat::Rgba8).unwrap();
+                for threads in 2..=7{
+                    let mut parallel = vec![1; width * height * 4];
+                    convert_threads(&planes, format, ColorSpace::default(), width as u32, height as u32, &mut parallel, PixelFormat::Rgba8, threads).unwrap();
+                    assert!(single == parallel, "{format:?} {width}x{height} {threads} threads");
+                }
+            }
+        }
+    }
+
+    /// 3x2 的图像 a b c / d e f 在 8 种方向变换后的像素顺序: (rotation, mirror, 输出宽, 输出像素)
+    const DIHEDRAL: [(i32, bool, u32, [u8; 6]); 8] = [
+        (0, false, 3, *b"abcdef"),
+        (0, true, 3, *b"cbafed"),
+        (90, false, 2, *b"daebfc"),
+        (90, true, 2, *b"adbecf"),
+        (180, false, 3, *b"fedcba"),
+        (180, true, 3, *b"defabc"),
+        (270, false, 2, *b"cfbead"),
+        (270, true, 2, *b"fcebda"),
+    ];
+
+    #[test]
+    fn orientation_mapping(){
+        let src: Vec<u8> = b"abcdef".iter().flat_map(|c| [*c, 0, 0, 255]).collect();
+        for (rotation, mirror, out_width, expected) in DIHEDRAL{
+            let orientation = Orientation::new(rotation, mirror);
+            assert_eq!((orientation.rotation(), orientation.mirror()), (rotation, mirror));
+            let mut dst = vec![];
+            let size = transform_rgba(&src, 3, 2, orientation, &mut dst);
+            assert_eq!(size, (out_width, 6 / out_width), "{orientation:?}");
+            assert_eq!(size, orientation.output_size(3, 2));
+            let pixels: Vec<u8> = dst.chunks_exact(4).map(|pixel| pixel[0]).collect();
+            assert_eq!(pixels, expected, "{orientation:?}");
+            // map() 和矩阵给出同样的位置
+            for (i, c) in b"abcdef".iter().enumerate(){
+                let (x, y) = orientation.map(i as u32 % 3, i as u32 / 3, 3, 2);
+                assert_eq!(expected[(y * out_width + x) as usize], *c, "{orientation:?} {}", *c as char);
+            }
+        }
+        assert_eq!(Orientation::flip_horizontal(), Orientation::new(0, true));
+        assert_eq!(Orientation::flip_vertical().output_size(3, 2), (3, 2));
+    }
+
+    #[test]
+    fn orientation_normalized(){
+        // 不是 90 的倍数的角度向下取整，负角度按顺时针换算
+        assert_eq!(Orientation::rotate(45).rotation(), 0);
+        assert_eq!(Orientation::rotate(135).rotation(), 90);
+        assert_eq!(Orientation::rotate(-90).rotation(), 270);
+        assert_eq!(Orientation::rotate(450).rotation(), 90);
+        assert!(Orientation::rotate(360).is_identity());
+        let mut dst = vec![];
+        assert_eq!(transform_rgba(&[0; 24], 3, 2, Orientation::rotate(-270), &mut dst), (2, 3));
+    }
+
+    #[test]
+    fn pack_yuv420_layouts(){
+        for (width, height) in [(16usize, 8usize), (13, 7)]{
+            let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+            let (data, strides) = yuv420(PixelFormat::Nv21, width, height, width + 4, cw * 2 + 6);
+            let planes = planes(&data, PixelFormat::Nv21, width as u32, height as u32, &strides).unwrap();
+            // 紧密排列，以及编码器要求的 stride、slice-height
+            for (stride, slice_height) in [(width, height), (32, 16)]{
+                for format in [PixelFormat::I420, PixelFormat::Nv12]{
+                    let mut packed = vec![];
+                    pack_yuv420_padded(&planes, width as u32, height as u32, format, stride, slice_height, &mut packed).unwrap();
+                    let c_stride = if format == PixelFormat::I420 { stride.div_ceil(2) } else { stride.div_ceil(2) * 2 };
+                    let chroma = &packed[stride * slice_height..];
+                    let chroma_size = c_stride * slice_height.div_ceil(2);
+                    let planes_count = if format == PixelFormat::I420 { 2 } else { 1 };
+                    assert_eq!(packed.len(), stride * slice_height + chroma_size * planes_count);
+                    for y in 0..slice_height{
+                        for x in 0..stride{
+                            let expected = if x < width && y < height { y_at(x, y) } else { 0 };
+                            assert_eq!(packed[y * stride + x], expected, "{format:?} {stride}x{slice_height} Y({x},{y})");
+                        }
+                    }
+                    for y in 0..slice_height.div_ceil(2){
+                        for x in 0..cw{
+                            let (u, v) = if y < ch { (u_at(x, y), v_at(x, y)) } else { (0, 0) };
+                            let actual = match format{
+                                PixelFormat::I420 => (chroma[y * c_stride + x], chroma[chroma_size + y * c_stride + x]),
+                                _ => (chroma[y * c_stride + x * 2], chroma[y * c_stride + x * 2 + 1]),
+                            };
+                            assert_eq!(actual, (u, v), "{format:?} {stride}x{slice_height} UV({x},{y})");
+                        }
+                    }
+                }
+            }
+            let mut tight = vec![];
+            pack_yuv420(&planes, width as u32, height as u32, PixelFormat::I420, &mut tight).unwrap();
+            assert_eq!(tight.len(), width * height + cw * ch * 2);
+            assert!(pack_yuv420_padded(&planes, width as u32, height as u32, PixelFormat::I420, width - 1, height, &mut tight).is_err());
+        }
+    }
+
+    #[test]
+    fn plane_too_small(){
+        let (data, strides) = yuv420(PixelFormat::Nv21, 16, 8, 20, 20);
+        let planes = planes(&data[..data.len() - 8], PixelFormat::Nv21, 16, 8, &strides).unwrap();
+        let mut rgba = vec![0; 16 * 8 * 4];
+        assert!(convert(&planes, PixelFormat::Nv21, ColorSpace::default(), 16, 8, &mut rgba, PixelFormat::Rgba8).is_err());
+    }
+}
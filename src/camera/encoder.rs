@@ -0,0 +1,529 @@
+//! 硬件编码录像: 编码器和封装器的接口，以及与平台无关的时间戳、封装逻辑
+//!
+//! android 上由 MediaCodec、MediaMuxer 实现，测试中用 MockEncoder、MemoryMuxer 运行同样的逻辑
+
+use std::{
+    sync::mpsc::{sync_channel, SyncSender},
+    time::{Duration, Instant},
+};
+use anyhow::{anyhow, Result};
+
+use super::{worker::{FrameHandler, FrameWorker}, Frame};
+
+/// 停止时等待编码器输出剩余数据的最长时间
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 视频编码格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoCodec{
+    #[default]
+    H264,
+    Hevc,
+}
+
+impl VideoCodec{
+    pub fn mime(&self) -> &'static str{
+        match self{
+            VideoCodec::H264 => "video/avc",
+            VideoCodec::Hevc => "video/hevc",
+        }
+    }
+}
+
+/// 硬件编码参数
+#[derive(Debug, Clone, Copy)]
+pub struct EncodeOptions{
+    pub codec: VideoCodec,
+    /// 码率(bps)，为 0 时按分辨率和帧率计算
+    pub bit_rate: u32,
+    /// 编码器参考的帧率，实际时间戳按相机的时间
+    pub fps: u32,
+    /// 关键帧间隔(秒)
+    pub key_frame_interval: u32,
+}
+
+impl Default for EncodeOptions{
+    fn default() -> Self{
+        Self { codec: VideoCodec::H264, bit_rate: 0, fps: 30, key_frame_interval: 1 }
+    }
+}
+
+impl EncodeOptions{
+    /// 1080p30 约 6Mbps
+    pub fn bit_rate_for(&self, width: u32, height: u32) -> u32{
+        match self.bit_rate{
+            0 => (width as u64 * height as u64 * self.fps.max(1) as u64 / 10).min(u32::MAX as u64) as u32,
+            bit_rate => bit_rate,
+        }
+    }
+}
+
+/// 编码器输出的轨道格式，封装器用它创建轨道
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackFormat{
+    pub mime: String,
+    pub width: u32,
+    pub height: u32,
+    /// 解码配置 csd-0、csd-1...，H.264 为 SPS、PPS
+    pub codec_config: Vec<Vec<u8>>,
+}
+
+/// 编码后的一帧
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedPacket{
+    pub data: Vec<u8>,
+    /// 显示时间(微秒)，从 0 开始
+    pub pts_us: i64,
+    pub key_frame: bool,
+}
+
+pub enum EncoderOutput{
+    /// 在第一个数据包之前输出一次
+    Format(TrackFormat),
+    Packet(EncodedPacket),
+    /// 暂时没有输出
+    Pending,
+    /// queue_end_of_stream 之后的数据已经全部输出
+    EndOfStream,
+}
+
+/// 视频编码器，输入 I420 帧
+pub trait VideoEncoder: Send{
+    /// 送入一帧，pts_us 严格递增，返回 false 表示编码器没有空闲的输入缓冲区，这一帧被丢弃
+    fn queue_frame(&mut self, frame: &Frame, pts_us: i64) -> Result<bool>;
+    /// 输入结束
+    fn queue_end_of_stream(&mut self) -> Result<()>;
+    /// 取出一个输出，最多等待 timeout
+    fn poll_output(&mut self, timeout: Duration) -> Result<EncoderOutput>;
+}
+
+/// 封装器，如 MP4
+pub trait Muxer: Send{
+    /// 播放时顺时针旋转的角度(0、90、180、270)，在 start 之前调用
+    fn set_orientation_hint(&mut self, degrees: i32) -> Result<()>;
+    /// 添加轨道，返回轨道序号
+    fn add_track(&mut self, format: &TrackFormat) -> Result<usize>;
+    fn start(&mut self) -> Result<()>;
+    fn write_sample(&mut self, track: usize, packet: &EncodedPacket) -> Result<()>;
+    /// 结束并写完文件
+    fn stop(&mut self) -> Result<()>;
+}
+
+/// 硬件编码录像统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncodeStats{
+    /// 录制期间(不包括暂停)收到的帧数
+    pub received: u64,
+    /// 送入编码器的帧数
+    pub encoded: u64,
+    /// 时间戳没有递增、大小和第一帧不同或者编码器繁忙而丢弃的帧数
+    pub dropped: u64,
+    /// 写入文件的数据包数
+    pub written: u64,
+    /// 最后一个数据包的时间
+    pub duration: Duration,
+}
+
+/// 把相机帧送入编码器，把编码器的输出写入封装器
+///
+/// 时间戳从第一帧开始计算，暂停的时间不计入视频
+pub struct EncodeSession<E: VideoEncoder, M: Muxer>{
+    encoder: E,
+    muxer: M,
+    track: Option<usize>,
+    size: Option<(u32, u32)>,
+    first_ns: Option<i64>,
+    /// 暂停去掉的时间
+    offset_ns: i64,
+    last_pts_us: Option<i64>,
+    /// 最近两帧的间隔，暂停恢复后的第一帧紧接在最后一帧之后
+    frame_interval_us: i64,
+    paused: bool,
+    stats: EncodeStats,
+}
+
+impl<E: VideoEncoder, M: Muxer> EncodeSession<E, M>{
+    /// rotation 为播放时顺时针旋转的角度
+    pub fn new(encoder: E, mut muxer: M, rotation: i32) -> Result<Self>{
+        muxer.set_orientation_hint(rotation.rem_euclid(360) / 90 * 90)?;
+        Ok(Self {
+            encoder,
+            muxer,
+            track: None,
+            size: None,
+            first_ns: None,
+            offset_ns: 0,
+            last_pts_us: None,
+            frame_interval_us: 33_333,
+            paused: false,
+            stats: EncodeStats::default(),
+        })
+    }
+
+    /// 送入一帧 I420，timestamp_ns 为相机的时间戳
+    pub fn push_frame(&mut self, frame: &Frame) -> Result<()>{
+        self.stats.received += 1;
+        if *self.size.get_or_insert((frame.width, frame.height)) != (frame.width, frame.height){
+            self.stats.dropped += 1;
+            return Ok(());
+        }
+        let first_ns = *self.first_ns.get_or_insert(frame.timestamp_ns);
+        if let (true, Some(last_pts_us)) = (self.paused, self.last_pts_us){
+            let elapsed_ns = frame.timestamp_ns - first_ns - self.offset_ns;
+            self.offset_ns += elapsed_ns - (last_pts_us + self.frame_interval_us) * 1000;
+        }
+        self.paused = false;
+        let pts_us = (frame.timestamp_ns - first_ns - self.offset_ns) / 1000;
+        if let Some(last_pts_us) = self.last_pts_us{
+            if pts_us <= last_pts_us{
+                self.stats.dropped += 1;
+                return Ok(());
+            }
+        }
+        if !self.encoder.queue_frame(frame, pts_us)?{
+            self.stats.dropped += 1;
+            return Ok(());
+        }
+        if let Some(last_pts_us) = self.last_pts_us{
+            self.frame_interval_us = pts_us - last_pts_us;
+        }
+        self.last_pts_us = Some(pts_us);
+        self.stats.encoded += 1;
+        self.drain(Duration::ZERO, false)
+    }
+
+    /// 暂停，之后送入的第一帧时间接在暂停前的最后一帧之后
+    pub fn pause(&mut self){
+        self.paused = true;
+    }
+
+    /// 写入编码器当前的所有输出，until_end_of_stream 时一直等到 EndOfStream
+    fn drain(&mut self, timeout: Duration, until_end_of_stream: bool) -> Result<()>{
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        loop{
+            match self.encoder.poll_output(timeout)?{
+                EncoderOutput::Format(format) => {
+                    if self.track.is_some(){
+                        return Err(anyhow!("encoder output format changed after start"));
+                    }
+                    self.track = Some(self.muxer.add_track(&format)?);
+                    self.muxer.start()?;
+                }
+                EncoderOutput::Packet(packet) => {
+                    let track = self.track.ok_or(anyhow!("encoder output a packet before its format"))?;
+                    self.muxer.write_sample(track, &packet)?;
+                    self.stats.written += 1;
+                    self.stats.duration = Duration::from_micros(packet.pts_us.max(0) as u64);
+                }
+                EncoderOutput::Pending if until_end_of_stream && Instant::now() < deadline => (),
+                EncoderOutput::Pending => return Ok(()),
+                EncoderOutput::EndOfStream => return Ok(()),
+            }
+        }
+    }
+
+    /// 结束输入，写完编码器剩余的输出并结束封装
+    pub fn finish(&mut self) -> Result<EncodeStats>{
+        self.encoder.queue_end_of_stream()?;
+        self.drain(Duration::from_millis(10), true)?;
+        if self.track.is_none(){
+            return Err(anyhow!("no frame encoded"));
+        }
+        self.muxer.stop()?;
+        Ok(self.stats)
+    }
+
+    pub fn stats(&self) -> EncodeStats{
+        self.stats
+    }
+
+    pub fn muxer(&self) -> &M{
+        &self.muxer
+    }
+}
+
+impl<E: VideoEncoder + 'static, M: Muxer + 'static> FrameHandler for EncodeSession<E, M>{
+    type Output = EncodeStats;
+
+    fn on_frame(&mut self, frame: Frame) -> Result<()>{
+        self.push_frame(&frame)
+    }
+
+    fn on_paused(&mut self) -> Result<()>{
+        self.pause();
+        Ok(())
+    }
+
+    fn on_idle(&mut self) -> Result<()>{
+        self.drain(Duration::ZERO, false)
+    }
+
+    fn on_stop(mut self) -> Result<EncodeStats>{
+        self.finish()
+    }
+}
+
+/// 在单独的线程中运行 EncodeSession，相机线程调用 send() 送入帧，不会阻塞
+pub struct EncodeRecorder{
+    sender: Option<SyncSender<Frame>>,
+    worker: FrameWorker<EncodeStats>,
+}
+
+impl EncodeRecorder{
+    pub fn start<E: VideoEncoder + 'static, M: Muxer + 'static>(session: EncodeSession<E, M>) -> Self{
+        // 编码慢于相机时最多缓存几帧
+        let (sender, receiver) = sync_channel::<Frame>(4);
+        Self { sender: Some(sender), worker: FrameWorker::start(receiver, session) }
+    }
+
+    /// 送入一帧，编码线程来不及处理时丢弃，返回 false
+    pub fn send(&self, frame: Frame) -> bool{
+        self.sender.as_ref().is_some_and(|sender| sender.try_send(frame).is_ok())
+    }
+
+    pub fn pause(&self){
+        self.worker.pause();
+    }
+
+    pub fn resume(&self){
+        self.worker.resume();
+    }
+
+    pub fn is_paused(&self) -> bool{
+        self.worker.is_paused()
+    }
+
+    /// 停止录像并写完文件
+    pub fn stop(mut self) -> Result<EncodeStats>{
+        self.finish()
+    }
+
+    /// 关闭发送端，编码线程处理完已经送入的帧后结束
+    fn finish(&mut self) -> Result<EncodeStats>{
+        self.sender.take();
+        self.worker.join()
+    }
+}
+
+impl Drop for EncodeRecorder{
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::collections::VecDeque;
+    use super::*;
+    use super::super::PixelFormat;
+
+    /// 不做编码的编码器，每一帧输出一个包含帧序号的数据包，用于在没有硬件编码器的平台上检查时间戳和封装逻辑
+    struct MockEncoder{
+        format: TrackFormat,
+        /// 每隔多少帧一个关键帧
+        key_frame_interval: u64,
+        outputs: VecDeque<EncoderOutput>,
+        frames: u64,
+        end_of_stream: bool,
+    }
+
+    impl MockEncoder{
+        fn new(width: u32, height: u32, options: &EncodeOptions) -> Self{
+            let format = TrackFormat {
+                mime: options.codec.mime().to_string(),
+                width,
+                height,
+                codec_config: vec![b"mock".to_vec()],
+            };
+            let key_frame_interval = (options.fps * options.key_frame_interval).max(1) as u64;
+            Self { format, key_frame_interval, outputs: VecDeque::new(), frames: 0, end_of_stream: false }
+        }
+    }
+
+    impl VideoEncoder for MockEncoder{
+        fn queue_frame(&mut self, frame: &Frame, pts_us: i64) -> Result<bool>{
+            if self.end_of_stream{
+                return Err(anyhow!("frame queued after end of stream"));
+            }
+            if (frame.width, frame.height) != (self.format.width, self.format.height){
+                return Err(anyhow!("frame size {}x{} != {}x{}", frame.width, frame.height, self.format.width, self.format.height));
+            }
+            if self.frames == 0{
+                self.outputs.push_back(EncoderOutput::Format(self.format.clone()));
+            }
+            // u64::is_multiple_of 需要 Rust 1.87，key_frame_interval 在 new 中保证不为 0
+            #[allow(clippy::manual_is_multiple_of)]
+            let key_frame = self.frames % self.key_frame_interval == 0;
+            self.outputs.push_back(EncoderOutput::Packet(EncodedPacket { data: frame.sequence.to_le_bytes().to_vec(), pts_us, key_frame }));
+            self.frames += 1;
+            Ok(true)
+        }
+
+        fn queue_end_of_stream(&mut self) -> Result<()>{
+            self.end_of_stream = true;
+            Ok(())
+        }
+
+        fn poll_output(&mut self, _timeout: Duration) -> Result<EncoderOutput>{
+            Ok(match self.outputs.pop_front(){
+                Some(output) => output,
+                None if self.end_of_stream => EncoderOutput::EndOfStream,
+                None => EncoderOutput::Pending,
+            })
+        }
+    }
+
+    /// 把轨道和数据包保存在内存中的封装器
+    #[derive(Debug, Default)]
+    struct MemoryMuxer{
+        orientation_hint: i32,
+        tracks: Vec<TrackFormat>,
+        /// (轨道序号, 数据包)
+        samples: Vec<(usize, EncodedPacket)>,
+        started: bool,
+        stopped: bool,
+    }
+
+    impl Muxer for MemoryMuxer{
+        fn set_orientation_hint(&mut self, degrees: i32) -> Result<()>{
+            if self.started{
+                return Err(anyhow!("orientation hint must be set before start"));
+            }
+            self.orientation_hint = degrees;
+            Ok(())
+        }
+
+        fn add_track(&mut self, format: &TrackFormat) -> Result<usize>{
+            if self.started{
+                return Err(anyhow!("track added after start"));
+            }
+            self.tracks.push(format.clone());
+            Ok(self.tracks.len() - 1)
+        }
+
+        fn start(&mut self) -> Result<()>{
+            self.started = true;
+            Ok(())
+        }
+
+        fn write_sample(&mut self, track: usize, packet: &EncodedPacket) -> Result<()>{
+            if !self.started || self.stopped || track >= self.tracks.len(){
+                return Err(anyhow!("invalid sample write, track {track}"));
+            }
+            self.samples.push((track, packet.clone()));
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<()>{
+            if !self.started{
+                return Err(anyhow!("muxer stopped before start"));
+            }
+            self.stopped = true;
+            Ok(())
+        }
+    }
+
+    const INTERVAL_NS: i64 = 33_333_333;
+
+    fn frame(width: u32, height: u32, timestamp_ns: i64, sequence: u64) -> Frame{
+        let mut frame = Frame::new(vec![0; (width * height * 3 / 2) as usize], PixelFormat::I420, width, height);
+        frame.timestamp_ns = timestamp_ns;
+        frame.sequence = sequence;
+        frame
+    }
+
+    fn session(rotation: i32) -> EncodeSession<MockEncoder, MemoryMuxer>{
+        EncodeSession::new(MockEncoder::new(16, 8, &EncodeOptions::default()), MemoryMuxer::default(), rotation).unwrap()
+    }
+
+    /// 写入的数据包: (帧序号, pts)
+    fn written(session: &EncodeSession<MockEncoder, MemoryMuxer>) -> Vec<(u64, i64)>{
+        session
+            .muxer()
+            .samples
+            .iter()
+            .map(|(_, packet)| (u64::from_le_bytes(packet.data[..8].try_into().unwrap()), packet.pts_us))
+            .collect()
+    }
+
+    #[test]
+    fn pts_is_monotonic(){
+        let mut session = session(0);
+        // 相机的时间戳不从 0 开始，中间有重复和倒退的
+        let start_ns = 5_000_000_000;
+        for (sequence, offset_ns) in [0, INTERVAL_NS, INTERVAL_NS, INTERVAL_NS / 2, 2 * INTERVAL_NS, 3 * INTERVAL_NS].into_iter().enumerate(){
+            session.push_frame(&frame(16, 8, start_ns + offset_ns, sequence as u64)).unwrap();
+        }
+        let stats = session.finish().unwrap();
+        assert_eq!(written(&session), [(0, 0), (1, 33_333), (4, 66_666), (5, 99_999)]);
+        assert_eq!((stats.received, stats.encoded, stats.dropped, stats.written), (6, 4, 2, 4));
+        assert_eq!(stats.duration, Duration::from_micros(99_999));
+        assert!(session.muxer().stopped);
+    }
+
+    #[test]
+    fn pause_gap_is_removed(){
+        let mut session = session(0);
+        for i in 0..3{
+            session.push_frame(&frame(16, 8, i * INTERVAL_NS, i as u64)).unwrap();
+        }
+        // 暂停 2 秒，恢复后的第一帧紧接在暂停前的最后一帧之后
+        session.pause();
+        let resume_ns = 2 * INTERVAL_NS + 2_000_000_000;
+        for i in 0..2{
+            session.push_frame(&frame(16, 8, resume_ns + i * INTERVAL_NS, 3 + i as u64)).unwrap();
+        }
+        session.finish().unwrap();
+        assert_eq!(written(&session), [(0, 0), (1, 33_333), (2, 66_666), (3, 99_999), (4, 133_332)]);
+    }
+
+    #[test]
+    fn frames_with_other_size_are_dropped(){
+        let mut session = session(0);
+        session.push_frame(&frame(16, 8, 0, 0)).unwrap();
+        // MockEncoder 收到大小不同的帧会返回错误
+        session.push_frame(&frame(32, 16, INTERVAL_NS, 1)).unwrap();
+        session.push_frame(&frame(16, 8, 2 * INTERVAL_NS, 2)).unwrap();
+        let stats = session.finish().unwrap();
+        assert_eq!(written(&session), [(0, 0), (2, 66_666)]);
+        assert_eq!((stats.received, stats.dropped), (3, 1));
+    }
+
+    #[test]
+    fn format_is_added_before_packets(){
+        let mut session = session(0);
+        assert!(!session.muxer().started);
+        session.push_frame(&frame(16, 8, 0, 0)).unwrap();
+        let muxer = session.muxer();
+        assert!(muxer.started);
+        assert_eq!(muxer.tracks.len(), 1);
+        assert_eq!((muxer.tracks[0].width, muxer.tracks[0].height, muxer.tracks[0].mime.as_str()), (16, 8, "video/avc"));
+        assert_eq!(muxer.samples.len(), 1);
+        assert!(muxer.samples[0].1.key_frame);
+
+        // 编码器先输出数据包时报错，不写入封装器
+        let mut encoder = MockEncoder::new(16, 8, &EncodeOptions::default());
+        encoder.frames = 1;
+        let mut session = EncodeSession::new(encoder, MemoryMuxer::default(), 0).unwrap();
+        assert!(session.push_frame(&frame(16, 8, 0, 0)).is_err());
+        assert!(session.muxer().samples.is_empty());
+        assert!(session.finish().is_err());
+    }
+
+    #[test]
+    fn recorder_encodes_queued_frames_on_stop(){
+        let recorder = EncodeRecorder::start(session(90));
+        for i in 0..4{
+            assert!(recorder.send(frame(16, 8, i * INTERVAL_NS, i as u64)));
+        }
+        let stats = recorder.stop().unwrap();
+        assert_eq!((stats.received, stats.encoded, stats.written), (4, 4, 4));
+    }
+
+    #[test]
+    fn orientation_hint_is_a_multiple_of_90(){
+        for (rotation, hint) in [(0, 0), (90, 90), (270, 270), (360, 0), (450, 90), (-90, 270), (-450, 270), (100, 90)]{
+            assert_eq!(session(rotation).muxer().orientation_hint, hint, "rotation {rotation}");
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+
+use super::{CameraInfo, Exposure, Frame};
+
+/// 写入照片的 EXIF 信息，不包含 GPS
+#[derive(Debug, Clone)]
+pub struct ExifInfo{
+    /// 设备厂商
+    pub make: String,
+    /// 设备型号
+    pub model: String,
+    /// 镜头(相机)名称
+    pub lens_model: String,
+    pub software: String,
+    /// 拍摄时间
+    pub time: SystemTime,
+    /// 拍摄时所在时区和 UTC 相差的秒数，DateTime 按当地时间写入，OffsetTime 写入这个差值
+    pub utc_offset_secs: i32,
+    /// EXIF 方向 1~8，1 为正常
+    pub orientation: u16,
+    pub width: u32,
+    pub height: u32,
+    pub exposure: Exposure,
+}
+
+impl ExifInfo{
+    /// 使用帧的元数据和相机信息，time 为拍摄时间，时区使用系统当前的时区
+    ///
+    /// Frame 的方向已经应用到像素上，所以 orientation 为 1
+    pub fn new(frame: &Frame, info: &CameraInfo, time: SystemTime) -> Self{
+        #[cfg(target_os = "android")]
+        let (make, model) = super::camera2::device_make_model();
+        #[cfg(not(target_os = "android"))]
+        let (make, model) = (String::new(), info.name.clone());
+        Self {
+            make,
+            model,
+            lens_model: info.name.clone(),
+            software: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            time,
+            utc_offset_secs: DateTime::<Local>::from(time).offset().local_minus_utc(),
+            orientation: 1,
+            width: frame.width,
+            height: frame.height,
+            exposure: frame.exposure,
+        }
+    }
+
+    /// APP1 段的内容: "Exif\0\0" + TIFF(小端)
+    pub fn to_app1(&self) -> Vec<u8>{
+        let since_epoch = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        // DateTimeOriginal 没有时区，读取时按当地时间处理
+        let datetime = format_datetime((since_epoch.as_secs() as i64 + self.utc_offset_secs as i64).max(0) as u64);
+        let offset = format_offset(self.utc_offset_secs);
+        let subsec = format!("{:03}", since_epoch.subsec_millis());
+
+        let mut ifd0 = vec![];
+        ascii(&mut ifd0, 0x010F, &self.make);
+        ascii(&mut ifd0, 0x0110, &self.model);
+        ifd0.push((0x0112, Value::Short(self.orientation)));
+        ifd0.push((0x011A, Value::Rational(72, 1)));
+        ifd0.push((0x011B, Value::Rational(72, 1)));
+        // 分辨率单位: 英寸
+        ifd0.push((0x0128, Value::Short(2)));
+        ascii(&mut ifd0, 0x0131, &self.software);
+        ascii(&mut ifd0, 0x0132, &datetime);
+
+        let mut exif = vec![];
+        if let Some(ns) = self.exposure.exposure_time_ns{
+            let (numerator, denominator) = reduce(ns.clamp(0, u32::MAX as i64) as u64, 1_000_000_000);
+            exif.push((0x829A, Value::Rational(numerator, denominator)));
+        }
+        if let Some(aperture) = self.exposure.aperture{
+            exif.push((0x829D, Value::Rational((aperture * 100.).round() as u32, 100)));
+        }
+        if let Some(iso) = self.exposure.iso{
+            exif.push((0x8827, Value::Short(iso.min(u16::MAX as u32) as u16)));
+        }
+        exif.push((0x9000, Value::Undefined(b"0231".to_vec())));
+        ascii(&mut exif, 0x9003, &datetime);
+        ascii(&mut exif, 0x9004, &datetime);
+        ascii(&mut exif, 0x9010, &offset);
+        ascii(&mut exif, 0x9011, &offset);
+        if let Some(focal_length) = self.exposure.focal_length{
+            exif.push((0x920A, Value::Rational((focal_length * 1000.).round() as u32, 1000)));
+        }
+        ascii(&mut exif, 0x9291, &subsec);
+        // 色彩空间: sRGB
+        exif.push((0xA001, Value::Short(1)));
+        exif.push((0xA002, Value::Long(self.width)));
+        exif.push((0xA003, Value::Long(self.height)));
+        ascii(&mut exif, 0xA434, &self.lens_model);
+
+        // IFD0 的大小和 Exif IFD 指针的值无关，先按 0 计算出 Exif IFD 的位置
+        const IFD0_OFFSET: u32 = 8;
+        ifd0.push((0x8769, Value::Long(0)));
+        let exif_offset = IFD0_OFFSET + write_ifd(&mut ifd0, IFD0_OFFSET).len() as u32;
+        if let Some((_, value)) = ifd0.iter_mut().find(|(tag, _)| *tag == 0x8769){
+            *value = Value::Long(exif_offset);
+        }
+
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(b"II");
+        app1.extend_from_slice(&42u16.to_le_bytes());
+        app1.extend_from_slice(&IFD0_OFFSET.to_le_bytes());
+        app1.extend(write_ifd(&mut ifd0, IFD0_OFFSET));
+        app1.extend(write_ifd(&mut exif, exif_offset));
+        app1
+    }
+
+    /// 把 EXIF 写入 JPEG，替换原有的 EXIF 段
+    pub fn insert_into_jpeg(&self, jpeg: &[u8]) -> Result<Vec<u8>>{
+        let app1 = self.to_app1();
+        if app1.len() + 2 > u16::MAX as usize{
+            return Err(anyhow!("EXIF too large: {} bytes", app1.len()));
+        }
+        if !jpeg.starts_with(&[0xFF, 0xD8]){
+            return Err(anyhow!("not a JPEG"));
+        }
+        let mut output = Vec::with_capacity(jpeg.len() + app1.len() + 4);
+        output.extend_from_slice(&jpeg[..2]);
+        let mut inserted = false;
+        let mut pos = 2;
+        // 遍历 SOS 之前的段，EXIF 放在 JFIF(APP0) 之后
+        while pos + 4 <= jpeg.len() && jpeg[pos] == 0xFF && jpeg[pos + 1] != 0xDA{
+            let marker = jpeg[pos + 1];
+            let length = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+            let end = pos + 2 + length;
+            if length < 2 || end > jpeg.len(){
+                return Err(anyhow!("invalid JPEG segment at {pos}"));
+            }
+            if !inserted && marker != 0xE0{
+                write_app1(&mut output, &app1);
+                inserted = true;
+            }
+            // 丢弃原有的 EXIF
+            if !(marker == 0xE1 && jpeg[pos + 4..end].starts_with(b"Exif\0")){
+                output.extend_from_slice(&jpeg[pos..end]);
+            }
+            pos = end;
+        }
+        if !inserted{
+            write_app1(&mut output, &app1);
+        }
+        output.extend_from_slice(&jpeg[pos..]);
+        Ok(output)
+    }
+}
+
+fn write_app1(output: &mut Vec<u8>, app1: &[u8]){
+    output.extend_from_slice(&[0xFF, 0xE1]);
+    output.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+    output.extend_from_slice(app1);
+}
+
+enum Value{
+    Ascii(String),
+    Short(u16),
+    Long(u32),
+    Rational(u32, u32),
+    Undefined(Vec<u8>),
+}
+
+impl Value{
+    /// (类型, 个数, 数据)
+    fn encode(&self) -> (u16, u32, Vec<u8>){
+        match self{
+            Value::Ascii(text) => {
+                let mut data = text.as_bytes().to_vec();
+                data.push(0);
+                (2, data.len() as u32, data)
+            }
+            Value::Short(value) => (3, 1, value.to_le_bytes().to_vec()),
+            Value::Long(value) => (4, 1, value.to_le_bytes().to_vec()),
+            Value::Rational(numerator, denominator) => (5, 1, [numerator.to_le_bytes(), denominator.to_le_bytes()].concat()),
+            Value::Undefined(data) => (7, data.len() as u32, data.clone()),
+        }
+    }
+}
+
+/// 空字符串不写
+fn ascii(entries: &mut Vec<(u16, Value)>, tag: u16, text: &str){
+    if !text.is_empty(){
+        entries.push((tag, Value::Ascii(text.to_string())));
+    }
+}
+
+/// 写一个 IFD 和它引用的数据，offset 是 IFD 相对 TIFF 头的位置，没有下一个 IFD
+fn write_ifd(entries: &mut [(u16, Value)], offset: u32) -> Vec<u8>{
+    entries.sort_by_key(|(tag, _)| *tag);
+    let data_offset = offset + 2 + entries.len() as u32 * 12 + 4;
+    let mut ifd = (entries.len() as u16).to_le_bytes().to_vec();
+    let mut data = vec![];
+    for (tag, value) in entries.iter(){
+        let (value_type, count, mut bytes) = value.encode();
+        ifd.extend_from_slice(&tag.to_le_bytes());
+        ifd.extend_from_slice(&value_type.to_le_bytes());
+        ifd.extend_from_slice(&count.to_le_bytes());
+        if bytes.len() <= 4{
+            // 4 字节以内的值直接放在条目中，左对齐
+            bytes.resize(4, 0);
+            ifd.extend_from_slice(&bytes);
+        }else{
+            ifd.extend_from_slice(&(data_offset + data.len() as u32).to_le_bytes());
+            data.extend_from_slice(&bytes);
+            // 数据按字对齐
+            if data.len() % 2 == 1{
+                data.push(0);
+            }
+        }
+    }
+    ifd.extend_from_slice(&0u32.to_le_bytes());
+    ifd.extend(data);
+    ifd
+}
+
+/// 约分，结果不超过 u32
+fn reduce(numerator: u64, denominator: u64) -> (u32, u32){
+    let gcd = |mut a: u64, mut b: u64| {
+        while b != 0{
+            (a, b) = (b, a % b);
+        }
+        a.max(1)
+    };
+    let divisor = gcd(numerator, denominator);
+    let (mut numerator, mut denominator) = (numerator / divisor, denominator / divisor);
+    while numerator > u32::MAX as u64 || denominator > u32::MAX as u64{
+        numerator /= 2;
+        denominator = (denominator / 2).max(1);
+    }
+    (numerator as u32, denominator as u32)
+}
+
+/// EXIF 的时间格式 "YYYY:MM:DD HH:MM:SS"
+fn format_datetime(unix_secs: u64) -> String{
+    let (days, secs) = ((unix_secs / 86400) as i64, unix_secs % 86400);
+    // 公历日期，见 http://howardhinnant.github.io/date_algorithms.html civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}:{month:02}:{day:02} {:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// EXIF 的时区格式 "+HH:MM"
+fn format_offset(utc_offset_secs: i32) -> String{
+    let sign = if utc_offset_secs < 0 { '-' } else { '+' };
+    let minutes = utc_offset_secs.unsigned_abs() / 60;
+    format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests{
+    use std::{collections::HashMap, time::Duration};
+    use image::{codecs::jpeg::JpegEncoder, ColorType};
+    use super::*;
+
+    /// IFD 条目: 标签 -> (类型, 个数, 数据)
+    type Ifd = HashMap<u16, (u16, u32, Vec<u8>)>;
+
+    fn test_jpeg() -> Vec<u8>{
+        let rgb: Vec<u8> = (0..16 * 8).flat_map(|i| [i as u8, 128, 255 - i as u8]).collect();
+        let mut jpeg = vec![];
+        JpegEncoder::new_with_quality(&mut jpeg, 90).encode(&rgb, 16, 8, ColorType::Rgb8).unwrap();
+        jpeg
+    }
+
+    fn test_info() -> ExifInfo{
+        ExifInfo {
+            make: "Acme".into(),
+            model: "Phone 1".into(),
+            lens_model: "back camera".into(),
+            software: "test".into(),
+            time: UNIX_EPOCH + Duration::from_millis(1_700_000_000_250),
+            utc_offset_secs: 0,
+            orientation: 6,
+            width: 16,
+            height: 8,
+            exposure: Exposure {
+                exposure_time_ns: Some(10_000_000),
+                iso: Some(200),
+                focal_length: Some(4.25),
+                aperture: Some(1.8),
+            },
+        }
+    }
+
+    /// SOS 之前的段: (marker, 数据)
+    fn segments(jpeg: &[u8]) -> Vec<(u8, &[u8])>{
+        assert_eq!(&jpeg[..2], [0xFF, 0xD8]);
+        let mut segments = vec![];
+        let mut pos = 2;
+        while jpeg[pos + 1] != 0xDA{
+            assert_eq!(jpeg[pos], 0xFF);
+            let length = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+            segments.push((jpeg[pos + 1], &jpeg[pos + 4..pos + 2 + length]));
+            pos += 2 + length;
+        }
+        segments
+    }
+
+    fn exif_segments<'a>(segments: &[(u8, &'a [u8])]) -> Vec<&'a [u8]>{
+        segments.iter().filter(|(marker, data)| *marker == 0xE1 && data.starts_with(b"Exif\0\0")).map(|(_, data)| *data).collect()
+    }
+
+    fn read_ifd(tiff: &[u8], offset: usize) -> Ifd{
+        let u16_at = |pos: usize| u16::from_le_bytes([tiff[pos], tiff[pos + 1]]);
+        let u32_at = |pos: usize| u32::from_le_bytes(tiff[pos..pos + 4].try_into().unwrap());
+        (0..u16_at(offset) as usize)
+            .map(|i| {
+                let entry = offset + 2 + i * 12;
+                let (value_type, count) = (u16_at(entry + 2), u32_at(entry + 4));
+                let size = count as usize * match value_type{ 3 => 2, 4 => 4, 5 => 8, _ => 1 };
+                let pos = if size <= 4 { entry + 8 } else { u32_at(entry + 8) as usize };
+                (u16_at(entry), (value_type, count, tiff[pos..pos + size].to_vec()))
+            })
+            .collect()
+    }
+
+    /// 解析 APP1: (IFD0, Exif IFD)
+    fn parse_app1(app1: &[u8]) -> (Ifd, Ifd){
+        let tiff = &app1[6..];
+        assert_eq!(&tiff[..4], b"II*\0");
+        let ifd0 = read_ifd(tiff, u32::from_le_bytes(tiff[4..8].try_into().unwrap()) as usize);
+        let exif_offset = long(&ifd0, 0x8769);
+        let exif = read_ifd(tiff, exif_offset as usize);
+        (ifd0, exif)
+    }
+
+    fn ascii(ifd: &Ifd, tag: u16) -> &str{
+        let (value_type, _, data) = &ifd[&tag];
+        assert_eq!(*value_type, 2);
+        std::str::from_utf8(data.strip_suffix(&[0]).unwrap()).unwrap()
+    }
+
+    fn short(ifd: &Ifd, tag: u16) -> u16{
+        let (value_type, count, data) = &ifd[&tag];
+        assert_eq!((*value_type, *count), (3, 1));
+        u16::from_le_bytes([data[0], data[1]])
+    }
+
+    fn long(ifd: &Ifd, tag: u16) -> u32{
+        let (value_type, count, data) = &ifd[&tag];
+        assert_eq!((*value_type, *count), (4, 1));
+        u32::from_le_bytes(data[..4].try_into().unwrap())
+    }
+
+    fn rational(ifd: &Ifd, tag: u16) -> (u32, u32){
+        let (value_type, count, data) = &ifd[&tag];
+        assert_eq!((*value_type, *count), (5, 1));
+        (u32::from_le_bytes(data[..4].try_into().unwrap()), u32::from_le_bytes(data[4..8].try_into().unwrap()))
+    }
+
+    #[test]
+    fn round_trip(){
+        let jpeg = test_info().insert_into_jpeg(&test_jpeg()).unwrap();
+        let image = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg).unwrap();
+        assert_eq!((image.width(), image.height()), (16, 8));
+
+        let segments = segments(&jpeg);
+        // EXIF 紧跟在 JFIF 之后
+        assert_eq!(segments[0].0, 0xE0);
+        assert_eq!(exif_segments(&segments), [segments[1].1]);
+        let (ifd0, exif) = parse_app1(segments[1].1);
+
+        assert_eq!(ascii(&ifd0, 0x010F), "Acme");
+        assert_eq!(ascii(&ifd0, 0x0110), "Phone 1");
+        assert_eq!(short(&ifd0, 0x0112), 6);
+        assert_eq!(ascii(&ifd0, 0x0131), "test");
+        assert_eq!(ascii(&ifd0, 0x0132), "2023:11:14 22:13:20");
+
+        assert_eq!(rational(&exif, 0x829A), (1, 100));
+        assert_eq!(rational(&exif, 0x829D), (180, 100));
+        assert_eq!(short(&exif, 0x8827), 200);
+        assert_eq!(ascii(&exif, 0x9003), "2023:11:14 22:13:20");
+        assert_eq!(ascii(&exif, 0x9011), "+00:00");
+        assert_eq!(ascii(&exif, 0x9291), "250");
+        assert_eq!(rational(&exif, 0x920A), (4250, 1000));
+        assert_eq!((long(&exif, 0xA002), long(&exif, 0xA003)), (16, 8));
+        assert_eq!(ascii(&exif, 0xA434), "back camera");
+    }
+
+    #[test]
+    fn local_time_and_offset(){
+        // 时间按当地时间写入，可能跨过日期
+        for (utc_offset_secs, datetime, offset) in [
+            (8 * 3600, "2023:11:15 06:13:20", "+08:00"),
+            (-(5 * 3600 + 30 * 60), "2023:11:14 16:43:20", "-05:30"),
+        ]{
+            let (ifd0, exif) = parse_app1(&ExifInfo { utc_offset_secs, ..test_info() }.to_app1());
+            assert_eq!(ascii(&ifd0, 0x0132), datetime);
+            assert_eq!((ascii(&exif, 0x9003), ascii(&exif, 0x9004)), (datetime, datetime));
+            assert_eq!((ascii(&exif, 0x9010), ascii(&exif, 0x9011)), (offset, offset));
+        }
+    }
+
+    #[test]
+    fn missing_values_are_omitted(){
+        let info = ExifInfo { make: String::new(), exposure: Exposure::default(), ..test_info() };
+        let (ifd0, exif) = parse_app1(&info.to_app1());
+        assert!(!ifd0.contains_key(&0x010F));
+        for tag in [0x829A, 0x829D, 0x8827, 0x920A]{
+            assert!(!exif.contains_key(&tag), "tag {tag:#06x}");
+        }
+        assert_eq!(short(&ifd0, 0x0112), 6);
+    }
+
+    #[test]
+    fn existing_exif_is_replaced(){
+        // 原有的 XMP(也是 APP1)保留
+        let xmp = b"http://ns.adobe.com/xap/1.0/\0<x/>";
+        let mut jpeg = test_jpeg();
+        let app0_end = 4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&(xmp.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(xmp);
+        jpeg.splice(app0_end..app0_end, segment);
+
+        let first = test_info().insert_into_jpeg(&jpeg).unwrap();
+        let second = ExifInfo { orientation: 3, model: "Phone 2".into(), ..test_info() }.insert_into_jpeg(&first).unwrap();
+        assert_eq!(second.len(), first.len());
+
+        let segments = segments(&second);
+        let exif_segments = exif_segments(&segments);
+        assert_eq!(exif_segments.len(), 1);
+        let (ifd0, _) = parse_app1(exif_segments[0]);
+        assert_eq!(short(&ifd0, 0x0112), 3);
+        assert_eq!(ascii(&ifd0, 0x0110), "Phone 2");
+        assert_eq!(segments.iter().filter(|(marker, data)| *marker == 0xE1 && data == xmp).count(), 1);
+        image::load_from_memory_with_format(&second, image::ImageFormat::Jpeg).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use std::borrow::Cow;
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use pollster::FutureExt;
+use super::lut::Lut3d;
+use wgpu::{BindGroupLayout, Buffer, CommandEncoder, ComputePipeline, Device, Queue, Sampler, Texture};
+
+const PRELUDE: &str = include_str!("filters/prelude.wgsl");
+
+/// 一个 GPU 滤镜，在 rgba 纹理上执行的计算着色器
+///
+/// source 前面会自动加上 filters/prelude.wgsl，其中声明了输入、输出纹理和采样器，
+/// 滤镜只需要声明 `@group(0) @binding(3) var<uniform> params : Params;` 和入口函数 main
+pub struct FilterStage {
+    name: String,
+    source: Cow<'static, str>,
+    /// uniform 参数的字节，不足 16 字节时补 0
+    params: Vec<u8>,
+    /// 为 false 时跳过这个滤镜
+    pub enabled: bool,
+    params_dirty: bool,
+    /// 绑定到 lut_texture 的 3D 纹理: (边长, rgba)
+    texture_3d: Option<(u32, Vec<[f32; 4]>)>,
+    texture_dirty: bool,
+    compiled: Option<CompiledStage>,
+}
+
+struct CompiledStage {
+    pipeline: ComputePipeline,
+    params_buffer: Buffer,
+    texture_3d: Texture,
+}
+
+impl FilterStage {
+    pub fn new(name: impl Into<String>, source: impl Into<Cow<'static, str>>, params: &[u8]) -> Self {
+        Self {
+            name: name.into(),
+            source: source.into(),
+            params: params.to_vec(),
+            enabled: true,
+            params_dirty: true,
+            texture_3d: None,
+            texture_dirty: true,
+            compiled: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 修改 uniform 参数，从下一帧开始生效
+    pub fn set_params<T: bytemuck::Pod>(&mut self, params: &[T]) {
+        self.params = bytemuck::cast_slice(params).to_vec();
+        self.params_dirty = true;
+    }
+
+    /// 设置着色器中的 lut_texture，data 为 size^3 个 rgba，x 变化最快
+    pub fn set_texture_3d(&mut self, size: u32, data: Vec<[f32; 4]>) -> Result<()> {
+        if data.len() != (size as usize).pow(3) {
+            return Err(anyhow!("texture_3d size {size} needs {} texels, got {}", (size as usize).pow(3), data.len()));
+        }
+        self.texture_3d = Some((size, data));
+        self.texture_dirty = true;
+        Ok(())
+    }
+
+    /// 3D LUT 调色
+    pub fn lut(lut: &Lut3d) -> Self {
+        let [r0, g0, b0] = lut.domain_min;
+        let [r1, g1, b1] = lut.domain_max;
+        let mut stage = Self::new("lut", include_str!("filters/lut.wgsl"), bytemuck::cast_slice(&[r0, g0, b0, 0., r1, g1, b1, 1.]));
+        stage.texture_3d = Some((lut.size as u32, lut.data.iter().map(|&[r, g, b]| [r, g, b, 1.]).collect()));
+        stage
+    }
+
+    /// brightness 加在 0~1 的颜色上，contrast 为 1 时不变
+    pub fn brightness_contrast(brightness: f32, contrast: f32) -> Self {
+        Self::new("brightness_contrast", include_str!("filters/adjust.wgsl"), bytemuck::cast_slice(&[brightness, contrast, 1., 0.]))
+    }
+
+    /// 灰度
+    pub fn grayscale() -> Self {
+        Self::new("grayscale", include_str!("filters/adjust.wgsl"), bytemuck::cast_slice(&[0f32, 1., 0., 0.]))
+    }
+
+    /// 怀旧，strength 为 0~1
+    pub fn sepia(strength: f32) -> Self {
+        Self::new("sepia", include_str!("filters/sepia.wgsl"), bytemuck::cast_slice(&[strength, 0., 0., 0.]))
+    }
+
+    /// 锐化，amount 一般为 0~2
+    pub fn sharpen(amount: f32) -> Self {
+        Self::new("sharpen", include_str!("filters/sharpen.wgsl"), bytemuck::cast_slice(&[amount, 0., 0., 0.]))
+    }
+
+    /// 高斯模糊，radius 为像素半径
+    pub fn blur(radius: u32) -> Self {
+        Self::new("blur", include_str!("filters/blur.wgsl"), bytemuck::cast_slice(&[radius as i32, 0, 0, 0]))
+    }
+
+    /// 补齐到 16 字节的参数
+    fn padded_params(&self) -> Vec<u8> {
+        let mut params = self.params.clone();
+        params.resize(params.len().max(1).next_multiple_of(16), 0);
+        params
+    }
+
+    fn compile(&mut self, device: &Device, layout: &BindGroupLayout) -> Result<()> {
+        info!("compile filter {}", self.name);
+        // 着色器错误默认会 panic，这里捕获后返回错误
+        let error_scope = device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&self.name),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(format!("{PRELUDE}\n{}", self.source))),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&self.name),
+            bind_group_layouts: &[Some(layout)],
+            immediate_size: 0,
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&self.name),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        if let Some(err) = error_scope.pop().block_on() {
+            return Err(anyhow!("filter {} compile error: {err:?}", self.name));
+        }
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&self.name),
+            size: self.padded_params().len() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let texture_3d = create_texture_3d(device, self.texture_3d.as_ref().map(|(size, _)| *size).unwrap_or(1));
+        self.compiled = Some(CompiledStage { pipeline, params_buffer, texture_3d });
+        self.params_dirty = true;
+        self.texture_dirty = true;
+        Ok(())
+    }
+}
+
+/// 按顺序执行的 GPU 滤镜，在 YuvGpuDecoder 旋转、缩放之后，回读之前执行
+#[derive(Default)]
+pub struct FramePipeline {
+    stages: Vec<FilterStage>,
+    bind_group_layout: Option<BindGroupLayout>,
+    sampler: Option<Sampler>,
+    /// 滤镜之间交替使用的两个纹理
+    targets: Option<(wgpu::Extent3d, [Texture; 2])>,
+}
+
+impl FramePipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加到最后
+    pub fn push(&mut self, stage: FilterStage) -> &mut Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn insert(&mut self, index: usize, stage: FilterStage) {
+        self.stages.insert(index.min(self.stages.len()), stage);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<FilterStage> {
+        let index = self.stages.iter().position(|stage| stage.name == name)?;
+        Some(self.stages.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.stages.clear();
+    }
+
+    pub fn stage_mut(&mut self, name: &str) -> Option<&mut FilterStage> {
+        self.stages.iter_mut().find(|stage| stage.name == name)
+    }
+
+    pub fn stages(&self) -> &[FilterStage] {
+        &self.stages
+    }
+
+    /// 没有启用的滤镜
+    pub fn is_empty(&self) -> bool {
+        !self.stages.iter().any(|stage| stage.enabled)
+    }
+
+    /// 编译新的滤镜、更新参数，并准备 size 大小的中间纹理
+    pub(crate) fn prepare(&mut self, device: &Device, queue: &Queue, size: wgpu::Extent3d) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let layout = self.bind_group_layout.get_or_insert_with(|| create_bind_group_layout(device));
+        for stage in self.stages.iter_mut().filter(|stage| stage.enabled) {
+            let buffer_too_small = stage
+                .compiled
+                .as_ref()
+                .is_some_and(|compiled| compiled.params_buffer.size() < stage.padded_params().len() as u64);
+            if stage.compiled.is_none() || buffer_too_small {
+                // 编译失败时禁用，不会每一帧重新编译、输出同样的错误，修改后重新设置 enabled 再次编译
+                if let Err(err) = stage.compile(device, layout) {
+                    error!("{:?}, filter disabled", err);
+                    stage.enabled = false;
+                    continue;
+                }
+            }
+            let params = stage.padded_params();
+            let compiled = stage.compiled.as_mut().unwrap();
+            if stage.params_dirty {
+                queue.write_buffer(&compiled.params_buffer, 0, &params);
+                stage.params_dirty = false;
+            }
+            if let (true, Some((size, data))) = (stage.texture_dirty, stage.texture_3d.as_ref()) {
+                if compiled.texture_3d.width() != *size {
+                    compiled.texture_3d = create_texture_3d(device, *size);
+                }
+                queue.write_texture(
+                    compiled.texture_3d.as_image_copy(),
+                    bytemuck::cast_slice(data),
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(size * 16),
+                        rows_per_image: Some(*size),
+                    },
+                    compiled.texture_3d.size(),
+                );
+            }
+            stage.texture_dirty = false;
+        }
+        if self.sampler.is_none() {
+            self.sampler = Some(device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }));
+        }
+        if self.targets.as_ref().map(|(target_size, _)| *target_size) != Some(size) {
+            let create_target = || {
+                device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("filter texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC
+                        | wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+            };
+            self.targets = Some((size, [create_target(), create_target()]));
+        }
+        Ok(())
+    }
+
+    /// 启用并且已经编译的阶段
+    fn active_stages(&self) -> impl Iterator<Item = (&FilterStage, &CompiledStage)> {
+        self.stages
+            .iter()
+            .filter(|stage| stage.enabled)
+            .filter_map(|stage| Some((stage, stage.compiled.as_ref()?)))
+    }
+
+    /// encode() 输出的纹理，没有滤镜时就是 input
+    pub(crate) fn output<'a>(&'a self, input: &'a Texture) -> &'a Texture {
+        match (self.targets.as_ref(), self.active_stages().count()) {
+            (Some((_, targets)), count) if count > 0 => &targets[(count - 1) % 2],
+            _ => input,
+        }
+    }
+
+    /// 在 encoder 中依次执行启用的滤镜，返回最后一个滤镜的输出纹理，需要先调用 prepare
+    pub(crate) fn encode<'a>(&'a self, device: &Device, encoder: &mut CommandEncoder, input: &'a Texture) -> &'a Texture {
+        let (Some((size, targets)), Some(layout), Some(sampler)) = (self.targets.as_ref(), self.bind_group_layout.as_ref(), self.sampler.as_ref()) else {
+            return input;
+        };
+        let mut current = input;
+        for (i, (stage, compiled)) in self.active_stages().enumerate() {
+            let output = &targets[i % 2];
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&current.create_view(&wgpu::TextureViewDescriptor::default())),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&output.create_view(&wgpu::TextureViewDescriptor::default())),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: compiled.params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(&compiled.texture_3d.create_view(&wgpu::TextureViewDescriptor::default())),
+                    },
+                ],
+                label: Some(&stage.name),
+            });
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            cpass.set_pipeline(&compiled.pipeline);
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.dispatch_workgroups(size.width.div_ceil(8), size.height.div_ceil(8), 1);
+            drop(cpass);
+            current = output;
+        }
+        current
+    }
+}
+
+fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+        ],
+        label: Some("filter_bind_group_layout"),
+    })
+}
+
+/// Rgba32Float 不能线性过滤，着色器中用 textureLoad 自己插值
+fn create_texture_3d(device: &Device, size: u32) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("filter texture_3d"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
@@ -0,0 +1,21 @@
+// 亮度、对比度、饱和度
+struct Params {
+    brightness : f32,
+    contrast : f32,
+    saturation : f32,
+    _pad : f32,
+}
+@group(0) @binding(3) var<uniform> params : Params;
+
+@compute @workgroup_size(8,8)
+fn main(@builtin(global_invocation_id) global_id : vec3u) {
+    let coords = vec2<i32>(global_id.xy);
+    if (!in_output(coords)) {
+        return;
+    }
+    let pixel = load(coords);
+    var rgb = (pixel.rgb - 0.5) * params.contrast + 0.5 + params.brightness;
+    let luma = dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    rgb = mix(vec3<f32>(luma), rgb, params.saturation);
+    textureStore(output_texture, coords, vec4<f32>(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), pixel.a));
+}
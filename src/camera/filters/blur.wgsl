@@ -0,0 +1,28 @@
+// 高斯模糊，sigma = radius / 2
+struct Params {
+    radius : i32,
+    _pad0 : i32,
+    _pad1 : i32,
+    _pad2 : i32,
+}
+@group(0) @binding(3) var<uniform> params : Params;
+
+@compute @workgroup_size(8,8)
+fn main(@builtin(global_invocation_id) global_id : vec3u) {
+    let coords = vec2<i32>(global_id.xy);
+    if (!in_output(coords)) {
+        return;
+    }
+    let radius = max(params.radius, 0);
+    let sigma = max(f32(radius) * 0.5, 0.5);
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let weight = exp(-f32(x * x + y * y) / (2.0 * sigma * sigma));
+            sum += load(coords + vec2<i32>(x, y)) * weight;
+            total += weight;
+        }
+    }
+    textureStore(output_texture, coords, sum / total);
+}
@@ -0,0 +1,31 @@
+// 3D LUT 调色，lut_texture 中 x、y、z 分别对应 r、g、b
+struct Params {
+    domain_min : vec4<f32>,
+    domain_max : vec4<f32>,
+}
+@group(0) @binding(3) var<uniform> params : Params;
+
+fn lut(index : vec3<i32>) -> vec3<f32> {
+    return textureLoad(lut_texture, index, 0).rgb;
+}
+
+@compute @workgroup_size(8,8)
+fn main(@builtin(global_invocation_id) global_id : vec3u) {
+    let coords = vec2<i32>(global_id.xy);
+    if (!in_output(coords)) {
+        return;
+    }
+    let pixel = load(coords);
+    let size = i32(textureDimensions(lut_texture).x);
+    let max_index = f32(size - 1);
+    let t = clamp((pixel.rgb - params.domain_min.rgb) / (params.domain_max.rgb - params.domain_min.rgb), vec3<f32>(0.0), vec3<f32>(1.0)) * max_index;
+    // 和 Lut3d::sample 一样的三线性插值
+    let i = min(vec3<i32>(floor(t)), vec3<i32>(size - 2));
+    let f = t - vec3<f32>(i);
+    let c00 = mix(lut(i), lut(i + vec3<i32>(1, 0, 0)), f.r);
+    let c10 = mix(lut(i + vec3<i32>(0, 1, 0)), lut(i + vec3<i32>(1, 1, 0)), f.r);
+    let c01 = mix(lut(i + vec3<i32>(0, 0, 1)), lut(i + vec3<i32>(1, 0, 1)), f.r);
+    let c11 = mix(lut(i + vec3<i32>(0, 1, 1)), lut(i + vec3<i32>(1, 1, 1)), f.r);
+    let rgb = mix(mix(c00, c10, f.g), mix(c01, c11, f.g), f.b);
+    textureStore(output_texture, coords, vec4<f32>(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), pixel.a));
+}
@@ -0,0 +1,20 @@
+// FramePipeline 自动加在每个滤镜前面的绑定
+// 滤镜自己声明参数: @group(0) @binding(3) var<uniform> params : Params;
+// 入口函数为 main，workgroup_size 为 (8,8)
+@group(0) @binding(0) var input_texture : texture_2d<f32>;
+@group(0) @binding(1) var input_sampler : sampler;
+@group(0) @binding(2) var output_texture : texture_storage_2d<rgba8unorm, write>;
+// FilterStage::set_texture_3d 设置的 3D 纹理(如 LUT)，没有设置时为 1x1x1
+@group(0) @binding(4) var lut_texture : texture_3d<f32>;
+
+// 读取输入像素，超出范围时取边缘的像素
+fn load(coords : vec2<i32>) -> vec4<f32> {
+    let max_coords = vec2<i32>(textureDimensions(input_texture)) - 1;
+    return textureLoad(input_texture, clamp(coords, vec2<i32>(0), max_coords), 0);
+}
+
+// 是否在输出图像内
+fn in_output(coords : vec2<i32>) -> bool {
+    let dimensions = vec2<i32>(textureDimensions(output_texture));
+    return coords.x < dimensions.x && coords.y < dimensions.y;
+}
@@ -0,0 +1,24 @@
+// 怀旧(棕褐色)
+struct Params {
+    strength : f32,
+    _pad0 : f32,
+    _pad1 : f32,
+    _pad2 : f32,
+}
+@group(0) @binding(3) var<uniform> params : Params;
+
+@compute @workgroup_size(8,8)
+fn main(@builtin(global_invocation_id) global_id : vec3u) {
+    let coords = vec2<i32>(global_id.xy);
+    if (!in_output(coords)) {
+        return;
+    }
+    let pixel = load(coords);
+    let sepia = vec3<f32>(
+        dot(pixel.rgb, vec3<f32>(0.393, 0.769, 0.189)),
+        dot(pixel.rgb, vec3<f32>(0.349, 0.686, 0.168)),
+        dot(pixel.rgb, vec3<f32>(0.272, 0.534, 0.131)),
+    );
+    let rgb = mix(pixel.rgb, min(sepia, vec3<f32>(1.0)), params.strength);
+    textureStore(output_texture, coords, vec4<f32>(rgb, pixel.a));
+}
@@ -0,0 +1,21 @@
+// 锐化: 中心像素减去上下左右的拉普拉斯
+struct Params {
+    amount : f32,
+    _pad0 : f32,
+    _pad1 : f32,
+    _pad2 : f32,
+}
+@group(0) @binding(3) var<uniform> params : Params;
+
+@compute @workgroup_size(8,8)
+fn main(@builtin(global_invocation_id) global_id : vec3u) {
+    let coords = vec2<i32>(global_id.xy);
+    if (!in_output(coords)) {
+        return;
+    }
+    let pixel = load(coords);
+    let neighbors = load(coords + vec2<i32>(-1, 0)) + load(coords + vec2<i32>(1, 0))
+        + load(coords + vec2<i32>(0, -1)) + load(coords + vec2<i32>(0, 1));
+    let rgb = pixel.rgb + (pixel.rgb * 4.0 - neighbors.rgb) * params.amount;
+    textureStore(output_texture, coords, vec4<f32>(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), pixel.a));
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use slint::{Rgba8Pixel, SharedPixelBuffer};
+
+use super::{convert::{self, ColorSpace, Orientation, PixelFormat, Plane}, gpu::GpuFrame};
+
+/// 相机的拍摄结果，后端不提供的项为 None
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Exposure{
+    /// 曝光时间(纳秒)
+    pub exposure_time_ns: Option<i64>,
+    /// 感光度 ISO
+    pub iso: Option<u32>,
+    /// 焦距(毫米)
+    pub focal_length: Option<f32>,
+    /// 光圈 f 值
+    pub aperture: Option<f32>,
+}
+
+/// 相机输出的一帧图像以及采集时的元数据
+#[derive(Debug, Clone)]
+pub struct Frame{
+    pub data: Vec<u8>,
+    pub format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    /// 每个平面一行所占的字节数
+    pub strides: Vec<usize>,
+    /// YUV 数据的色彩空间，RGB 格式忽略
+    pub color_space: ColorSpace,
+    /// 采集时间戳(纳秒)，时间基准由后端决定，同一相机内单调递增
+    pub timestamp_ns: i64,
+    /// 帧序号
+    pub sequence: u64,
+    /// 图像数据相对传感器原始方向已经应用的旋转和镜像
+    pub orientation: Orientation,
+    pub camera_id: String,
+    pub exposure: Exposure,
+    /// 和界面共用 wgpu 设备时 GPU 上的 Rgba8 输出，这时 data 为空，界面线程提交后直接显示纹理
+    pub gpu: Option<GpuFrame>,
+}
+
+impl Frame{
+    /// 创建紧密排列(没有行填充)的帧，元数据使用默认值
+    pub fn new(data: Vec<u8>, format: PixelFormat, width: u32, height: u32) -> Self{
+        Self {
+            data,
+            format,
+            width,
+            height,
+            strides: format.default_strides(width),
+            color_space: ColorSpace::default(),
+            timestamp_ns: 0,
+            sequence: 0,
+            orientation: Orientation::default(),
+            camera_id: String::new(),
+            exposure: Exposure::default(),
+            gpu: None,
+        }
+    }
+
+    /// GPU 上的帧，像素数据留在纹理中
+    pub fn from_gpu(gpu: GpuFrame, width: u32, height: u32) -> Self{
+        Self { gpu: Some(gpu), ..Self::new(vec![], PixelFormat::Rgba8, width, height) }
+    }
+
+    pub fn planes(&self) -> Result<Vec<Plane<'_>>>{
+        convert::planes(&self.data, self.format, self.width, self.height, &self.strides)
+    }
+
+    /// 转换为 Rgba8、Rgb8 或 Gray8
+    pub fn convert(&self, dst_format: PixelFormat) -> Result<Vec<u8>>{
+        convert::convert_buffer(&self.data, self.format, self.color_space, self.width, self.height, &self.strides, dst_format)
+    }
+
+    /// 转换为 slint 显示用的 rgba 缓冲区
+    pub fn to_pixel_buffer(&self) -> Result<SharedPixelBuffer<Rgba8Pixel>>{
+        let mut buffer = SharedPixelBuffer::<Rgba8Pixel>::new(self.width, self.height);
+        convert::convert(&self.planes()?, self.format, self.color_space, self.width, self.height, buffer.make_mut_bytes(), PixelFormat::Rgba8)?;
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,1361 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use anyhow::{anyhow, Result};
+use log::info;
+use pollster::FutureExt;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, Buffer, BufferAsyncError, ComputePipeline, Device, Queue, Sampler,
+    SubmissionIndex, Texture, TextureView,
+};
+
+use super::{
+    convert::{ColorSpace, Orientation, Plane},
+    filter::FramePipeline,
+};
+
+/// 回读缓冲区的数量，submit 之后最多可以有这么多帧没有 read
+pub const READBACK_BUFFERS: usize = 2;
+
+/// 解码一帧各阶段在 CPU 上的耗时
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecodeTimings {
+    /// 打包 uv 并写入纹理
+    pub upload: Duration,
+    /// 记录命令并提交
+    pub submit: Duration,
+    /// read 时等待 GPU 完成并映射缓冲区，流水线正常工作时接近 0
+    pub wait: Duration,
+    /// 从映射的缓冲区复制到输出
+    pub copy: Duration,
+}
+
+/// 缩放时宽高比的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    /// 保持宽高比完整显示在目标大小内，输出的宽或高可能小于目标
+    #[default]
+    Fit,
+    /// 拉伸到目标大小，不保持宽高比
+    Fill,
+    /// 保持宽高比缩放到覆盖目标大小，只保留居中的部分
+    CropCenter,
+}
+
+/// 缩放的采样方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleFilter {
+    /// 双线性插值，适合放大或小幅缩小
+    #[default]
+    Bilinear,
+    /// 按面积平均，适合大幅缩小
+    Area,
+}
+
+/// 解码输出的缩放设置，大小是旋转之后的宽高，通常为窗口大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputScale {
+    pub width: u32,
+    pub height: u32,
+    pub mode: ScaleMode,
+    pub filter: ScaleFilter,
+}
+
+impl OutputScale {
+    pub fn new(width: u32, height: u32, mode: ScaleMode, filter: ScaleFilter) -> Self {
+        Self { width, height, mode, filter }
+    }
+
+    /// 输入大小为 src_width x src_height 时，返回输出的宽高和采样的输入区域 [x, y, width, height]
+    pub fn layout(&self, src_width: u32, src_height: u32) -> ((u32, u32), [f32; 4]) {
+        let (sw, sh) = (src_width as f32, src_height as f32);
+        let (dw, dh) = (self.width as f32, self.height as f32);
+        match self.mode {
+            ScaleMode::Fill => ((self.width, self.height), [0., 0., sw, sh]),
+            ScaleMode::Fit => {
+                let scale = (dw / sw).min(dh / sh);
+                let width = ((sw * scale).round() as u32).clamp(1, self.width);
+                let height = ((sh * scale).round() as u32).clamp(1, self.height);
+                ((width, height), [0., 0., sw, sh])
+            }
+            ScaleMode::CropCenter => {
+                let scale = (dw / sw).max(dh / sh);
+                let (crop_width, crop_height) = ((dw / scale).min(sw), (dh / scale).min(sh));
+                (
+                    (self.width, self.height),
+                    [(sw - crop_width) / 2., (sh - crop_height) / 2., crop_width, crop_height],
+                )
+            }
+        }
+    }
+}
+
+/// 缩放阶段的输出，输入的纹理或缩放设置改变时重新创建
+struct ScaleStage {
+    /// 输入是否为旋转后的纹理
+    source_rotated: bool,
+    texture: Texture,
+    size: wgpu::Extent3d,
+    bind_group: BindGroup,
+}
+
+/// 预先分配的 MAP_READ 缓冲区，按环形依次使用
+struct Readback {
+    buffer: Buffer,
+    /// 复制到缓冲区的图像大小
+    size: wgpu::Extent3d,
+    padded_bytes_per_row: usize,
+    /// 已提交还没有读取时为 Some
+    submission: Option<SubmissionIndex>,
+    /// map_async 的回调结果
+    mapped: Arc<Mutex<Option<Result<(), BufferAsyncError>>>>,
+}
+
+/// wgpu 的实例、adapter、设备和队列
+///
+/// 交给 slint 渲染界面时，同一个设备上解码输出的纹理可以直接显示
+#[derive(Debug, Clone)]
+pub struct GpuContext {
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: Device,
+    pub queue: Queue,
+}
+
+impl GpuContext {
+    /// 在 backends 中优先使用硬件 GPU，没有时使用软件渲染的 fallback adapter
+    ///
+    /// force_fallback_adapter 为 true 时只使用软件渲染的 adapter
+    pub fn new(backends: wgpu::Backends, force_fallback_adapter: bool) -> Result<Self> {
+        info!("create GpuContext instance...");
+        let mut descriptor = wgpu::InstanceDescriptor::new_without_display_handle();
+        descriptor.backends = backends;
+        let instance = wgpu::Instance::new(descriptor);
+
+        info!("create GpuContext adapter...");
+        let request_adapter = |force_fallback_adapter| {
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    force_fallback_adapter,
+                    compatible_surface: None,
+                    apply_limit_buckets: false,
+                })
+                .block_on()
+        };
+        let adapter = match force_fallback_adapter {
+            true => request_adapter(true),
+            false => request_adapter(false).or_else(|_| request_adapter(true)),
+        }
+        .map_err(|err| anyhow!("Couldn't create the adapter: {err}"))?;
+        info!("GpuContext adapter: {:?}", adapter.get_info());
+
+        // 不依赖可选特性，使用低端设备也支持的限制，纹理大小按 adapter 的上限
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+                ..Default::default()
+            })
+            .block_on()?;
+        Ok(Self { instance, adapter, device, queue })
+    }
+}
+
+/// 记录好还没有提交的一帧，texture 为解码器输出的纹理
+///
+/// slint 的渲染器可能直接使用设备底层的队列，和界面共用设备时只能在界面线程调用 submit()
+#[derive(Debug, Clone)]
+pub struct GpuFrame {
+    queue: Queue,
+    commands: Arc<Mutex<Option<wgpu::CommandBuffer>>>,
+    texture: Texture,
+}
+
+impl GpuFrame {
+    /// 提交计算命令并返回输出的纹理，多次调用只提交一次
+    pub fn submit(&self) -> Texture {
+        if let Some(commands) = self.commands.lock().unwrap().take() {
+            self.queue.submit(Some(commands));
+        }
+        self.texture.clone()
+    }
+}
+
+/// 在 GPU 上把 YUV420 转换为 rgba 并旋转、镜像，不依赖窗口，桌面和 android 都可以使用
+pub struct YuvGpuDecoder {
+    device: Device,
+    queue: Queue,
+    width: u32,
+    height: u32,
+    y_texture: Texture,
+    u_texture: Texture,
+    easu_texture: Texture,
+    texture_size: wgpu::Extent3d,
+    u_size: wgpu::Extent3d,
+    compute_pipeline_yuv: ComputePipeline,
+    compute_yuv_bind_group: BindGroup,
+    /// ColorSpace::coefficients()
+    color_params_buffer: Buffer,
+    /// 打包后的 VU 数据
+    uv_buffer: Vec<u8>,
+
+    rgba_texture_view: TextureView,
+    rotate_compute_pipeline: ComputePipeline,
+    rotate_bind_group: Option<BindGroup>,
+    rotate_output_texture: Option<Texture>,
+    rotate_output_size: Option<wgpu::Extent3d>,
+    last_orientation: Orientation,
+
+    scale: Option<OutputScale>,
+    scale_bind_group_layout: BindGroupLayout,
+    scale_bilinear_pipeline: ComputePipeline,
+    scale_area_pipeline: ComputePipeline,
+    scale_sampler: Sampler,
+    scale_stage: Option<ScaleStage>,
+    pipeline: FramePipeline,
+
+    /// 最后一次 submit 输出的大小
+    output_size: wgpu::Extent3d,
+    /// 最后一次输出是否经过了旋转
+    output_rotated: bool,
+
+    readbacks: Vec<Readback>,
+    /// 已提交还没有读取的 readbacks 序号，按提交顺序
+    in_flight: VecDeque<usize>,
+    next_readback: usize,
+    timings: DecodeTimings,
+}
+
+impl YuvGpuDecoder {
+    /// 优先使用硬件 GPU，没有时使用软件渲染的 fallback adapter
+    ///
+    /// android 相机不提供 YUV 的色彩空间，一般使用 ColorSpace::default()
+    pub fn new(width: u32, height: u32, color_space: ColorSpace) -> Result<Self> {
+        Self::with_fallback_adapter(width, height, color_space, false)
+    }
+
+    /// force_fallback_adapter 为 true 时只使用软件渲染的 adapter，用于在没有 GPU 的环境中测试
+    pub fn with_fallback_adapter(width: u32, height: u32, color_space: ColorSpace, force_fallback_adapter: bool) -> Result<Self> {
+        let gpu = GpuContext::new(wgpu::Backends::all(), force_fallback_adapter)?;
+        Self::with_context(&gpu, width, height, color_space)
+    }
+
+    /// 使用已有的设备，例如和 slint 共用的设备，render() 输出的纹理可以直接显示
+    pub fn with_context(gpu: &GpuContext, width: u32, height: u32, color_space: ColorSpace) -> Result<Self> {
+        info!("create YuvGpuDecoder {width}x{height}");
+        if width == 0 || height == 0 {
+            return Err(anyhow!("invalid decoder size {width}x{height}"));
+        }
+        // 界面的设备可能只满足 WebGL2 的限制，没有计算着色器
+        let limits = gpu.device.limits();
+        if limits.max_compute_workgroups_per_dimension == 0 || limits.max_storage_textures_per_shader_stage == 0 {
+            return Err(anyhow!("the device does not support compute shaders"));
+        }
+        if width.max(height) > limits.max_texture_dimension_2d {
+            return Err(anyhow!("decoder size {width}x{height} exceeds the texture limit {}", limits.max_texture_dimension_2d));
+        }
+        let (device, queue) = (gpu.device.clone(), gpu.queue.clone());
+
+        //------------------------------------------------------
+        // 创建 pipeline layout、compute pipeline、bind group layout 和 shader module
+        //------------------------------------------------------
+        info!("create YuvGpuDecoder compute_texture_yuv_bind_group_layout...");
+        let compute_texture_yuv_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(
+                            // SamplerBindingType::Comparison is only for TextureSampleType::Depth
+                            // SamplerBindingType::Filtering if the sample_type of the texture is:
+                            //     TextureSampleType::Float { filterable: true }
+                            // Otherwise you'll get an error.
+                            wgpu::SamplerBindingType::Filtering,
+                        ),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::Rgba8Unorm,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            });
+        info!("create YuvGpuDecoder compute_yuv_pipeline_layout...");
+        let compute_yuv_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[Some(&compute_texture_yuv_bind_group_layout)],
+                immediate_size: 0,
+            });
+        info!("create YuvGpuDecoder compute_pipeline_yuv...");
+        let compute_pipeline_yuv =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("compute_pipeline"),
+                layout: Some(&compute_yuv_pipeline_layout),
+                module: &device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("compute_shader_module"),
+                    source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("yuv2rgb.wgsl"))),
+                }),
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                cache: None,
+            });
+
+        //------------------------------------------------------
+        // 创建纹理、纹理视图、采样器和缓冲区，并设置它们的相关描述符
+        //------------------------------------------------------
+        info!("create YuvGpuDecoder texture_size...");
+        let texture_size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        // 宽高为奇数时色度平面向上取整
+        let u_size = wgpu::Extent3d {
+            width: width.div_ceil(2),
+            height: height.div_ceil(2),
+            depth_or_array_layers: 1,
+        };
+        info!("create YuvGpuDecoder y_texture...");
+        let y_texture = device.create_texture(&wgpu::TextureDescriptor {
+            // All textures are stored as 3D, we represent our 2D texture
+            // by setting depth to 1.
+            size: texture_size,
+            mip_level_count: 1, // We'll talk about this a little later
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Most images are stored using sRGB so we need to reflect that here.
+            format: wgpu::TextureFormat::R8Unorm,
+            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
+            // COPY_DST means that we want to copy data to this texture
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("y_texture"),
+            view_formats: &[],
+        });
+
+        info!("create YuvGpuDecoder u_texture...");
+        let u_texture = device.create_texture(&wgpu::TextureDescriptor {
+            // All textures are stored as 3D, we represent our 2D texture
+            // by setting depth to 1.
+            size: u_size,
+            mip_level_count: 1, // We'll talk about this a little later
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Most images are stored using sRGB so we need to reflect that here.
+            format: wgpu::TextureFormat::Rg8Unorm,
+            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
+            // COPY_DST means that we want to copy data to this texture
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("uv_texture"),
+            view_formats: &[],
+        });
+
+        info!("create YuvGpuDecoder easu_texture...");
+        let easu_texture = device.create_texture(&wgpu::TextureDescriptor {
+            // All textures are stored as 3D, we represent our 2D texture
+            // by setting depth to 1.
+            size: texture_size,
+            mip_level_count: 1, // We'll talk about this a little later
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Most images are stored using sRGB so we need to reflect that here.
+            // format: wgpu::TextureFormat::Rgba8Unorm,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
+            // COPY_DST means that we want to copy data to this texture
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: Some("diffuse_texture"),
+            view_formats: &[],
+        });
+
+        info!("create YuvGpuDecoder y_texture_view...");
+        let y_texture_view = y_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let u_texture_view = u_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let easu_texture_view = easu_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let uv_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let color_params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("color_params"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::cast_slice(&Self::color_params(color_space)),
+        });
+
+        info!("create YuvGpuDecoder compute_yuv_bind_group...");
+        let compute_yuv_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &compute_texture_yuv_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&y_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&u_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&uv_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&easu_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: color_params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("yuv_bind_group2"),
+        });
+
+        // 缓冲区大小同时满足旋转前后的宽高
+        let readback_size = (YuvGpuDecoder::padded_bytes_per_row(width) * height as usize)
+            .max(YuvGpuDecoder::padded_bytes_per_row(height) * width as usize);
+        let readbacks = (0..READBACK_BUFFERS)
+            .map(|_| Readback {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("readback_buffer"),
+                    size: readback_size as u64,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                size: texture_size,
+                padded_bytes_per_row: 0,
+                submission: None,
+                mapped: Arc::new(Mutex::new(None)),
+            })
+            .collect();
+
+        let rotate_compute_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("compute_pipeline"),
+                layout: None,
+                module: &device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("compute_shader_module"),
+                    source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("rotate.wgsl"))),
+                }),
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                cache: None,
+            });
+
+        // 缩放
+        let scale_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba8Unorm,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("scale_bind_group_layout"),
+        });
+        let scale_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("scale_pipeline_layout"),
+            bind_group_layouts: &[Some(&scale_bind_group_layout)],
+            immediate_size: 0,
+        });
+        let scale_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("scale_shader_module"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("scale.wgsl"))),
+        });
+        let create_scale_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&scale_pipeline_layout),
+                module: &scale_module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let scale_bilinear_pipeline = create_scale_pipeline("bilinear");
+        let scale_area_pipeline = create_scale_pipeline("area");
+        let scale_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            device,
+            queue,
+            width,
+            height,
+            y_texture,
+            u_texture,
+            easu_texture,
+            texture_size,
+            u_size,
+            compute_pipeline_yuv,
+            compute_yuv_bind_group,
+            color_params_buffer,
+            uv_buffer: vec![],
+            rotate_compute_pipeline,
+            rgba_texture_view: easu_texture_view,
+            rotate_bind_group: None,
+            rotate_output_texture: None,
+            rotate_output_size: None,
+            last_orientation: Orientation::default(),
+            scale: None,
+            scale_bind_group_layout,
+            scale_bilinear_pipeline,
+            scale_area_pipeline,
+            scale_sampler,
+            scale_stage: None,
+            pipeline: FramePipeline::new(),
+            output_size: texture_size,
+            output_rotated: false,
+            readbacks,
+            in_flight: VecDeque::new(),
+            next_readback: 0,
+            timings: DecodeTimings::default(),
+        })
+    }
+
+    /// 同步解码一帧，等待 GPU 完成后把结果复制到 output
+    ///
+    /// planes 为 Y、U、V 三个平面，已经裁剪为解码器的宽高
+    ///
+    /// output 的大小至少为输出的宽 * 高 * 4，旋转、缩放后的宽高通过 output_size() 获取
+    pub fn decode(&mut self, planes: &[Plane], output: &mut [u8], orientation: Orientation) -> Result<()> {
+        // 先取回之前 submit 的帧，腾出回读缓冲区
+        while self.in_flight.len() >= READBACK_BUFFERS {
+            self.read(output)?;
+        }
+        self.submit(planes, orientation)?;
+        while self.read(output)?.is_some() {}
+        Ok(())
+    }
+
+    /// 上传一帧并提交 GPU 计算，不等待结果，结果按提交顺序通过 read() 取回
+    ///
+    /// 最多同时有 READBACK_BUFFERS 帧没有取回，超出时返回错误
+    pub fn submit(&mut self, planes: &[Plane], orientation: Orientation) -> Result<()> {
+        let t = Instant::now();
+        let index = self.next_readback;
+        if self.readbacks[index].submission.is_some() {
+            return Err(anyhow!("all {READBACK_BUFFERS} readback buffers are in flight, call read() first"));
+        }
+        let mut encoder = self.record(planes, orientation)?;
+        let output_size = self.output_size;
+
+        // 复制到空闲的回读缓冲区，放大时缓冲区可能不够用
+        let readback_size = (Self::padded_bytes_per_row(output_size.width) * output_size.height as usize) as u64;
+        if self.readbacks[index].buffer.size() < readback_size {
+            self.readbacks[index].buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("readback_buffer"),
+                size: readback_size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+        }
+        self.readbacks[index].size = output_size;
+        self.readbacks[index].padded_bytes_per_row = Self::padded_bytes_per_row(output_size.width);
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: self.output_texture(),
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readbacks[index].buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.readbacks[index].padded_bytes_per_row as u32),
+                    rows_per_image: Some(output_size.height),
+                },
+            },
+            output_size,
+        );
+
+        let readback = &mut self.readbacks[index];
+        readback.submission = Some(self.queue.submit(Some(encoder.finish())));
+
+        // 提交后立即请求映射，GPU 完成后回调，下一帧上传时不会阻塞
+        let mapped = readback.mapped.clone();
+        *mapped.lock().unwrap() = None;
+        readback.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            *mapped.lock().unwrap() = Some(result);
+        });
+        let _ = self.device.poll(wgpu::PollType::Poll);
+
+        self.in_flight.push_back(index);
+        self.next_readback = (index + 1) % READBACK_BUFFERS;
+        self.timings.submit = t.elapsed().saturating_sub(self.timings.upload);
+        Ok(())
+    }
+
+    /// 上传一帧并记录计算命令，不提交也不回读，由 GpuFrame::submit() 提交后直接使用输出的纹理
+    ///
+    /// 输出的纹理在帧之间复用，后提交的帧会覆盖之前的结果
+    pub fn render(&mut self, planes: &[Plane], orientation: Orientation) -> Result<GpuFrame> {
+        let t = Instant::now();
+        let encoder = self.record(planes, orientation)?;
+        let frame = GpuFrame {
+            queue: self.queue.clone(),
+            commands: Arc::new(Mutex::new(Some(encoder.finish()))),
+            texture: self.output_texture().clone(),
+        };
+        self.timings.submit = t.elapsed().saturating_sub(self.timings.upload);
+        Ok(frame)
+    }
+
+    /// 最后一次 record() 输出的纹理
+    fn output_texture(&self) -> &Texture {
+        let texture = match (self.scale_stage.as_ref(), self.rotate_output_texture.as_ref()) {
+            (Some(stage), _) if self.scale.is_some() => &stage.texture,
+            (_, Some(texture)) if self.output_rotated => texture,
+            _ => &self.easu_texture,
+        };
+        self.pipeline.output(texture)
+    }
+
+    /// 上传 YUV 数据并记录所有计算 pass，更新 output_size
+    fn record(&mut self, planes: &[Plane], orientation: Orientation) -> Result<wgpu::CommandEncoder> {
+        let t = Instant::now();
+
+        //------------------------------------------------------
+        // YUV数据写入纹理中
+        //------------------------------------------------------
+
+        let (y_plane, u_plane, v_plane) = match planes {
+            [y, u, v, ..] => (y, u, v),
+            _ => return Err(anyhow!("YUV420 needs 3 planes, got {}", planes.len())),
+        };
+        let (width, height) = (self.width as usize, self.height as usize);
+        let (chroma_width, chroma_height) = (self.u_size.width as usize, self.u_size.height as usize);
+        if chroma_width == 0 || chroma_height == 0 {
+            return Err(anyhow!("invalid decoder size {width}x{height}"));
+        }
+        y_plane.check(width, height, 1, "Y")?;
+        u_plane.check(chroma_width, chroma_height, 1, "U")?;
+        v_plane.check(chroma_width, chroma_height, 1, "V")?;
+        if y_plane.pixel_stride != 1 || y_plane.row_stride < width {
+            return Err(anyhow!("unsupported Y plane layout: {:?}", (y_plane.row_stride, y_plane.pixel_stride)));
+        }
+
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.y_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            y_plane.data,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(y_plane.row_stride as u32),
+                rows_per_image: Some(self.height),
+            },
+            self.texture_size,
+        );
+
+        // uv 纹理按 V,U 交错存放(NV21)
+        let is_nv21 = u_plane.pixel_stride == 2
+            && v_plane.pixel_stride == 2
+            && u_plane.row_stride == v_plane.row_stride
+            && v_plane.row_stride >= chroma_width * 2
+            && u_plane.data.as_ptr() == v_plane.data.as_ptr().wrapping_add(1);
+        if is_nv21 {
+            // V 平面的数据比交错的 VU 少最后一个 U，最后一行单独打包
+            let rows = chroma_height - 1;
+            self.write_uv_rows(v_plane.data, v_plane.row_stride, 0, rows);
+            pack_vu(u_plane, v_plane, chroma_width, rows..chroma_height, &mut self.uv_buffer);
+        } else {
+            // I420 或其他布局，在CPU上交错为 VU
+            pack_vu(u_plane, v_plane, chroma_width, 0..chroma_height, &mut self.uv_buffer);
+        }
+        let packed_rows = self.uv_buffer.len() / (chroma_width * 2);
+        self.write_uv_rows(&self.uv_buffer, chroma_width * 2, chroma_height - packed_rows, packed_rows);
+        self.timings.upload = t.elapsed();
+
+        //------------------------------------------------------
+        // 开始新的计算 pass
+        //------------------------------------------------------
+
+        //是否需要旋转或镜像
+        let need_rotate = !orientation.is_identity();
+        if need_rotate && (self.rotate_bind_group.is_none() || self.last_orientation != orientation) {
+            self.rotate_init(orientation);
+        }
+        if let Some(scale) = self.scale {
+            if self.scale_stage.as_ref().map(|stage| stage.source_rotated) != Some(need_rotate) {
+                self.scale_init(scale, need_rotate);
+            }
+        }
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            cpass.set_pipeline(&self.compute_pipeline_yuv);
+            cpass.set_bind_group(0, &self.compute_yuv_bind_group, &[]);
+            cpass.dispatch_workgroups(self.width.div_ceil(8), self.height.div_ceil(8), 1);
+        }
+
+        //rgba图像转换完成之后，直接使用rgba_texture_view再次处理旋转
+        let (output_texture, output_size) = match need_rotate {
+            true => {
+                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+                cpass.set_pipeline(&self.rotate_compute_pipeline);
+                cpass.set_bind_group(0, self.rotate_bind_group.as_ref().unwrap(), &[]);
+                let workgroup_count_x = self.texture_size.width.div_ceil(16);
+                let workgroup_count_y = self.texture_size.height.div_ceil(16);
+                cpass.dispatch_workgroups(workgroup_count_x, workgroup_count_y, 1);
+                (self.rotate_output_texture.as_ref().unwrap(), self.rotate_output_size.unwrap())
+            }
+            false => (&self.easu_texture, self.texture_size),
+        };
+
+        //缩放到需要的大小，减少回读的数据量
+        let (output_texture, output_size) = match (self.scale, self.scale_stage.as_ref()) {
+            (Some(scale), Some(stage)) => {
+                let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+                cpass.set_pipeline(match scale.filter {
+                    ScaleFilter::Bilinear => &self.scale_bilinear_pipeline,
+                    ScaleFilter::Area => &self.scale_area_pipeline,
+                });
+                cpass.set_bind_group(0, &stage.bind_group, &[]);
+                cpass.dispatch_workgroups(stage.size.width.div_ceil(8), stage.size.height.div_ceil(8), 1);
+                (&stage.texture, stage.size)
+            }
+            _ => (output_texture, output_size),
+        };
+
+        //自定义滤镜
+        self.pipeline.prepare(&self.device, &self.queue, output_size)?;
+        self.pipeline.encode(&self.device, &mut encoder, output_texture);
+        self.output_size = output_size;
+        self.output_rotated = need_rotate;
+        Ok(encoder)
+    }
+
+    /// 取回最早 submit 的一帧，返回输出的宽高，没有未取回的帧时返回 None
+    ///
+    /// 如果 GPU 还没有完成会阻塞等待
+    pub fn read(&mut self, output: &mut [u8]) -> Result<Option<(u32, u32)>> {
+        let index = match self.in_flight.pop_front() {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let readback = &mut self.readbacks[index];
+        let submission = readback.submission.take().unwrap();
+        let (width, height) = (readback.size.width, readback.size.height);
+        let unpadded_bytes_per_row = width as usize * 4;
+
+        let t = Instant::now();
+        if readback.mapped.lock().unwrap().is_none() {
+            self.device
+                .poll(wgpu::PollType::Wait { submission_index: Some(submission), timeout: None })
+                .map_err(|err| anyhow!("wait for GPU failed: {err}"))?;
+        }
+        let mapped = readback.mapped.lock().unwrap().take();
+        self.timings.wait = t.elapsed();
+        match mapped {
+            Some(Ok(())) => (),
+            Some(Err(err)) => return Err(anyhow!("map readback buffer failed: {:?}", err)),
+            None => return Err(anyhow!("readback buffer is not mapped")),
+        }
+
+        let t = Instant::now();
+        let result = if output.len() < unpadded_bytes_per_row * height as usize {
+            Err(anyhow!("output buffer too small: {} < {}", output.len(), unpadded_bytes_per_row * height as usize))
+        } else {
+            let padded_bytes_per_row = readback.padded_bytes_per_row;
+            readback
+                .buffer
+                .slice(..)
+                .get_mapped_range()
+                .map_err(|err| anyhow!("get mapped range failed: {:?}", err))
+                .map(|padded_data| {
+                    for (padded, pixels) in padded_data
+                        .chunks_exact(padded_bytes_per_row)
+                        .zip(output.chunks_exact_mut(unpadded_bytes_per_row))
+                        .take(height as usize)
+                    {
+                        pixels.copy_from_slice(&padded[..unpadded_bytes_per_row]);
+                    }
+                    Some((width, height))
+                })
+        };
+        readback.buffer.unmap();
+        self.timings.copy = t.elapsed();
+        result
+    }
+
+    /// 已经 submit 还没有 read 的帧数
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// 最近一帧各阶段的耗时
+    pub fn timings(&self) -> DecodeTimings {
+        self.timings
+    }
+
+    /// 输入的宽高
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// 最后一次 submit 输出的宽高
+    pub fn output_size(&self) -> (u32, u32) {
+        (self.output_size.width, self.output_size.height)
+    }
+
+    /// 设置输出的缩放，None 时输出原始大小，从下一帧开始生效
+    pub fn set_output_scale(&mut self, scale: Option<OutputScale>) -> Result<()> {
+        if let Some(scale) = scale.as_ref() {
+            if scale.width == 0 || scale.height == 0 {
+                return Err(anyhow!("invalid output size {}x{}", scale.width, scale.height));
+            }
+        }
+        if self.scale != scale {
+            self.scale = scale;
+            self.scale_stage = None;
+        }
+        Ok(())
+    }
+
+    pub fn output_scale(&self) -> Option<OutputScale> {
+        self.scale
+    }
+
+    /// 在缩放之后、回读之前执行的滤镜
+    pub fn pipeline_mut(&mut self) -> &mut FramePipeline {
+        &mut self.pipeline
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: FramePipeline) {
+        self.pipeline = pipeline;
+    }
+
+    /// 切换 YUV 的色彩空间，从下一帧开始生效
+    pub fn set_color_space(&self, color_space: ColorSpace) {
+        self.queue.write_buffer(&self.color_params_buffer, 0, bytemuck::cast_slice(&Self::color_params(color_space)));
+    }
+
+    /// 着色器中的 ColorParams，补齐到 32 字节
+    fn color_params(color_space: ColorSpace) -> [f32; 8] {
+        let [y_offset, y_gain, r_v, g_u, g_v, b_u] = color_space.coefficients();
+        [y_offset, y_gain, r_v, g_u, g_v, b_u, 0., 0.]
+    }
+
+    /// 写入 uv 纹理从 first_row 开始的 rows 行
+    fn write_uv_rows(&self, data: &[u8], bytes_per_row: usize, first_row: usize, rows: usize) {
+        if rows == 0 {
+            return;
+        }
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.u_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: first_row as u32, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row as u32),
+                rows_per_image: Some(rows as u32),
+            },
+            wgpu::Extent3d {
+                width: self.u_size.width,
+                height: rows as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    fn rotate_init(&mut self, orientation: Orientation) {
+        self.last_orientation = orientation;
+        //创建旋转缓冲区
+        let (rotate_output_width, rotate_output_height) = orientation.output_size(self.width, self.height);
+        let rotate_output_size = wgpu::Extent3d {
+            width: rotate_output_width,
+            height: rotate_output_height,
+            depth_or_array_layers: 1,
+        };
+
+        // 输出图像
+        let rotate_output_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("output texture"),
+            size: rotate_output_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        // 变换矩阵
+        let config_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            usage: wgpu::BufferUsages::STORAGE,
+            contents: bytemuck::cast_slice(&orientation.matrix(self.width, self.height)),
+        });
+
+        let rotate_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.rotate_compute_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.rgba_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &rotate_output_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: config_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bind_group"),
+        });
+
+        self.rotate_bind_group = Some(rotate_bind_group);
+        self.rotate_output_texture = Some(rotate_output_texture);
+        self.rotate_output_size = Some(rotate_output_size);
+        // 旋转后的纹理变了，缩放阶段需要重新创建
+        self.scale_stage = None;
+    }
+
+    fn scale_init(&mut self, scale: OutputScale, source_rotated: bool) {
+        let (input_view, input_size) = match (source_rotated, self.rotate_output_texture.as_ref()) {
+            (true, Some(texture)) => (
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                self.rotate_output_size.unwrap(),
+            ),
+            _ => (
+                self.easu_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                self.texture_size,
+            ),
+        };
+        let ((width, height), [x, y, src_width, src_height]) = scale.layout(input_size.width, input_size.height);
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        info!("scale {}x{} => {width}x{height} {:?}", input_size.width, input_size.height, scale);
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("scale output texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let params_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("scale_params"),
+            usage: wgpu::BufferUsages::UNIFORM,
+            contents: bytemuck::cast_slice(&[x, y, src_width, src_height]),
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.scale_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&input_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.scale_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("scale_bind_group"),
+        });
+
+        self.scale_stage = Some(ScaleStage {
+            source_rotated,
+            texture,
+            size,
+            bind_group,
+        });
+    }
+
+    /// Compute the next multiple of 256 for texture retrieval padding.
+    pub fn padded_bytes_per_row(width: u32) -> usize {
+        let bytes_per_row = width as usize * 4;
+        let padding = (256 - bytes_per_row % 256) % 256;
+        bytes_per_row + padding
+    }
+}
+
+/// 把 U、V 平面的 rows 行交错为 V,U,V,U...
+fn pack_vu(u: &Plane, v: &Plane, width: usize, rows: std::ops::Range<usize>, out: &mut Vec<u8>) {
+    out.clear();
+    for row in rows {
+        let (u_row, v_row) = (&u.data[row * u.row_stride..], &v.data[row * v.row_stride..]);
+        for x in 0..width {
+            out.push(v_row[x * v.pixel_stride]);
+            out.push(u_row[x * u.pixel_stride]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{convert::{self, PixelFormat}, FilterStage, Lut3d};
+
+    /// 软件渲染的 decoder，没有可用的 adapter 时返回 None，测试跳过
+    fn fallback_decoder(width: u32, height: u32) -> Option<YuvGpuDecoder> {
+        match YuvGpuDecoder::with_fallback_adapter(width, height, ColorSpace::default(), true) {
+            Ok(decoder) => Some(decoder),
+            Err(err) => {
+                eprintln!("skip: no fallback adapter: {err:?}");
+                None
+            }
+        }
+    }
+
+    /// NV21 测试图像: 亮度为包含黑电平以下和白电平以上的渐变，chroma 返回色度平面 (x, y) 处的 (u, v)
+    fn nv21_image(width: u32, height: u32, chroma: impl Fn(usize, usize) -> (u8, u8)) -> Vec<u8> {
+        let (w, h) = (width as usize, height as usize);
+        let mut data: Vec<u8> = (0..w * h).map(|i| ((i % w) * 255 / w.saturating_sub(1).max(1) + i / w) as u8).collect();
+        for y in 0..h.div_ceil(2) {
+            for x in 0..w.div_ceil(2) {
+                let (u, v) = chroma(x, y);
+                data.extend_from_slice(&[v, u]);
+            }
+        }
+        data
+    }
+
+    /// 色度平滑变化的测试图像，相邻的色度采样最多相差 1
+    fn smooth_nv21(width: u32, height: u32) -> Vec<u8> {
+        let triangle = |v: usize| (v % 256).min(255 - v % 256) as u8;
+        nv21_image(width, height, |x, y| (192 - triangle(y), 64 + triangle(x)))
+    }
+
+    /// 解码一帧 NV21
+    fn gpu_rgba(decoder: &mut YuvGpuDecoder, nv21: &[u8], orientation: Orientation) -> Vec<u8> {
+        let (width, height) = decoder.size();
+        let planes = convert::planes(nv21, PixelFormat::Nv21, width, height, &[]).unwrap();
+        let mut rgba = vec![0; (width * height * 4) as usize];
+        decoder.decode(&planes, &mut rgba, orientation).unwrap();
+        rgba
+    }
+
+    /// CPU 参考实现的输出
+    fn cpu_rgba(nv21: &[u8], width: u32, height: u32) -> Vec<u8> {
+        convert::convert_buffer(nv21, PixelFormat::Nv21, ColorSpace::default(), width, height, &[], PixelFormat::Rgba8).unwrap()
+    }
+
+    /// 最大误差和平均误差
+    fn diff(a: &[u8], b: &[u8]) -> (u8, f64) {
+        assert_eq!(a.len(), b.len());
+        let max = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+        let sum: u64 = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b) as u64).sum();
+        (max, sum as f64 / a.len() as f64)
+    }
+
+    #[test]
+    fn golden_nv21() {
+        for (width, height) in [(64, 48), (320, 240)] {
+            let Some(mut decoder) = fallback_decoder(width, height) else { return };
+            let nv21 = smooth_nv21(width, height);
+            let rgba = gpu_rgba(&mut decoder, &nv21, Orientation::default());
+            // GPU 对色度做双线性插值，CPU 取最近的色度，平滑的色度下误差很小
+            let (max, mean) = diff(&rgba, &cpu_rgba(&nv21, width, height));
+            assert!(max <= 4 && mean < 1., "{width}x{height}: max {max}, mean {mean}");
+            assert!(rgba.chunks_exact(4).all(|pixel| pixel[3] == 255));
+        }
+    }
+
+    #[test]
+    fn golden_solid_colors() {
+        let (width, height) = (32, 16);
+        let Some(mut decoder) = fallback_decoder(width, height) else { return };
+        // 灰、红、绿、蓝、黄的色度，色度一致时只有浮点和定点计算的取整误差
+        for (u, v) in [(128, 128), (90, 240), (54, 34), (240, 110), (16, 146)] {
+            let nv21 = nv21_image(width, height, |_, _| (u, v));
+            let (max, _) = diff(&gpu_rgba(&mut decoder, &nv21, Orientation::default()), &cpu_rgba(&nv21, width, height));
+            assert!(max <= 1, "u {u} v {v}: max {max}");
+        }
+    }
+
+    #[test]
+    fn arbitrary_sizes() {
+        // 宽高不是 8 的倍数，也可能是奇数
+        for (width, height) in [(1279, 719), (176, 144), (17, 9)] {
+            let Some(mut decoder) = fallback_decoder(width, height) else { return };
+            let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+            assert_eq!((decoder.u_size.width, decoder.u_size.height), (chroma_width, chroma_height));
+
+            let nv21 = smooth_nv21(width, height);
+            assert_eq!(nv21.len(), PixelFormat::Nv21.frame_size(width, height));
+            let planes = convert::planes(&nv21, PixelFormat::Nv21, width, height, &[]).unwrap();
+            // 预先填充 0，没有转换的像素 alpha 为 0
+            let mut rgba = vec![0; (width * height * 4) as usize];
+            decoder.decode(&planes, &mut rgba, Orientation::default()).unwrap();
+            assert_eq!(decoder.output_size(), (width, height));
+            assert!(rgba.chunks_exact(4).all(|pixel| pixel[3] == 255), "{width}x{height}: unconverted pixels");
+
+            let expected = cpu_rgba(&nv21, width, height);
+            let (max, mean) = diff(&rgba, &expected);
+            assert!(max <= 4 && mean < 1., "{width}x{height}: max {max}, mean {mean}");
+            // 最后一列和最后一行使用最后一个色度采样
+            let (w, h) = (width as usize, height as usize);
+            let last_column: Vec<usize> = (0..h).map(|y| (y * w + w - 1) * 4).collect();
+            let last_row: Vec<usize> = (0..w).map(|x| ((h - 1) * w + x) * 4).collect();
+            for i in last_column.into_iter().chain(last_row) {
+                let (max, _) = diff(&rgba[i..i + 4], &expected[i..i + 4]);
+                assert!(max <= 4, "{width}x{height}: edge pixel {} differs by {max}", i / 4);
+            }
+        }
+    }
+
+    #[test]
+    fn orientation_matches_cpu() {
+        let (width, height) = (7, 5);
+        let Some(mut decoder) = fallback_decoder(width, height) else { return };
+        // 每个像素的亮度都不同，色度中性
+        let mut nv21: Vec<u8> = (0..width * height).map(|i| (16 + i * 6) as u8).collect();
+        nv21.resize(PixelFormat::Nv21.frame_size(width, height), 128);
+        let upright = gpu_rgba(&mut decoder, &nv21, Orientation::default());
+        for rotation in [0, 90, 180, 270] {
+            for mirror in [false, true] {
+                let orientation = Orientation::new(rotation, mirror);
+                let mut expected = vec![];
+                let size = convert::transform_rgba(&upright, width, height, orientation, &mut expected);
+                let rgba = gpu_rgba(&mut decoder, &nv21, orientation);
+                assert_eq!(decoder.output_size(), size, "{orientation:?}");
+                assert!(rgba == expected, "{orientation:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn lut_filter_matches_cpu() {
+        let (width, height) = (32, 16);
+        let Some(mut decoder) = fallback_decoder(width, height) else { return };
+        let nv21 = smooth_nv21(width, height);
+        let upright = gpu_rgba(&mut decoder, &nv21, Orientation::default());
+        // 非线性的 5x5x5 LUT，domain 不是 0~1
+        let size = 5;
+        let max = (size - 1) as f32;
+        let data = (0..size * size * size)
+            .map(|i| {
+                let (r, g, b) = ((i % size) as f32 / max, (i / size % size) as f32 / max, (i / size / size) as f32 / max);
+                [r * g, 1. - b, r * r]
+            })
+            .collect();
+        let lut = Lut3d { title: String::new(), size, domain_min: [0.; 3], domain_max: [0.9, 1., 1.1], data };
+        let expected: Vec<u8> = upright
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let rgb = lut.sample([pixel[0] as f32 / 255., pixel[1] as f32 / 255., pixel[2] as f32 / 255.]);
+                let [r, g, b] = rgb.map(|value| (value * 255. + 0.5).clamp(0., 255.) as u8);
+                [r, g, b, pixel[3]]
+            })
+            .collect();
+
+        decoder.pipeline_mut().push(FilterStage::lut(&lut));
+        let rgba = gpu_rgba(&mut decoder, &nv21, Orientation::default());
+        let (max, _) = diff(&rgba, &expected);
+        assert!(max <= 1, "max {max}");
+    }
+
+    #[test]
+    fn broken_filter_is_disabled() {
+        let (width, height) = (16, 8);
+        let Some(mut decoder) = fallback_decoder(width, height) else { return };
+        decoder.pipeline_mut().push(FilterStage::new("broken", "this is not wgsl", &[])).push(FilterStage::grayscale());
+        let nv21 = nv21_image(width, height, |_, _| (90, 240));
+        for _ in 0..2 {
+            let rgba = gpu_rgba(&mut decoder, &nv21, Orientation::default());
+            // 编译失败的滤镜被跳过，后面的滤镜照常执行
+            assert!(rgba.chunks_exact(4).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]));
+            let stages = decoder.pipeline_mut().stages();
+            assert!(!stages[0].enabled && stages[1].enabled);
+        }
+    }
+
+    #[test]
+    fn output_scale_layout() {
+        use ScaleMode::*;
+        // (输入大小, 目标大小, 模式, 输出大小, 采样区域)
+        let cases = [
+            // 宽高比相同
+            ((1280, 720), (1920, 1080), Fit, (1920, 1080), [0., 0., 1280., 720.]),
+            // 目标比输入宽
+            ((1280, 720), (800, 200), Fit, (356, 200), [0., 0., 1280., 720.]),
+            ((1280, 720), (800, 200), Fill, (800, 200), [0., 0., 1280., 720.]),
+            ((1280, 720), (800, 200), CropCenter, (800, 200), [0., 200., 1280., 320.]),
+            // 目标比输入窄
+            ((1280, 720), (300, 600), Fit, (300, 169), [0., 0., 1280., 720.]),
+            ((1280, 720), (300, 600), Fill, (300, 600), [0., 0., 1280., 720.]),
+            ((1280, 720), (300, 600), CropCenter, (300, 600), [460., 0., 360., 720.]),
+            // 奇数大小
+            ((1280, 720), (101, 57), Fit, (101, 57), [0., 0., 1280., 720.]),
+            ((641, 481), (100, 100), Fit, (100, 75), [0., 0., 641., 481.]),
+            ((641, 481), (100, 100), CropCenter, (100, 100), [80., 0., 481., 481.]),
+            // 缩放后不足一个像素的边保留 1 个像素
+            ((1, 1000), (10, 10), Fit, (1, 10), [0., 0., 1., 1000.]),
+        ];
+        for (src, dst, mode, size, rect) in cases {
+            let scale = OutputScale::new(dst.0, dst.1, mode, ScaleFilter::default());
+            let (out_size, out_rect) = scale.layout(src.0, src.1);
+            let case = format!("{src:?} -> {dst:?} {mode:?}");
+            assert_eq!(out_size, size, "{case}");
+            assert!(out_rect.iter().zip(rect).all(|(a, b)| (a - b).abs() < 1e-3), "{case}: {out_rect:?}");
+        }
+    }
+
+    #[test]
+    fn pack_vu_layouts() {
+        // 5x3 的色度，U = 10*y + x，V = 100 + 10*y + x，行尾有填充
+        let u_rows: Vec<Vec<u8>> = (0..3).map(|y| (0..5).map(|x| 10 * y + x).collect()).collect();
+        let v_rows: Vec<Vec<u8>> = u_rows.iter().map(|row| row.iter().map(|u| u + 100).collect()).collect();
+        let expected = |rows: std::ops::Range<usize>| -> Vec<u8> {
+            rows.flat_map(|y| (0..5).flat_map(move |x| [100 + 10 * y as u8 + x, 10 * y as u8 + x])).collect()
+        };
+
+        // I420: 独立的平面，pixel_stride 1，row_stride 8
+        let planar = |rows: &[Vec<u8>]| -> Vec<u8> { rows.iter().flat_map(|row| [row.clone(), vec![0xEE; 3]].concat()).collect() };
+        let (u, v) = (planar(&u_rows), planar(&v_rows));
+        let (u, v) = (Plane::new(&u, 8, 1), Plane::new(&v, 8, 1));
+        let mut out = vec![];
+        pack_vu(&u, &v, 5, 0..3, &mut out);
+        assert_eq!(out, expected(0..3));
+        pack_vu(&u, &v, 5, 1..3, &mut out);
+        assert_eq!(out, expected(1..3));
+
+        // NV12/NV21: 交错存放，pixel_stride 2，row_stride 12
+        for format in [PixelFormat::Nv12, PixelFormat::Nv21] {
+            let mut data = vec![0xEE; 6 * 16];
+            for y in 0..3 {
+                for x in 0..5 {
+                    let (u, v) = (u_rows[y][x], v_rows[y][x]);
+                    let pair = if format == PixelFormat::Nv12 { [u, v] } else { [v, u] };
+                    data.extend_from_slice(&pair);
+                }
+                data.extend_from_slice(&[0xEE; 2]);
+            }
+            let planes = convert::planes(&data, format, 10, 6, &[16, 12]).unwrap();
+            pack_vu(&planes[1], &planes[2], 5, 0..3, &mut out);
+            assert_eq!(out, expected(0..3), "{format:?}");
+        }
+    }
+}
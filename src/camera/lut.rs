@@ -0,0 +1,210 @@
+use std::{path::Path, thread};
+use anyhow::{anyhow, Result};
+
+/// .cube 格式(Adobe、DaVinci Resolve)的 3D LUT
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    pub title: String,
+    /// 每个维度的格点数
+    pub size: usize,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    /// size^3 个 rgb，r 变化最快，然后是 g、b
+    pub data: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| anyhow!("read {path:?} failed: {err}"))?;
+        Self::parse(&text).map_err(|err| anyhow!("{path:?}: {err}"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lut = Lut3d {
+            title: String::new(),
+            size: 0,
+            domain_min: [0.; 3],
+            domain_max: [1.; 3],
+            data: vec![],
+        };
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |msg: &str| anyhow!("line {}: {msg}: {line}", line_number + 1);
+            let (keyword, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            match keyword {
+                "TITLE" => lut.title = value.trim_matches('"').to_string(),
+                "LUT_3D_SIZE" => {
+                    lut.size = value.parse().map_err(|_| error("invalid size"))?;
+                    if !(2..=256).contains(&lut.size) {
+                        return Err(error("size must be 2~256"));
+                    }
+                    lut.data.reserve(lut.size.pow(3));
+                }
+                "LUT_1D_SIZE" => return Err(error("1D LUT is not supported")),
+                "DOMAIN_MIN" => lut.domain_min = parse_rgb(value).ok_or(error("invalid DOMAIN_MIN"))?,
+                "DOMAIN_MAX" => lut.domain_max = parse_rgb(value).ok_or(error("invalid DOMAIN_MAX"))?,
+                // Resolve 的写法: LUT_3D_INPUT_RANGE min max
+                "LUT_3D_INPUT_RANGE" => {
+                    let (min, max) = value
+                        .split_once(char::is_whitespace)
+                        .and_then(|(min, max)| Some((min.trim().parse().ok()?, max.trim().parse().ok()?)))
+                        .ok_or(error("invalid LUT_3D_INPUT_RANGE"))?;
+                    lut.domain_min = [min; 3];
+                    lut.domain_max = [max; 3];
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    if lut.size == 0 {
+                        return Err(error("data before LUT_3D_SIZE"));
+                    }
+                    lut.data.push(parse_rgb(line).ok_or(error("invalid rgb"))?);
+                }
+                // 其他关键字忽略
+                _ => (),
+            }
+        }
+        if lut.size == 0 {
+            return Err(anyhow!("missing LUT_3D_SIZE"));
+        }
+        if lut.data.len() != lut.size.pow(3) {
+            return Err(anyhow!("expect {} entries, got {}", lut.size.pow(3), lut.data.len()));
+        }
+        if (0..3).any(|i| lut.domain_max[i] <= lut.domain_min[i]) {
+            return Err(anyhow!("invalid domain {:?} {:?}", lut.domain_min, lut.domain_max));
+        }
+        Ok(lut)
+    }
+
+    /// 不改变颜色的 LUT
+    pub fn identity(size: usize) -> Self {
+        let size = size.clamp(2, 256);
+        let max = (size - 1) as f32;
+        let data = (0..size.pow(3))
+            .map(|i| [(i % size) as f32 / max, (i / size % size) as f32 / max, (i / size / size) as f32 / max])
+            .collect();
+        Lut3d {
+            title: String::from("identity"),
+            size,
+            domain_min: [0.; 3],
+            domain_max: [1.; 3],
+            data,
+        }
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.data[r + g * self.size + b * self.size * self.size]
+    }
+
+    /// 三线性插值，输入输出为 0~1 的 rgb
+    pub fn sample(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let mut index = [0; 3];
+        let mut fraction = [0.; 3];
+        for i in 0..3 {
+            (index[i], fraction[i]) = self.axis(i, rgb[i]);
+        }
+        self.interpolate(index, fraction)
+    }
+
+    /// 第 axis 个通道的值对应的格点和插值系数
+    fn axis(&self, axis: usize, value: f32) -> (usize, f32) {
+        let max = (self.size - 1) as f32;
+        let t = ((value - self.domain_min[axis]) / (self.domain_max[axis] - self.domain_min[axis])).clamp(0., 1.) * max;
+        let index = (t.floor() as usize).min(self.size - 2);
+        (index, t - index as f32)
+    }
+
+    fn interpolate(&self, [r, g, b]: [usize; 3], [fr, fg, fb]: [f32; 3]) -> [f32; 3] {
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t];
+        let c00 = lerp(self.entry(r, g, b), self.entry(r + 1, g, b), fr);
+        let c10 = lerp(self.entry(r, g + 1, b), self.entry(r + 1, g + 1, b), fr);
+        let c01 = lerp(self.entry(r, g, b + 1), self.entry(r + 1, g, b + 1), fr);
+        let c11 = lerp(self.entry(r, g + 1, b + 1), self.entry(r + 1, g + 1, b + 1), fr);
+        lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb)
+    }
+
+    /// 在 CPU 上处理 rgba 图像，没有 GPU 时使用，分给多个线程处理
+    pub fn apply_rgba(&self, rgba: &mut [u8]) {
+        // 每个通道 256 个值的格点和系数预先算好
+        let axes: Vec<Vec<(usize, f32)>> = (0..3)
+            .map(|axis| (0..256).map(|value| self.axis(axis, value as f32 / 255.)).collect())
+            .collect();
+        let apply = |pixels: &mut [u8]| {
+            for pixel in pixels.chunks_exact_mut(4) {
+                let (r, g, b) = (axes[0][pixel[0] as usize], axes[1][pixel[1] as usize], axes[2][pixel[2] as usize]);
+                let rgb = self.interpolate([r.0, g.0, b.0], [r.1, g.1, b.1]);
+                for (dst, value) in pixel.iter_mut().zip(rgb) {
+                    *dst = (value * 255. + 0.5).clamp(0., 255.) as u8;
+                }
+            }
+        };
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let chunk_size = (rgba.len() / 4).div_ceil(threads).max(1) * 4;
+        if threads <= 1 || chunk_size >= rgba.len() {
+            apply(rgba);
+            return;
+        }
+        thread::scope(|scope| {
+            for chunk in rgba.chunks_mut(chunk_size) {
+                scope.spawn(|| apply(chunk));
+            }
+        });
+    }
+}
+
+fn parse_rgb(value: &str) -> Option<[f32; 3]> {
+    let mut values = value.split_whitespace().map(|v| v.parse::<f32>());
+    let rgb = [values.next()?.ok()?, values.next()?.ok()?, values.next()?.ok()?];
+    values.next().is_none().then_some(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2x2x2 的 .cube 数据，r 变化最快
+    const ENTRIES: &str = "0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+    #[test]
+    fn parse_keywords() {
+        let text = format!("# comment\nTITLE \"Warm look\"\nLUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 4\n\n{ENTRIES}");
+        let lut = Lut3d::parse(&text).unwrap();
+        assert_eq!(lut.title, "Warm look");
+        assert_eq!(lut.size, 2);
+        assert_eq!((lut.domain_min, lut.domain_max), ([0.; 3], [2., 2., 4.]));
+        assert_eq!(lut.data.len(), 8);
+        assert_eq!((lut.data[1], lut.data[2], lut.data[4]), ([1., 0., 0.], [0., 1., 0.], [0., 0., 1.]));
+        // 输入按 domain 归一化
+        assert_eq!(lut.sample([1., 2., 1.]), [0.5, 1., 0.25]);
+
+        let lut = Lut3d::parse(&format!("LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0.5 1.5\n{ENTRIES}")).unwrap();
+        assert_eq!((lut.domain_min, lut.domain_max), ([0.5; 3], [1.5; 3]));
+        assert_eq!(lut.title, "");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| Lut3d::parse(text).unwrap_err().to_string();
+        assert!(error(&format!("{ENTRIES}LUT_3D_SIZE 2\n")).contains("data before LUT_3D_SIZE"));
+        let missing = ENTRIES.lines().skip(1).collect::<Vec<_>>().join("\n");
+        assert_eq!(error(&format!("LUT_3D_SIZE 2\n{missing}")), "expect 8 entries, got 7");
+        assert!(error(&format!("LUT_3D_SIZE 2\n{ENTRIES}1 1 1\n")).contains("got 9"));
+        assert!(error("LUT_1D_SIZE 1024\n0 0 0\n").contains("1D LUT is not supported"));
+        assert!(error("LUT_3D_SIZE 1\n0 0 0\n").contains("size must be 2~256"));
+        assert!(error(&format!("LUT_3D_SIZE 2\nDOMAIN_MIN 1 1 1\nDOMAIN_MAX 1 1 1\n{ENTRIES}")).contains("invalid domain"));
+    }
+
+    #[test]
+    fn identity_keeps_pixels() {
+        let lut = Lut3d::identity(17);
+        assert_eq!(lut.sample([0.25, 0.5, 1.]), [0.25, 0.5, 1.]);
+        // 像素足够多，分给多个线程处理
+        let mut rgba: Vec<u8> = (0..256 * 64).flat_map(|i| [i as u8, (i / 256 * 4) as u8, (i * 7 / 3) as u8, (i / 3) as u8]).collect();
+        let expected = rgba.clone();
+        lut.apply_rgba(&mut rgba);
+        assert!(rgba == expected);
+    }
+}
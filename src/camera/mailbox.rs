@@ -0,0 +1,243 @@
+use std::{sync::{atomic::{AtomicU64, Ordering}, mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError}, Arc, Mutex}, time::Duration};
+use anyhow::anyhow;
+
+use super::{Frame, PendingStill};
+
+/// 拍照时等待一张照片的最长时间
+pub(super) const STILL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 帧计数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats{
+    /// 相机线程发送的帧数
+    pub produced: u64,
+    /// 界面取走的帧数
+    pub displayed: u64,
+    /// 界面来不及取走、被新帧覆盖的帧数
+    pub dropped: u64,
+}
+
+type Waker = Box<dyn Fn(&FrameMailbox) + Send>;
+
+#[derive(Default)]
+struct Inner{
+    slot: Mutex<Option<Frame>>,
+    waker: Mutex<Option<Waker>>,
+    /// 等待下一帧的 next_frame() 调用
+    watchers: Mutex<Vec<Sender<Frame>>>,
+    /// subscribe() 的订阅者
+    subscribers: Mutex<Vec<SyncSender<Frame>>>,
+    produced: AtomicU64,
+    displayed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// 只保存最新一帧的信箱，相机线程 send，界面线程 take
+///
+/// 界面处理不过来时旧帧直接丢弃，不会排队增加延迟。
+/// 信箱从空变为有帧时调用 waker 通知界面，界面取走之前不会重复通知。
+#[derive(Clone, Default)]
+pub struct FrameMailbox{
+    inner: Arc<Inner>,
+}
+
+impl FrameMailbox{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    /// 设置有新帧时的通知，在相机线程中调用，例如用 slint::invoke_from_event_loop 唤醒界面
+    ///
+    /// 参数为信箱本身，waker 不需要持有信箱的克隆
+    pub fn set_waker(&self, waker: impl Fn(&FrameMailbox) + Send + 'static){
+        *self.inner.waker.lock().unwrap() = Some(Box::new(waker));
+    }
+
+    /// 放入一帧，覆盖还没有取走的帧
+    pub fn send(&self, frame: Frame){
+        self.inner.produced.fetch_add(1, Ordering::Relaxed);
+        // 只有纹理的帧没有像素数据，next_frame() 和订阅者等下一帧
+        if frame.gpu.is_none(){
+            self.send_data(&frame);
+        }
+        let old = self.inner.slot.lock().unwrap().replace(frame);
+        if old.is_some(){
+            self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+        }else if let Some(waker) = self.inner.waker.lock().unwrap().as_ref(){
+            waker(self);
+        }
+    }
+
+    /// 取走最新的一帧
+    pub fn take(&self) -> Option<Frame>{
+        let frame = self.inner.slot.lock().unwrap().take();
+        if frame.is_some(){
+            self.inner.displayed.fetch_add(1, Ordering::Relaxed);
+        }
+        frame
+    }
+
+    /// 是否有 next_frame() 或订阅者在等待像素数据，没有时共用界面设备的后端可以不回读
+    ///
+    /// 释放了接收端的订阅者在下一次发送像素数据时才移除
+    pub fn wants_data(&self) -> bool{
+        !self.inner.watchers.lock().unwrap().is_empty() || !self.inner.subscribers.lock().unwrap().is_empty()
+    }
+
+    fn send_data(&self, frame: &Frame){
+        for watcher in self.inner.watchers.lock().unwrap().drain(..){
+            let _ = watcher.send(frame.clone());
+        }
+        // 接收端已经释放的订阅者移除，缓存满时这一帧不发给它
+        self.inner.subscribers.lock().unwrap().retain(|subscriber| {
+            !matches!(subscriber.try_send(frame.clone()), Err(TrySendError::Disconnected(_)))
+        });
+    }
+
+    /// 等待相机线程发送下一帧并返回它的副本，不影响界面显示，超时返回 None
+    pub fn next_frame(&self, timeout: Duration) -> Option<Frame>{
+        let (sender, receiver) = channel();
+        self.inner.watchers.lock().unwrap().push(sender);
+        receiver.recv_timeout(timeout).ok()
+    }
+
+    /// 订阅之后发送的每一帧，例如录像，释放 Receiver 即取消订阅
+    ///
+    /// 接收端来不及处理、缓存了 capacity 帧时丢弃新帧，不会阻塞相机线程
+    pub fn subscribe(&self, capacity: usize) -> Receiver<Frame>{
+        let (sender, receiver) = sync_channel(capacity.max(1));
+        self.inner.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// 从预览流中取接下来的 count 帧作为照片，没有单独拍照通道的后端使用
+    pub(crate) fn still_frames(&self, count: usize) -> PendingStill{
+        let mailbox = self.clone();
+        Box::new(move || {
+            (0..count)
+                .map(|_| mailbox.next_frame(STILL_TIMEOUT).ok_or(anyhow!("no preview frame in {STILL_TIMEOUT:?}, is the preview started?")))
+                .collect()
+        })
+    }
+
+    pub fn stats(&self) -> FrameStats{
+        FrameStats{
+            produced: self.inner.produced.load(Ordering::Relaxed),
+            displayed: self.inner.displayed.load(Ordering::Relaxed),
+            dropped: self.inner.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::{sync::atomic::AtomicUsize, thread, time::Instant};
+    use super::*;
+    use super::super::PixelFormat;
+
+    fn frame(sequence: u64) -> Frame{
+        let mut frame = Frame::new(vec![0; 4], PixelFormat::Rgba8, 1, 1);
+        frame.sequence = sequence;
+        frame
+    }
+
+    #[test]
+    fn latest_frame_replaces_old(){
+        let mailbox = FrameMailbox::new();
+        assert!(mailbox.take().is_none());
+        for sequence in 1..=3{
+            mailbox.send(frame(sequence));
+        }
+        assert_eq!(mailbox.take().map(|frame| frame.sequence), Some(3));
+        assert!(mailbox.take().is_none());
+        mailbox.send(frame(4));
+        assert_eq!(mailbox.take().map(|frame| frame.sequence), Some(4));
+        assert_eq!(mailbox.stats(), FrameStats{ produced: 4, displayed: 2, dropped: 2 });
+    }
+
+    #[test]
+    fn full_subscriber_drops_frames(){
+        let mailbox = FrameMailbox::new();
+        let receiver = mailbox.subscribe(2);
+        // 缓存满了之后 send 不阻塞，新帧不进入队列
+        for sequence in 1..=5{
+            mailbox.send(frame(sequence));
+        }
+        let received: Vec<u64> = receiver.try_iter().map(|frame| frame.sequence).collect();
+        assert_eq!(received, [1, 2]);
+        mailbox.send(frame(6));
+        assert_eq!(receiver.try_recv().map(|frame| frame.sequence).ok(), Some(6));
+        // 释放接收端后取消订阅
+        drop(receiver);
+        mailbox.send(frame(7));
+        assert!(mailbox.inner.subscribers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn wants_data_follows_consumers(){
+        let mailbox = FrameMailbox::new();
+        assert!(!mailbox.wants_data());
+        let receiver = mailbox.subscribe(1);
+        assert!(mailbox.wants_data());
+        // 释放的订阅者在下一次发送像素数据之后移除
+        drop(receiver);
+        assert!(mailbox.wants_data());
+        mailbox.send(frame(1));
+        assert!(!mailbox.wants_data());
+
+        let watcher = {
+            let mailbox = mailbox.clone();
+            thread::spawn(move || mailbox.next_frame(Duration::from_secs(1)))
+        };
+        let start = Instant::now();
+        while !mailbox.wants_data() && start.elapsed() < Duration::from_secs(1){
+            thread::yield_now();
+        }
+        assert!(mailbox.wants_data());
+        mailbox.send(frame(2));
+        assert_eq!(watcher.join().unwrap().map(|frame| frame.sequence), Some(2));
+        assert!(!mailbox.wants_data());
+    }
+
+    #[test]
+    fn waker_fires_once_per_pending_frame(){
+        let mailbox = FrameMailbox::new();
+        let wakes = Arc::new(AtomicUsize::new(0));
+        {
+            let wakes = wakes.clone();
+            mailbox.set_waker(move |_| { wakes.fetch_add(1, Ordering::Relaxed); });
+        }
+        mailbox.send(frame(1));
+        mailbox.send(frame(2));
+        assert_eq!(wakes.load(Ordering::Relaxed), 1);
+        mailbox.take();
+        mailbox.send(frame(3));
+        assert_eq!(wakes.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn still_frames_from_preview(){
+        let mailbox = FrameMailbox::new();
+        let pending = mailbox.still_frames(2);
+        let task = thread::spawn(pending);
+        let sender = mailbox.clone();
+        thread::spawn(move || {
+            for sequence in 1..=20{
+                sender.send(frame(sequence));
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let frames = task.join().unwrap().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].sequence < frames[1].sequence);
+    }
+
+    #[test]
+    fn still_frames_time_out(){
+        let mailbox = FrameMailbox::new();
+        let start = Instant::now();
+        let err = mailbox.still_frames(1)().unwrap_err();
+        assert!(start.elapsed() >= STILL_TIMEOUT);
+        assert!(err.to_string().contains("is the preview started"));
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use ndk_sys::{
+    media_status_t, AMediaCodec, AMediaCodecBufferInfo, AMediaCodec_configure,
+    AMediaCodec_createEncoderByType, AMediaCodec_delete, AMediaCodec_dequeueInputBuffer,
+    AMediaCodec_dequeueOutputBuffer, AMediaCodec_getInputBuffer, AMediaCodec_getOutputBuffer,
+    AMediaCodec_getOutputFormat, AMediaCodec_queueInputBuffer, AMediaCodec_releaseOutputBuffer,
+    AMediaCodec_start, AMediaCodec_stop, AMediaFormat, AMediaFormat_delete, AMediaFormat_getBuffer,
+    AMediaFormat_getInt32, AMediaFormat_new, AMediaFormat_setBuffer, AMediaFormat_setInt32,
+    AMediaFormat_setString, AMediaMuxer, AMediaMuxer_addTrack, AMediaMuxer_delete, AMediaMuxer_new,
+    AMediaMuxer_setOrientationHint, AMediaMuxer_start, AMediaMuxer_stop, AMediaMuxer_writeSampleData,
+    OutputFormat, AMEDIACODEC_BUFFER_FLAG_CODEC_CONFIG, AMEDIACODEC_BUFFER_FLAG_END_OF_STREAM,
+    AMEDIACODEC_CONFIGURE_FLAG_ENCODE, AMEDIACODEC_INFO_OUTPUT_FORMAT_CHANGED,
+};
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    fs::{File, OpenOptions},
+    mem::zeroed,
+    os::fd::AsRawFd,
+    path::Path,
+    ptr::null_mut,
+    slice,
+    time::Duration,
+};
+
+use super::{
+    convert,
+    encoder::{EncodeOptions, EncodedPacket, EncoderOutput, Muxer, TrackFormat, VideoEncoder},
+    Frame, PixelFormat,
+};
+
+/// MediaCodecInfo.CodecCapabilities 的颜色格式，NV12 支持最广，其次是 I420
+const COLOR_FORMAT_YUV420_SEMI_PLANAR: i32 = 21;
+const COLOR_FORMAT_YUV420_PLANAR: i32 = 19;
+/// MediaCodec.BUFFER_FLAG_KEY_FRAME
+const BUFFER_FLAG_KEY_FRAME: u32 = 1;
+/// 等待空闲输入缓冲区的时间
+const INPUT_TIMEOUT_US: i64 = 10_000;
+
+/// AMediaFormat 的 key，直接使用字符串，AMEDIAFORMAT_KEY_* 常量有些要 API 28 以上
+const KEY_MIME: &CStr = c"mime";
+const KEY_WIDTH: &CStr = c"width";
+const KEY_HEIGHT: &CStr = c"height";
+const KEY_COLOR_FORMAT: &CStr = c"color-format";
+const KEY_BIT_RATE: &CStr = c"bitrate";
+const KEY_FRAME_RATE: &CStr = c"frame-rate";
+const KEY_I_FRAME_INTERVAL: &CStr = c"i-frame-interval";
+const KEY_STRIDE: &CStr = c"stride";
+const KEY_SLICE_HEIGHT: &CStr = c"slice-height";
+const KEY_CSD: [&CStr; 3] = [c"csd-0", c"csd-1", c"csd-2"];
+
+/// dlopen 的 RTLD_LAZY，32 位和 64 位的 bionic 相同
+const RTLD_LAZY: c_int = 1;
+
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+}
+
+type GetInputFormat = unsafe extern "C" fn(*mut AMediaCodec) -> *mut AMediaFormat;
+
+/// AMediaCodec H.264/HEVC 编码器，输入 I420 帧，按编码器支持的颜色格式复制到输入缓冲区
+pub struct MediaCodecEncoder{
+    codec: *mut AMediaCodec,
+    mime: String,
+    /// 编码器的输入格式: Nv12 或 I420
+    input_format: PixelFormat,
+    /// 输入缓冲区中 Y 平面每行的字节数和行数，色度平面紧跟在 Y 平面之后
+    stride: usize,
+    slice_height: usize,
+    buffer: Vec<u8>,
+    last_pts_us: i64,
+}
+
+// AMediaCodec 可以在创建它的线程之外使用，只要不同时调用
+unsafe impl Send for MediaCodecEncoder {}
+
+impl MediaCodecEncoder{
+    pub fn new(width: u32, height: u32, options: &EncodeOptions) -> Result<Self>{
+        let mime = options.codec.mime();
+        let mime_cstr = CString::new(mime)?;
+        unsafe{
+            let codec = AMediaCodec_createEncoderByType(mime_cstr.as_ptr());
+            if codec.is_null(){
+                return Err(anyhow!("no {mime} encoder"));
+            }
+            for (color_format, input_format) in [
+                (COLOR_FORMAT_YUV420_SEMI_PLANAR, PixelFormat::Nv12),
+                (COLOR_FORMAT_YUV420_PLANAR, PixelFormat::I420),
+            ]{
+                let format = AMediaFormat_new();
+                AMediaFormat_setString(format, KEY_MIME.as_ptr(), mime_cstr.as_ptr());
+                AMediaFormat_setInt32(format, KEY_WIDTH.as_ptr(), width as i32);
+                AMediaFormat_setInt32(format, KEY_HEIGHT.as_ptr(), height as i32);
+                AMediaFormat_setInt32(format, KEY_COLOR_FORMAT.as_ptr(), color_format);
+                AMediaFormat_setInt32(format, KEY_BIT_RATE.as_ptr(), options.bit_rate_for(width, height) as i32);
+                AMediaFormat_setInt32(format, KEY_FRAME_RATE.as_ptr(), options.fps as i32);
+                AMediaFormat_setInt32(format, KEY_I_FRAME_INTERVAL.as_ptr(), options.key_frame_interval as i32);
+                let res = AMediaCodec_configure(codec, format, null_mut(), null_mut(), AMEDIACODEC_CONFIGURE_FLAG_ENCODE as u32);
+                AMediaFormat_delete(format);
+                if res != media_status_t::AMEDIA_OK{
+                    info!("{mime} 编码器不支持颜色格式 {color_format}: {:?}", res);
+                    continue;
+                }
+                let res = AMediaCodec_start(codec);
+                if res != media_status_t::AMEDIA_OK{
+                    AMediaCodec_delete(codec);
+                    return Err(anyhow!("AMediaCodec_start error res={:?}.", res));
+                }
+                let (stride, slice_height) = input_layout(codec, width, height);
+                info!("{mime} 编码器: {width}x{height} 输入 {input_format:?} stride={stride} slice-height={slice_height}");
+                return Ok(Self { codec, mime: mime.to_string(), input_format, stride, slice_height, buffer: vec![], last_pts_us: 0 });
+            }
+            AMediaCodec_delete(codec);
+            Err(anyhow!("{mime} encoder does not support {width}x{height} NV12 or I420 input"))
+        }
+    }
+
+    /// 读取输出格式中的宽高和 csd
+    unsafe fn read_format(&self, format: *mut AMediaFormat) -> TrackFormat{
+        let mut width = 0;
+        let mut height = 0;
+        AMediaFormat_getInt32(format, KEY_WIDTH.as_ptr(), &mut width);
+        AMediaFormat_getInt32(format, KEY_HEIGHT.as_ptr(), &mut height);
+        let codec_config = KEY_CSD
+            .iter()
+            .map_while(|key| {
+                let mut data: *mut c_void = null_mut();
+                let mut size = 0;
+                AMediaFormat_getBuffer(format, key.as_ptr(), &mut data, &mut size)
+                    .then(|| slice::from_raw_parts(data as *const u8, size).to_vec())
+            })
+            .collect();
+        TrackFormat { mime: self.mime.clone(), width: width as u32, height: height as u32, codec_config }
+    }
+}
+
+/// 编码器输入缓冲区的布局: (stride, slice-height)，有的编码器要求行和平面按 16 或更大对齐
+///
+/// AMediaCodec_getInputFormat 要 API 28 以上，运行时查找，没有时按宽高紧密排列
+unsafe fn input_layout(codec: *mut AMediaCodec, width: u32, height: u32) -> (usize, usize){
+    let (mut stride, mut slice_height) = (width as i32, height as i32);
+    let library = dlopen(c"libmediandk.so".as_ptr(), RTLD_LAZY);
+    if library.is_null(){
+        return (width as usize, height as usize);
+    }
+    let symbol = dlsym(library, c"AMediaCodec_getInputFormat".as_ptr());
+    if !symbol.is_null(){
+        let get_input_format = std::mem::transmute::<*mut c_void, GetInputFormat>(symbol);
+        let format = get_input_format(codec);
+        if !format.is_null(){
+            AMediaFormat_getInt32(format, KEY_STRIDE.as_ptr(), &mut stride);
+            AMediaFormat_getInt32(format, KEY_SLICE_HEIGHT.as_ptr(), &mut slice_height);
+            AMediaFormat_delete(format);
+        }
+    }
+    dlclose(library);
+    // 有的编码器不设置或设置为 0
+    (stride.max(width as i32) as usize, slice_height.max(height as i32) as usize)
+}
+
+impl VideoEncoder for MediaCodecEncoder{
+    fn queue_frame(&mut self, frame: &Frame, pts_us: i64) -> Result<bool>{
+        convert::pack_yuv420_padded(&frame.planes()?, frame.width, frame.height, self.input_format, self.stride, self.slice_height, &mut self.buffer)?;
+        unsafe{
+            let index = AMediaCodec_dequeueInputBuffer(self.codec, INPUT_TIMEOUT_US);
+            if index < 0{
+                return Ok(false);
+            }
+            let mut size = 0;
+            let input = AMediaCodec_getInputBuffer(self.codec, index as usize, &mut size);
+            if input.is_null() || size < self.buffer.len(){
+                return Err(anyhow!("input buffer too small: {size} < {}", self.buffer.len()));
+            }
+            slice::from_raw_parts_mut(input, self.buffer.len()).copy_from_slice(&self.buffer);
+            let res = AMediaCodec_queueInputBuffer(self.codec, index as usize, 0, self.buffer.len(), pts_us as u64, 0);
+            if res != media_status_t::AMEDIA_OK{
+                return Err(anyhow!("AMediaCodec_queueInputBuffer error res={:?}.", res));
+            }
+        }
+        self.last_pts_us = pts_us;
+        Ok(true)
+    }
+
+    fn queue_end_of_stream(&mut self) -> Result<()>{
+        unsafe{
+            let index = AMediaCodec_dequeueInputBuffer(self.codec, Duration::from_secs(1).as_micros() as i64);
+            if index < 0{
+                return Err(anyhow!("no input buffer for end of stream: {index}"));
+            }
+            let res = AMediaCodec_queueInputBuffer(
+                self.codec,
+                index as usize,
+                0,
+                0,
+                self.last_pts_us as u64,
+                AMEDIACODEC_BUFFER_FLAG_END_OF_STREAM as u32,
+            );
+            if res != media_status_t::AMEDIA_OK{
+                return Err(anyhow!("AMediaCodec_queueInputBuffer error res={:?}.", res));
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_output(&mut self, timeout: Duration) -> Result<EncoderOutput>{
+        unsafe{
+            let mut info: AMediaCodecBufferInfo = zeroed();
+            let index = AMediaCodec_dequeueOutputBuffer(self.codec, &mut info, timeout.as_micros() as i64);
+            if index == AMEDIACODEC_INFO_OUTPUT_FORMAT_CHANGED as isize{
+                let format = AMediaCodec_getOutputFormat(self.codec);
+                if format.is_null(){
+                    return Err(anyhow!("AMediaCodec_getOutputFormat returned null"));
+                }
+                let track_format = self.read_format(format);
+                AMediaFormat_delete(format);
+                return Ok(EncoderOutput::Format(track_format));
+            }
+            // TRY_AGAIN_LATER、OUTPUT_BUFFERS_CHANGED
+            if index < 0{
+                return Ok(EncoderOutput::Pending);
+            }
+            let mut size = 0;
+            let output = AMediaCodec_getOutputBuffer(self.codec, index as usize, &mut size);
+            let (offset, len) = (info.offset.max(0) as usize, info.size.max(0) as usize);
+            let data = match output.is_null() || offset + len > size{
+                true => None,
+                false => Some(slice::from_raw_parts(output.add(offset), len).to_vec()),
+            };
+            AMediaCodec_releaseOutputBuffer(self.codec, index as usize, false);
+            let data = data.ok_or(anyhow!("invalid output buffer {offset}+{len} > {size}"))?;
+
+            let flags = info.flags;
+            Ok(if flags & AMEDIACODEC_BUFFER_FLAG_END_OF_STREAM as u32 != 0{
+                EncoderOutput::EndOfStream
+            }else if flags & AMEDIACODEC_BUFFER_FLAG_CODEC_CONFIG as u32 != 0 || data.is_empty(){
+                // csd 已经在输出格式中
+                EncoderOutput::Pending
+            }else{
+                EncoderOutput::Packet(EncodedPacket { data, pts_us: info.presentationTimeUs, key_frame: flags & BUFFER_FLAG_KEY_FRAME != 0 })
+            })
+        }
+    }
+}
+
+impl Drop for MediaCodecEncoder{
+    fn drop(&mut self) {
+        unsafe{
+            AMediaCodec_stop(self.codec);
+            AMediaCodec_delete(self.codec);
+        }
+    }
+}
+
+/// AMediaMuxer MP4 封装
+pub struct MediaMuxer{
+    muxer: *mut AMediaMuxer,
+    /// AMediaMuxer 使用文件描述符，文件在 muxer 删除之后再关闭
+    _file: File,
+    started: bool,
+}
+
+unsafe impl Send for MediaMuxer {}
+
+impl MediaMuxer{
+    pub fn new(path: &Path) -> Result<Self>{
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|err| anyhow!("create {path:?} failed: {err}"))?;
+        let muxer = unsafe { AMediaMuxer_new(file.as_raw_fd(), OutputFormat::AMEDIAMUXER_OUTPUT_FORMAT_MPEG_4) };
+        if muxer.is_null(){
+            return Err(anyhow!("AMediaMuxer_new failed"));
+        }
+        Ok(Self { muxer, _file: file, started: false })
+    }
+}
+
+impl Muxer for MediaMuxer{
+    fn set_orientation_hint(&mut self, degrees: i32) -> Result<()>{
+        let res = unsafe { AMediaMuxer_setOrientationHint(self.muxer, degrees) };
+        if res != media_status_t::AMEDIA_OK{
+            return Err(anyhow!("AMediaMuxer_setOrientationHint error res={:?}.", res));
+        }
+        Ok(())
+    }
+
+    fn add_track(&mut self, format: &TrackFormat) -> Result<usize>{
+        let mime = CString::new(format.mime.as_str())?;
+        unsafe{
+            let media_format = AMediaFormat_new();
+            AMediaFormat_setString(media_format, KEY_MIME.as_ptr(), mime.as_ptr());
+            AMediaFormat_setInt32(media_format, KEY_WIDTH.as_ptr(), format.width as i32);
+            AMediaFormat_setInt32(media_format, KEY_HEIGHT.as_ptr(), format.height as i32);
+            for (key, csd) in KEY_CSD.iter().zip(&format.codec_config){
+                AMediaFormat_setBuffer(media_format, key.as_ptr(), csd.as_ptr() as *const c_void, csd.len());
+            }
+            let track = AMediaMuxer_addTrack(self.muxer, media_format);
+            AMediaFormat_delete(media_format);
+            if track < 0{
+                return Err(anyhow!("AMediaMuxer_addTrack error {track}"));
+            }
+            Ok(track as usize)
+        }
+    }
+
+    fn start(&mut self) -> Result<()>{
+        let res = unsafe { AMediaMuxer_start(self.muxer) };
+        if res != media_status_t::AMEDIA_OK{
+            return Err(anyhow!("AMediaMuxer_start error res={:?}.", res));
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    fn write_sample(&mut self, track: usize, packet: &EncodedPacket) -> Result<()>{
+        let info = AMediaCodecBufferInfo {
+            offset: 0,
+            size: packet.data.len() as i32,
+            presentationTimeUs: packet.pts_us,
+            flags: if packet.key_frame { BUFFER_FLAG_KEY_FRAME } else { 0 },
+        };
+        let res = unsafe { AMediaMuxer_writeSampleData(self.muxer, track, packet.data.as_ptr(), &info) };
+        if res != media_status_t::AMEDIA_OK{
+            return Err(anyhow!("AMediaMuxer_writeSampleData error res={:?}.", res));
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()>{
+        self.started = false;
+        let res = unsafe { AMediaMuxer_stop(self.muxer) };
+        if res != media_status_t::AMEDIA_OK{
+            return Err(anyhow!("AMediaMuxer_stop error res={:?}.", res));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MediaMuxer{
+    fn drop(&mut self) {
+        unsafe{
+            // 没有正常结束时也 stop，尽量写完文件
+            if self.started && AMediaMuxer_stop(self.muxer) != media_status_t::AMEDIA_OK{
+                error!("AMediaMuxer_stop failed");
+            }
+            AMediaMuxer_delete(self.muxer);
+        }
+    }
+}
@@ -1,58 +1,272 @@
-use std::sync::mpsc::Sender;
-
 #[cfg(target_os = "android")]
 use self::camera2::AndroidCamera;
-use anyhow::Result;
-use slint::{Image, Rgba8Pixel, SharedPixelBuffer};
+use std::{path::Path, thread, time::{Duration, SystemTime}};
+use anyhow::{anyhow, Result};
 
 #[cfg(target_os = "android")]
 mod camera2;
 
+#[cfg(target_os = "android")]
+mod mediacodec;
+
 #[cfg(target_os = "windows")]
 mod pcam;
 
+#[cfg(target_os = "linux")]
+mod v4l2;
+
+pub mod convert;
+pub use convert::{ColorMatrix, ColorRange, ColorSpace, Orientation, PixelFormat};
+
+mod filter;
+pub use filter::{FilterStage, FramePipeline};
+
+mod clip;
+pub use clip::{ClipBuffer, ClipFormat, ClipFrame, ClipOptions, ClipRecorder};
+
+mod encoder;
+pub use encoder::{EncodeOptions, EncodeRecorder, EncodeSession, EncodeStats, EncodedPacket, EncoderOutput, Muxer, TrackFormat, VideoCodec, VideoEncoder};
+
+mod exif;
+pub use exif::ExifInfo;
+
+mod frame;
+pub use frame::{Exposure, Frame};
+
+mod lut;
+pub use lut::Lut3d;
+
+mod mailbox;
+pub use mailbox::{FrameMailbox, FrameStats};
+
+mod gpu;
+pub use gpu::{DecodeTimings, GpuContext, GpuFrame, OutputScale, ScaleFilter, ScaleMode, YuvGpuDecoder, READBACK_BUFFERS};
+
+mod pattern;
+pub use pattern::{TestPattern, TestPatternCamera};
+
+mod playback;
+pub use playback::{PlaybackCamera, PlaybackOptions};
+
+mod record;
+pub use record::{RecordFormat, RecordOptions, RecordStats, Recorder};
+
+mod sequence;
+pub use sequence::{SequenceOptions, TimeLapse};
+
+mod still;
+pub use still::{StillCapture, StillFormat, StillOptions};
+
+mod worker;
+
+/// 镜头朝向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LensFacing{
+    Front,
+    Back,
+    /// 外接相机，如USB摄像头
+    External,
+    #[default]
+    Unknown,
+}
+
+/// 相机信息
+#[derive(Debug, Clone, Default)]
+pub struct CameraInfo{
+    /// 传给 CameraBackend::open 的id
+    pub id: String,
+    pub name: String,
+    pub lens_facing: LensFacing,
+    /// 传感器方向(顺时针角度)
+    pub sensor_orientation: i32,
+    /// 支持的预览分辨率: (width, height)
+    pub supported_sizes: Vec<(u32, u32)>,
+    /// 相机原生的像素格式名称，如 YUV_420_888、YUYV、MJPG
+    pub pixel_formats: Vec<String>,
+    /// 支持的帧率范围: (min, max)
+    pub fps_ranges: Vec<(u32, u32)>,
+}
+
+/// 等待拍照结果，在工作线程中调用，返回按拍摄顺序排列的帧
+pub type PendingStill = Box<dyn FnOnce() -> Result<Vec<Frame>> + Send>;
+
+/// 相机后端，各平台的相机以及自定义的图像源都实现这个trait
+pub trait CameraBackend{
+    /// 列出可用的相机
+    fn list_cameras(&mut self) -> Result<Vec<CameraInfo>>;
+    /// 打开相机
+    fn open(&mut self, camera_id: &str) -> Result<()>;
+    /// 开始预览，预览帧通过创建后端时传入的 FrameMailbox 发送
+    fn start_preview(&mut self, width: u32, height: u32) -> Result<()>;
+    /// 停止预览并关闭相机
+    fn stop_preview(&mut self) -> Result<()>;
+    /// 当前打开的相机的信息
+    fn capabilities(&mut self) -> Result<CameraInfo>;
+    /// 设置调色用的 3D LUT，返回 false 表示后端不处理，需要调用者在 CPU 上应用
+    fn set_lut(&mut self, _lut: Option<Lut3d>) -> Result<bool>{
+        Ok(false)
+    }
+    /// 设置界面渲染使用的 wgpu 设备，返回 false 表示后端不支持，预览帧仍然回读到内存
+    ///
+    /// 支持的后端在信箱没有订阅者时发送只有 Frame::gpu 的帧，由界面线程提交后直接显示纹理
+    fn set_display_device(&mut self, _gpu: Option<GpuContext>) -> Result<bool>{
+        Ok(false)
+    }
+    /// 连续拍 count 张照片，调用线程上只发出拍摄请求，不等待照片
+    ///
+    /// 照片为后端能提供的最高分辨率，已经按显示方向旋转
+    fn capture_still(&mut self, _count: usize) -> Result<PendingStill>{
+        Err(anyhow!("still capture is not supported"))
+    }
+    /// 开始硬件编码录像，保存为 MP4
+    fn start_recording(&mut self, _path: &Path, _options: &EncodeOptions) -> Result<()>{
+        Err(anyhow!("hardware recording is not supported"))
+    }
+    /// 暂停或继续录像
+    fn pause_recording(&mut self, _paused: bool) -> Result<()>{
+        Err(anyhow!("not recording"))
+    }
+    /// 停止录像并写完文件
+    fn stop_recording(&mut self) -> Result<EncodeStats>{
+        Err(anyhow!("not recording"))
+    }
+}
+
 pub struct Camera{
-    #[cfg(target_os = "android")]
-    camera: AndroidCamera,
-    #[cfg(target_os = "windows")]
-    camera: pcam::Camera,
+    backend: Box<dyn CameraBackend>,
+    /// 最近一次 list_cameras() 的结果，start_preview 按序号查找时使用，避免每次重新枚举设备
+    cameras: Option<Vec<CameraInfo>>,
 }
 
 impl Camera{
+    /// 使用当前平台默认的相机后端
     pub fn new(
         #[cfg(target_os = "android")]
         app: slint::android::AndroidApp,
-        image_sender: Sender<SharedPixelBuffer<Rgba8Pixel>> 
+        #[cfg_attr(not(any(target_os = "android", target_os = "windows", target_os = "linux")), allow(unused_variables))]
+        image_sender: FrameMailbox
     ) -> Result<Self>{
         #[cfg(target_os = "android")]
-        let camera = AndroidCamera::new(app, image_sender);
-        Ok(Camera{
-            #[cfg(target_os = "android")]
-            camera,
-            #[cfg(target_os = "windows")]
-            camera: pcam::Camera::new(image_sender)
-        })
+        let backend: Box<dyn CameraBackend> = Box::new(AndroidCamera::new(app, image_sender));
+        #[cfg(target_os = "windows")]
+        let backend: Box<dyn CameraBackend> = Box::new(pcam::Camera::new(image_sender));
+        #[cfg(target_os = "linux")]
+        let backend: Box<dyn CameraBackend> = Box::new(v4l2::Camera::new(image_sender));
+        #[cfg(not(any(target_os = "android", target_os = "windows", target_os = "linux")))]
+        return Err(anyhow!("no camera backend for this platform"));
+        #[cfg(any(target_os = "android", target_os = "windows", target_os = "linux"))]
+        Ok(Camera::with_backend(backend))
+    }
+
+    /// 使用自定义的相机后端
+    pub fn with_backend(backend: Box<dyn CameraBackend>) -> Self{
+        Camera{ backend, cameras: None }
     }
 
+    pub fn backend(&mut self) -> &mut dyn CameraBackend{
+        self.backend.as_mut()
+    }
+
+    /// 重新枚举相机并缓存结果
+    pub fn list_cameras(&mut self) -> Result<Vec<CameraInfo>>{
+        let cameras = self.backend.list_cameras()?;
+        self.cameras = Some(cameras.clone());
+        Ok(cameras)
+    }
+
+    /// camera_index 是 list_cameras() 返回列表中的序号，没有调用过 list_cameras() 时枚举一次
     pub fn start_preview(&mut self, camera_index: usize, width: u32, height: u32) -> Result<()>{
-        #[cfg(target_os = "android")]
-        {
-            self.camera.open(&format!("{camera_index}"))?;
-            self.camera.start_preview(width, height)?;
+        self.backend.stop_preview()?;
+        if self.cameras.is_none(){
+            self.list_cameras()?;
         }
-        #[cfg(target_os = "windows")]
-        self.camera.start_preview(camera_index, width, height)?;
-        Ok(())
+        let cameras = self.cameras.as_deref().unwrap_or_default();
+        let camera_id = cameras
+            .get(camera_index)
+            .ok_or(anyhow!("camera index {camera_index} not exist, available: {}", cameras.len()))?
+            .id
+            .clone();
+        self.backend.open(&camera_id)?;
+        self.backend.start_preview(width, height)
     }
 
     pub fn stop_preview(&mut self) -> Result<()>{
-        #[cfg(target_os = "android")]
-        {
-            self.camera.close();
-        }
-        #[cfg(target_os = "windows")]
-        self.camera.stop_preview();
+        self.backend.stop_preview()
+    }
+
+    pub fn set_lut(&mut self, lut: Option<Lut3d>) -> Result<bool>{
+        self.backend.set_lut(lut)
+    }
+
+    pub fn set_display_device(&mut self, gpu: Option<GpuContext>) -> Result<bool>{
+        self.backend.set_display_device(gpu)
+    }
+
+    /// 使用后端的硬件编码器录像，不支持时返回错误，可以改用 Recorder
+    pub fn start_recording(&mut self, path: &Path, options: &EncodeOptions) -> Result<()>{
+        self.backend.start_recording(path, options)
+    }
+
+    pub fn pause_recording(&mut self, paused: bool) -> Result<()>{
+        self.backend.pause_recording(paused)
+    }
+
+    pub fn stop_recording(&mut self) -> Result<EncodeStats>{
+        self.backend.stop_recording()
+    }
+
+    /// 拍照并按 options 编码，设置了 options.path 时同时保存到文件
+    ///
+    /// 在调用线程上等待照片，界面线程中使用 capture_still_async。
+    /// JPEG 照片写入 EXIF: 方向、时间、设备、镜头和拍摄参数
+    pub fn capture_still(&mut self, options: &StillOptions) -> Result<StillCapture>{
+        let frames = self.request_frames(1)?()?;
+        encode_still(frames, options)
+    }
+
+    /// 同 capture_still，调用线程上只发出拍摄请求，等待照片和编码在工作线程中进行，完成后在工作线程中调用 callback
+    pub fn capture_still_async(&mut self, options: &StillOptions, callback: impl FnOnce(Result<StillCapture>) + Send + 'static) -> Result<()>{
+        let (wait, options) = (self.request_frames(1)?, options.clone());
+        thread::spawn(move || callback(wait().and_then(|frames| encode_still(frames, &options))));
         Ok(())
     }
-    
-}
\ No newline at end of file
+
+    /// 连拍 count 张，拍摄时只保存在内存中，全部拍完后在多个线程上编码，按序号写入 options.dir
+    ///
+    /// 在调用线程上等待照片，界面线程中使用 capture_burst_async
+    pub fn capture_burst(&mut self, count: usize, options: &SequenceOptions) -> Result<Vec<StillCapture>>{
+        let frames = self.request_frames(count)?()?;
+        sequence::encode_frames(&frames, options)
+    }
+
+    /// 同 capture_burst，等待照片和编码在工作线程中进行，完成后在工作线程中调用 callback
+    pub fn capture_burst_async(&mut self, count: usize, options: &SequenceOptions, callback: impl FnOnce(Result<Vec<StillCapture>>) + Send + 'static) -> Result<()>{
+        let (wait, options) = (self.request_frames(count)?, options.clone());
+        thread::spawn(move || callback(wait().and_then(|frames| sequence::encode_frames(&frames, &options))));
+        Ok(())
+    }
+
+    /// 发出拍摄请求，返回等待照片并生成 EXIF 的闭包，可以在工作线程中调用
+    ///
+    /// 帧的时间戳是相机的单调时钟，不能换算成日期，EXIF 的拍摄时间使用发出请求时的系统时间，
+    /// 连拍的后续照片加上和第一张的时间戳之差
+    fn request_frames(&mut self, count: usize) -> Result<impl FnOnce() -> Result<Vec<(Frame, ExifInfo)>> + Send + 'static>{
+        let info = self.backend.capabilities().unwrap_or_default();
+        let requested = SystemTime::now();
+        let pending = self.backend.capture_still(count)?;
+        Ok(move || {
+            let frames = pending()?;
+            let first = frames.first().map(|frame| frame.timestamp_ns).unwrap_or_default();
+            Ok(frames.into_iter().map(|frame| {
+                let elapsed = Duration::from_nanos((frame.timestamp_ns - first).max(0) as u64);
+                let exif = ExifInfo::new(&frame, &info, requested + elapsed);
+                (frame, exif)
+            }).collect())
+        })
+    }
+}
+
+fn encode_still(frames: Vec<(Frame, ExifInfo)>, options: &StillOptions) -> Result<StillCapture>{
+    let (frame, exif) = frames.first().ok_or(anyhow!("no still frame"))?;
+    still::encode(frame, options, Some(exif))
+}
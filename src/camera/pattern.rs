@@ -0,0 +1,293 @@
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+use anyhow::{anyhow, Result};
+
+use super::{CameraBackend, CameraInfo, Frame, FrameMailbox, LensFacing, PendingStill, PixelFormat};
+
+/// 测试图案
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern{
+    /// SMPTE 彩条
+    ColorBars,
+    /// 随时间移动的渐变
+    Gradient,
+}
+
+impl TestPattern{
+    pub const ALL: [TestPattern; 2] = [TestPattern::ColorBars, TestPattern::Gradient];
+
+    pub fn id(&self) -> &'static str{
+        match self{
+            TestPattern::ColorBars => "color-bars",
+            TestPattern::Gradient => "gradient",
+        }
+    }
+}
+
+/// 不需要任何硬件的测试图像源，每个测试图案作为一个相机id
+pub struct TestPatternCamera{
+    fps: u32,
+    pattern: Option<TestPattern>,
+    camera_handle: Option<Arc<Mutex<bool>>>,
+    camera_task: Option<std::thread::JoinHandle<Result<()>>>,
+    image_sender: FrameMailbox,
+}
+
+impl TestPatternCamera{
+    pub fn new(image_sender: FrameMailbox, fps: u32) -> Self{
+        Self { fps: fps.max(1), pattern: None, camera_handle: None, camera_task: None, image_sender }
+    }
+
+    fn camera_info(&self, pattern: TestPattern) -> CameraInfo{
+        CameraInfo{
+            id: pattern.id().to_string(),
+            name: format!("Test pattern ({})", pattern.id()),
+            lens_facing: LensFacing::External,
+            sensor_orientation: 0,
+            // 任意分辨率都可以生成，这里列出常用的
+            supported_sizes: vec![(640, 480), (1280, 720), (1920, 1080)],
+            pixel_formats: vec!["RGBA".to_string()],
+            fps_ranges: vec![(self.fps, self.fps)],
+        }
+    }
+}
+
+impl CameraBackend for TestPatternCamera{
+    fn list_cameras(&mut self) -> Result<Vec<CameraInfo>>{
+        Ok(TestPattern::ALL.iter().map(|p| self.camera_info(*p)).collect())
+    }
+
+    fn open(&mut self, camera_id: &str) -> Result<()>{
+        let pattern = TestPattern::ALL
+            .into_iter()
+            .find(|p| p.id() == camera_id)
+            .ok_or(anyhow!("unknown test pattern: {camera_id}"))?;
+        self.pattern = Some(pattern);
+        Ok(())
+    }
+
+    fn start_preview(&mut self, width: u32, height: u32) -> Result<()>{
+        self.stop_preview()?;
+        let pattern = self.pattern.ok_or(anyhow!("camera not opened"))?;
+        if width == 0 || height == 0{
+            return Err(anyhow!("invalid size {width}x{height}"));
+        }
+        let camera_handle = Arc::new(Mutex::new(true));
+        self.camera_handle = Some(camera_handle.clone());
+        let image_sender_clone = self.image_sender.clone();
+        let frame_interval = Duration::from_secs(1) / self.fps;
+        self.camera_task = Some(std::thread::spawn(move ||{
+            let start = Instant::now();
+            let mut frame_count: u64 = 0;
+            let mut rgba_buffer = vec![0; (width * height * 4) as usize];
+            loop {
+                if let Ok(opened) = camera_handle.lock(){
+                    if !*opened{
+                        break;
+                    }
+                }
+
+                let timestamp = start.elapsed();
+                match pattern{
+                    TestPattern::ColorBars => draw_color_bars(&mut rgba_buffer, width, height),
+                    TestPattern::Gradient => draw_gradient(&mut rgba_buffer, width, height, frame_count),
+                }
+                let text = format!("#{:06} {}", frame_count, format_timestamp(timestamp));
+                let scale = (height / 120).max(1);
+                draw_text(&mut rgba_buffer, width, height, 4 * scale, 4 * scale, scale, &text);
+
+                let mut frame = Frame::new(rgba_buffer.clone(), PixelFormat::Rgba8, width, height);
+                frame.timestamp_ns = timestamp.as_nanos() as i64;
+                frame.sequence = frame_count;
+                frame.camera_id = pattern.id().to_string();
+                image_sender_clone.send(frame);
+
+                // 按帧率等待下一帧
+                frame_count += 1;
+                let next_frame = frame_interval * frame_count as u32;
+                if let Some(wait) = next_frame.checked_sub(start.elapsed()){
+                    std::thread::sleep(wait);
+                }
+            }
+            Ok(())
+        }));
+        Ok(())
+    }
+
+    fn stop_preview(&mut self) -> Result<()>{
+        if let Some(handle) = self.camera_handle.take(){
+            if let Ok(mut handle) = handle.lock(){
+                *handle = false;
+            }
+        }
+        if let Some(task) = self.camera_task.take(){
+            task.join().map_err(|err| anyhow!("{:?}", err))??;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&mut self) -> Result<CameraInfo>{
+        let pattern = self.pattern.ok_or(anyhow!("camera not opened"))?;
+        Ok(self.camera_info(pattern))
+    }
+
+    /// 照片就是下一帧测试图案
+    fn capture_still(&mut self, count: usize) -> Result<PendingStill>{
+        Ok(self.image_sender.still_frames(count))
+    }
+}
+
+impl Drop for TestPatternCamera{
+    fn drop(&mut self) {
+        let _ = self.stop_preview();
+    }
+}
+
+fn format_timestamp(t: Duration) -> String{
+    let ms = t.as_millis();
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// rect: [x0, y0, x1, y1]
+fn fill_rect(rgba: &mut [u8], width: u32, height: u32, rect: [u32; 4], color: [u8; 3]){
+    let [x0, y0, x1, y1] = rect;
+    let (x1, y1) = (x1.min(width), y1.min(height));
+    if x0 >= x1 || y0 >= y1{
+        return;
+    }
+    for y in y0..y1{
+        let row = &mut rgba[(y * width * 4) as usize..((y + 1) * width * 4) as usize];
+        for pixel in row[(x0 * 4) as usize..(x1 * 4) as usize].chunks_exact_mut(4){
+            pixel.copy_from_slice(&[color[0], color[1], color[2], 255]);
+        }
+    }
+}
+
+/// SMPTE 彩条: 上部 75% 七色条，中部反序色条，底部 -I/白/+Q/PLUGE
+pub fn draw_color_bars(rgba: &mut [u8], width: u32, height: u32){
+    const BLACK: [u8; 3] = [19, 19, 19];
+    const BARS: [[u8; 3]; 7] = [
+        [191, 191, 191], [191, 191, 0], [0, 191, 191], [0, 191, 0],
+        [191, 0, 191], [191, 0, 0], [0, 0, 191],
+    ];
+    const CASTELLATIONS: [[u8; 3]; 7] = [
+        [0, 0, 191], BLACK, [191, 0, 191], BLACK,
+        [0, 191, 191], BLACK, [191, 191, 191],
+    ];
+    // 底部色块，宽度以 1/12 个色条为单位
+    const BOTTOM: [([u8; 3], u32); 8] = [
+        ([0, 33, 76], 15), ([255, 255, 255], 15), ([50, 0, 106], 15), (BLACK, 15),
+        ([9, 9, 9], 4), (BLACK, 4), ([29, 29, 29], 4), (BLACK, 12),
+    ];
+
+    let top = height * 2 / 3;
+    let middle = height * 3 / 4;
+    let bar_x = |i: u32| i * width / 7;
+    for i in 0..7{
+        fill_rect(rgba, width, height, [bar_x(i), 0, bar_x(i + 1), top], BARS[i as usize]);
+        fill_rect(rgba, width, height, [bar_x(i), top, bar_x(i + 1), middle], CASTELLATIONS[i as usize]);
+    }
+    let mut units = 0;
+    for (color, w) in BOTTOM{
+        let x0 = units * width / 84;
+        units += w;
+        fill_rect(rgba, width, height, [x0, middle, units * width / 84, height], color);
+    }
+}
+
+/// 随帧数平移的渐变
+pub fn draw_gradient(rgba: &mut [u8], width: u32, height: u32, frame: u64){
+    // 三角波，避免渐变在 255->0 处出现断层
+    let triangle = |v: u64| { let v = v % 512; if v < 256 { v as u8 } else { (511 - v) as u8 } };
+    let phase = frame * 4;
+    for (y, row) in rgba.chunks_exact_mut((width * 4) as usize).enumerate(){
+        let g = triangle(y as u64 * 511 / height as u64 + phase / 2);
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate(){
+            let r = triangle(x as u64 * 511 / width as u64 + phase);
+            pixel.copy_from_slice(&[r, g, 255 - r, 255]);
+        }
+    }
+}
+
+/// 5x7 点阵字体，每行低5位有效
+fn glyph(c: char) -> [u8; 7]{
+    match c{
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        _ => [0; 7],
+    }
+}
+
+/// 在黑色底框上绘制白色文字
+pub fn draw_text(rgba: &mut [u8], width: u32, height: u32, x: u32, y: u32, scale: u32, text: &str){
+    let char_width = 6 * scale;
+    let text_width = char_width * text.chars().count() as u32;
+    fill_rect(rgba, width, height, [x.saturating_sub(scale), y.saturating_sub(scale), x + text_width, y + 8 * scale], [0, 0, 0]);
+    for (i, c) in text.chars().enumerate(){
+        let cx = x + i as u32 * char_width;
+        for (row, bits) in glyph(c).into_iter().enumerate(){
+            for col in 0..5{
+                if bits & (0x10 >> col) != 0{
+                    let px = cx + col * scale;
+                    let py = y + row as u32 * scale;
+                    fill_rect(rgba, width, height, [px, py, px + scale, py + scale], [255, 255, 255]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::{Camera, StillOptions};
+
+    #[test]
+    fn color_bars_through_mailbox(){
+        let mailbox = FrameMailbox::new();
+        let receiver = mailbox.subscribe(8);
+        let mut camera = TestPatternCamera::new(mailbox.clone(), 100);
+        camera.open(TestPattern::ColorBars.id()).unwrap();
+        camera.start_preview(70, 48).unwrap();
+        let frames: Vec<Frame> = (0..3).map(|_| receiver.recv_timeout(Duration::from_secs(2)).unwrap()).collect();
+        camera.stop_preview().unwrap();
+
+        for (i, frame) in frames.iter().enumerate(){
+            assert_eq!((frame.width, frame.height, frame.format), (70, 48, PixelFormat::Rgba8));
+            assert_eq!(frame.data.len(), 70 * 48 * 4);
+            assert_eq!(frame.sequence, i as u64);
+            assert_eq!(frame.camera_id, "color-bars");
+        }
+        assert!(frames.windows(2).all(|pair| pair[1].timestamp_ns > pair[0].timestamp_ns));
+
+        // 每个色条宽 10 像素，取文字下方、上部色条的中间
+        let pixel = |frame: &Frame, x: usize, y: usize| frame.data[(y * 70 + x) * 4..][..4].to_vec();
+        assert_eq!(pixel(&frames[0], 15, 24), [191, 191, 0, 255]);
+        assert_eq!(pixel(&frames[0], 65, 24), [0, 0, 191, 255]);
+        // 中部反序色条
+        assert_eq!(pixel(&frames[0], 5, 34), [0, 0, 191, 255]);
+        assert!(mailbox.stats().produced >= 3);
+    }
+
+    #[test]
+    fn capture_still_on_worker(){
+        let mut camera = Camera::with_backend(Box::new(TestPatternCamera::new(FrameMailbox::new(), 100)));
+        camera.start_preview(0, 64, 48).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        camera.capture_still_async(&StillOptions::default(), move |res| sender.send(res).unwrap()).unwrap();
+        let photo = receiver.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        camera.stop_preview().unwrap();
+        assert_eq!((photo.width, photo.height), (64, 48));
+        assert_eq!(&photo.data[..2], [0xFF, 0xD8]);
+    }
+}
@@ -1,21 +1,40 @@
-use std::{sync::{mpsc::Sender, Arc, Mutex}, time::{Duration, Instant}};
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
 use anyhow::{ anyhow, Result};
 use kamera::Camera as KCamera;
-use slint::{Image, Rgba8Pixel, SharedPixelBuffer};
+
+use super::{CameraBackend, CameraInfo, Frame, FrameMailbox, LensFacing, PendingStill, PixelFormat};
 
 pub struct Camera{
+    index: Option<usize>,
     camera_handle: Option<Arc<Mutex<bool>>>,
     camera_task: Option<std::thread::JoinHandle<Result<()>>>,
-    image_sender: Sender<SharedPixelBuffer<Rgba8Pixel>>,
+    image_sender: FrameMailbox,
 }
 
 impl Camera{
-    pub fn new(image_sender: Sender<SharedPixelBuffer<Rgba8Pixel>>) -> Self{
-        Self { camera_handle:None, camera_task: None, image_sender }
+    pub fn new(image_sender: FrameMailbox) -> Self{
+        Self { index: None, camera_handle:None, camera_task: None, image_sender }
+    }
+}
+
+impl CameraBackend for Camera{
+    fn list_cameras(&mut self) -> Result<Vec<CameraInfo>>{
+        // kamera 没有枚举接口，依次尝试打开设备
+        let mut cameras = vec![];
+        while cameras.len() < 16 && KCamera::new_device(cameras.len()).is_some(){
+            cameras.push(camera_info(cameras.len()));
+        }
+        Ok(cameras)
     }
 
-    pub fn start_preview(&mut self, index: usize, width: u32, height: u32) -> Result<()>{
-        self.stop_preview();
+    fn open(&mut self, camera_id: &str) -> Result<()>{
+        self.index = Some(camera_id.parse()?);
+        Ok(())
+    }
+
+    fn start_preview(&mut self, width: u32, height: u32) -> Result<()>{
+        self.stop_preview()?;
+        let index = self.index.ok_or(anyhow!("camera not opened"))?;
         let camera_handle = Arc::new(Mutex::new(true));
         self.camera_handle = Some(camera_handle.clone());
         let image_sender_clone = self.image_sender.clone();
@@ -27,7 +46,8 @@ impl Camera{
             camera.start();
             let mut count = 0;
             let mut timer = Instant::now();
-            let mut rgba_buffer = vec![];
+            let start = Instant::now();
+            let mut sequence = 0;
             loop {
                 if let Ok(opened) = camera_handle.lock(){
                     if !*opened{
@@ -44,21 +64,15 @@ impl Camera{
                     }
                 };
                 
+                // kamera 输出 BGRA，转换为 rgba 的工作留到显示时再做
                 let (width, height) = frame.size_u32();
-                if rgba_buffer.len() as u32 != width*height*4{
-                    rgba_buffer = vec![0; (width*height*4) as usize];
-                }
                 let frame_data = frame.data();
-                let data_u8 = frame_data.data_u8();
-                for (idx, bgra) in data_u8.chunks(4).enumerate(){
-                    rgba_buffer[idx*4] = bgra[2];
-                    rgba_buffer[idx*4+1] = bgra[1];
-                    rgba_buffer[idx*4+2] = bgra[0];
-                    rgba_buffer[idx*4+3] = bgra[3];
-                }
-                
-                let buf = SharedPixelBuffer::clone_from_slice(&rgba_buffer, width, height);
-                image_sender_clone.send(buf).map_err(|err| anyhow!("{:?}", err))?;
+                let mut output = Frame::new(frame_data.data_u8().to_vec(), PixelFormat::Bgra8, width, height);
+                output.timestamp_ns = start.elapsed().as_nanos() as i64;
+                output.sequence = sequence;
+                output.camera_id = format!("{index}");
+                sequence += 1;
+                image_sender_clone.send(output);
 
                 if count == 30{
                     // let time = timer.elapsed().as_millis();
@@ -74,7 +88,7 @@ impl Camera{
         Ok(())
     }
 
-    pub fn stop_preview(&mut self){
+    fn stop_preview(&mut self) -> Result<()>{
         let mut need_close = false;
         if let Some(handle) = self.camera_handle.as_ref(){
             if let Ok(mut handle) = handle.lock(){
@@ -90,5 +104,27 @@ impl Camera{
                 println!("stop preview: {:?}", res);
             }
         }
+        Ok(())
+    }
+
+    fn capabilities(&mut self) -> Result<CameraInfo>{
+        let index = self.index.ok_or(anyhow!("camera not opened"))?;
+        Ok(camera_info(index))
+    }
+
+    /// kamera 只有预览流，取下一帧
+    fn capture_still(&mut self, count: usize) -> Result<PendingStill>{
+        Ok(self.image_sender.still_frames(count))
+    }
+}
+
+fn camera_info(index: usize) -> CameraInfo{
+    // kamera 不提供分辨率、帧率查询，只能在预览开始后得到实际分辨率
+    CameraInfo{
+        id: format!("{index}"),
+        name: format!("Camera {index}"),
+        lens_facing: LensFacing::External,
+        pixel_formats: vec!["BGRA".to_string()],
+        ..Default::default()
     }
 }
\ No newline at end of file
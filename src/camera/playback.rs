@@ -0,0 +1,479 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use anyhow::{anyhow, Result};
+
+use super::{CameraBackend, CameraInfo, ColorRange, ColorSpace, Frame, FrameMailbox, GpuContext, LensFacing, Orientation, PendingStill, PixelFormat, YuvGpuDecoder};
+
+/// 回放参数
+#[derive(Debug, Clone)]
+pub struct PlaybackOptions{
+    /// 图片序列和 NV21 原始数据的帧率，Y4M 使用文件中记录的帧率
+    pub fps: f32,
+    /// 播放结束后从头循环
+    pub looping: bool,
+    /// NV21 原始数据的宽高，为 None 时从文件名中解析，如 dump_1280x720.nv21
+    pub raw_size: Option<(u32, u32)>,
+    /// YUV 数据的色彩空间，为 None 时使用 Y4M 文件头中记录的值，没有记录则为 BT.601 limited range
+    pub color_space: Option<ColorSpace>,
+    /// 和 android 预览一样用 YuvGpuDecoder 把 Y4M/NV21 转换为 rgba，GPU 不可用时发送原始的 YUV 帧，由界面在 CPU 上转换
+    pub gpu: bool,
+}
+
+impl Default for PlaybackOptions{
+    fn default() -> Self {
+        Self { fps: 30., looping: true, raw_size: None, color_space: None, gpu: true }
+    }
+}
+
+/// 回放图片序列目录(PNG/JPEG)、Y4M 文件或 NV21 原始数据，用于在电脑上复现现场采集的数据
+pub struct PlaybackCamera{
+    path: PathBuf,
+    options: PlaybackOptions,
+    opened: bool,
+    camera_handle: Option<Arc<Mutex<bool>>>,
+    camera_task: Option<std::thread::JoinHandle<Result<()>>>,
+    image_sender: FrameMailbox,
+    /// 界面渲染使用的设备，预览中可以改变
+    display: Arc<Mutex<Option<GpuContext>>>,
+}
+
+impl PlaybackCamera{
+    pub fn new(image_sender: FrameMailbox, path: impl Into<PathBuf>, options: PlaybackOptions) -> Self{
+        Self { path: path.into(), options, opened: false, camera_handle: None, camera_task: None, image_sender, display: Arc::default() }
+    }
+
+    fn camera_id(&self) -> String{
+        self.path.to_string_lossy().to_string()
+    }
+
+    fn camera_info(&self) -> Result<CameraInfo>{
+        let reader = FrameReader::open(&self.path, &self.options)?;
+        let pixel_format = match reader.source{
+            Source::Images{ .. } => "RGBA",
+            Source::Y4m{ .. } => "I420",
+            Source::Nv21{ .. } => "NV21",
+        };
+        let fps = reader.fps.round() as u32;
+        Ok(CameraInfo{
+            id: self.camera_id(),
+            name: self.path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(self.camera_id()),
+            lens_facing: LensFacing::External,
+            sensor_orientation: 0,
+            supported_sizes: vec![reader.size],
+            pixel_formats: vec![pixel_format.to_string()],
+            fps_ranges: vec![(fps, fps)],
+        })
+    }
+}
+
+impl CameraBackend for PlaybackCamera{
+    fn list_cameras(&mut self) -> Result<Vec<CameraInfo>>{
+        Ok(vec![self.camera_info()?])
+    }
+
+    fn open(&mut self, camera_id: &str) -> Result<()>{
+        if camera_id != self.camera_id(){
+            return Err(anyhow!("camera id not exist: {camera_id}"));
+        }
+        // 提前检查数据源是否可用
+        FrameReader::open(&self.path, &self.options)?;
+        self.opened = true;
+        Ok(())
+    }
+
+    fn start_preview(&mut self, _width: u32, _height: u32) -> Result<()>{
+        self.stop_preview()?;
+        if !self.opened{
+            return Err(anyhow!("camera not opened"));
+        }
+        let mut reader = FrameReader::open(&self.path, &self.options)?;
+        let looping = self.options.looping;
+        let gpu = self.options.gpu;
+        let display = self.display.clone();
+        let camera_handle = Arc::new(Mutex::new(true));
+        self.camera_handle = Some(camera_handle.clone());
+        let image_sender_clone = self.image_sender.clone();
+        let camera_id = self.camera_id();
+        self.camera_task = Some(std::thread::spawn(move ||{
+            let frame_interval = Duration::from_secs_f32(1. / reader.fps);
+            let mut start = Instant::now();
+            let mut frame_count = 0;
+            // 循环播放时帧序号和时间戳继续增长
+            let mut sequence: u64 = 0;
+            let mut decoder_gpu = match gpu && reader.is_yuv(){
+                true => {
+                    let (width, height) = reader.size;
+                    YuvGpuDecoder::new(width, height, reader.color_space)
+                        .map_err(|err| log::error!("YuvGpuDecoder 创建失败，使用 CPU 转换: {:?}", err))
+                        .ok()
+                }
+                false => None,
+            };
+            // 和界面共用设备的解码器，没有订阅者时只记录命令，由界面线程提交后直接显示纹理
+            let mut decoder_display: Option<YuvGpuDecoder> = None;
+            loop {
+                if let Ok(opened) = camera_handle.lock(){
+                    if !*opened{
+                        break;
+                    }
+                }
+
+                let mut frame = match reader.next_frame()?{
+                    Some(frame) => frame,
+                    None if looping && frame_count > 0 => {
+                        reader.rewind()?;
+                        start = Instant::now();
+                        frame_count = 0;
+                        continue;
+                    }
+                    None => break,
+                };
+                let display_gpu = display.lock().unwrap().clone();
+                match display_gpu{
+                    None => decoder_display = None,
+                    Some(display_gpu) if decoder_display.is_none() && gpu && reader.is_yuv() => {
+                        let (width, height) = reader.size;
+                        decoder_display = YuvGpuDecoder::with_context(&display_gpu, width, height, reader.color_space)
+                            .map_err(|err| {
+                                log::error!("界面设备上的 YuvGpuDecoder 创建失败，预览帧回读后显示: {:?}", err);
+                                // 不再重试
+                                *display.lock().unwrap() = None;
+                            })
+                            .ok();
+                    }
+                    Some(_) => (),
+                }
+                if let Some(decoder) = decoder_display.as_mut().filter(|_| !image_sender_clone.wants_data()){
+                    frame = Frame::from_gpu(decoder.render(&frame.planes()?, Orientation::default())?, frame.width, frame.height);
+                }else if let Some(decoder) = decoder_gpu.as_mut(){
+                    frame = decode_gpu(decoder, &frame)?;
+                }
+                frame.timestamp_ns = (frame_interval * sequence as u32).as_nanos() as i64;
+                frame.sequence = sequence;
+                frame.camera_id = camera_id.clone();
+                image_sender_clone.send(frame);
+
+                frame_count += 1;
+                sequence += 1;
+                if let Some(wait) = (frame_interval * frame_count).checked_sub(start.elapsed()){
+                    std::thread::sleep(wait);
+                }
+            }
+            Ok(())
+        }));
+        Ok(())
+    }
+
+    fn stop_preview(&mut self) -> Result<()>{
+        if let Some(handle) = self.camera_handle.take(){
+            if let Ok(mut handle) = handle.lock(){
+                *handle = false;
+            }
+        }
+        if let Some(task) = self.camera_task.take(){
+            task.join().map_err(|err| anyhow!("{:?}", err))??;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&mut self) -> Result<CameraInfo>{
+        self.camera_info()
+    }
+
+    fn set_display_device(&mut self, gpu: Option<GpuContext>) -> Result<bool>{
+        *self.display.lock().unwrap() = gpu;
+        Ok(true)
+    }
+
+    /// 回放的下一帧
+    fn capture_still(&mut self, count: usize) -> Result<PendingStill>{
+        Ok(self.image_sender.still_frames(count))
+    }
+}
+
+impl Drop for PlaybackCamera{
+    fn drop(&mut self) {
+        let _ = self.stop_preview();
+    }
+}
+
+enum Source{
+    /// 按文件名排序的图片
+    Images{ files: Vec<PathBuf>, next: usize },
+    /// YUV4MPEG2 文件，data_offset 为第一帧的位置
+    Y4m{ reader: BufReader<File>, data_offset: u64 },
+    /// 连续存放的 NV21 帧
+    Nv21{ reader: BufReader<File> },
+}
+
+struct FrameReader{
+    source: Source,
+    size: (u32, u32),
+    fps: f32,
+    color_space: ColorSpace,
+}
+
+impl FrameReader{
+    fn open(path: &Path, options: &PlaybackOptions) -> Result<Self>{
+        if path.is_dir(){
+            let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+                    ext == "png" || ext == "jpg" || ext == "jpeg"
+                })
+                .collect();
+            files.sort();
+            let first = files.first().ok_or(anyhow!("no PNG/JPEG frames in {:?}", path))?;
+            let size = image::image_dimensions(first)?;
+            return Ok(Self::new(Source::Images{ files, next: 0 }, size, options.fps, ColorSpace::default()));
+        }
+
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+        let mut reader = BufReader::new(File::open(path)?);
+        if ext == "y4m"{
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            let (size, fps, range) = parse_y4m_header(&header)?;
+            let data_offset = header.len() as u64;
+            let color_space = options.color_space.unwrap_or(ColorSpace{ range, ..Default::default() });
+            Ok(Self::new(Source::Y4m{ reader, data_offset }, size, fps, color_space))
+        }else{
+            let size = match options.raw_size{
+                Some(size) => size,
+                None => parse_size_from_name(path)
+                    .ok_or(anyhow!("unknown NV21 frame size, name the file like dump_1280x720.nv21"))?,
+            };
+            if size.0 % 2 != 0 || size.1 % 2 != 0{
+                return Err(anyhow!("NV21 frame size must be even: {}x{}", size.0, size.1));
+            }
+            Ok(Self::new(Source::Nv21{ reader }, size, options.fps, options.color_space.unwrap_or_default()))
+        }
+    }
+
+    fn new(source: Source, size: (u32, u32), fps: f32, color_space: ColorSpace) -> Self{
+        Self { source, size, fps: if fps > 0. { fps } else { 30. }, color_space }
+    }
+
+    /// 读出的帧是否为 YUV 数据
+    fn is_yuv(&self) -> bool{
+        !matches!(self.source, Source::Images{ .. })
+    }
+
+    /// 读取下一帧，YUV 数据保持原格式，数据结束时返回 None
+    fn next_frame(&mut self) -> Result<Option<Frame>>{
+        let (width, height) = self.size;
+        match &mut self.source{
+            Source::Images{ files, next } => {
+                let file = match files.get(*next){
+                    Some(file) => file,
+                    None => return Ok(None),
+                };
+                *next += 1;
+                let image = image::open(file)?.to_rgba8();
+                let (width, height) = image.dimensions();
+                Ok(Some(Frame::new(image.into_raw(), PixelFormat::Rgba8, width, height)))
+            }
+            Source::Y4m{ reader, .. } => {
+                let mut frame_header = String::new();
+                if reader.read_line(&mut frame_header)? == 0{
+                    return Ok(None);
+                }
+                if !frame_header.starts_with("FRAME"){
+                    return Err(anyhow!("invalid Y4M frame header: {:?}", frame_header));
+                }
+                let mut data = vec![0; PixelFormat::I420.frame_size(width, height)];
+                if !read_frame(reader, &mut data)?{
+                    return Ok(None);
+                }
+                let mut frame = Frame::new(data, PixelFormat::I420, width, height);
+                frame.color_space = self.color_space;
+                Ok(Some(frame))
+            }
+            Source::Nv21{ reader } => {
+                let mut data = vec![0; PixelFormat::Nv21.frame_size(width, height)];
+                if !read_frame(reader, &mut data)?{
+                    return Ok(None);
+                }
+                let mut frame = Frame::new(data, PixelFormat::Nv21, width, height);
+                frame.color_space = self.color_space;
+                Ok(Some(frame))
+            }
+        }
+    }
+
+    fn rewind(&mut self) -> Result<()>{
+        match &mut self.source{
+            Source::Images{ next, .. } => *next = 0,
+            Source::Y4m{ reader, data_offset } => { reader.seek(SeekFrom::Start(*data_offset))?; }
+            Source::Nv21{ reader } => { reader.rewind()?; }
+        }
+        Ok(())
+    }
+}
+
+/// 在 GPU 上转换为 rgba，回放的数据已经是显示方向，不需要旋转
+fn decode_gpu(decoder: &mut YuvGpuDecoder, frame: &Frame) -> Result<Frame>{
+    let mut rgba = vec![0; (frame.width * frame.height * 4) as usize];
+    decoder.decode(&frame.planes()?, &mut rgba, Orientation::default())?;
+    Ok(Frame::new(rgba, PixelFormat::Rgba8, frame.width, frame.height))
+}
+
+/// 读取完整的一帧，文件末尾不足一帧时返回 false
+fn read_frame(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool>{
+    match reader.read_exact(buf){
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// 解析 Y4M 文件头，如: YUV4MPEG2 W1280 H720 F30:1 Ip A1:1 C420jpeg XCOLORRANGE=FULL
+fn parse_y4m_header(header: &str) -> Result<((u32, u32), f32, ColorRange)>{
+    let mut params = header.trim_end().split(' ');
+    if params.next() != Some("YUV4MPEG2"){
+        return Err(anyhow!("not a Y4M file"));
+    }
+    let (mut width, mut height, mut fps) = (0, 0, 0.);
+    let mut range = ColorRange::Limited;
+    for param in params{
+        let (tag, value) = param.split_at(1.min(param.len()));
+        match tag{
+            "W" => width = value.parse()?,
+            "H" => height = value.parse()?,
+            "F" => {
+                if let Some((num, den)) = value.split_once(':'){
+                    let (num, den): (f32, f32) = (num.parse()?, den.parse()?);
+                    if den > 0.{
+                        fps = num / den;
+                    }
+                }
+            }
+            // 只支持 8 位的 4:2:0，C420p10 等高位深格式每个采样占两个字节
+            "C" if !matches!(value, "420" | "420jpeg" | "420paldv" | "420mpeg2") => {
+                return Err(anyhow!("unsupported Y4M colorspace: {value}"))
+            }
+            // ffmpeg 写入的扩展参数
+            "X" if value == "COLORRANGE=FULL" => range = ColorRange::Full,
+            _ => (),
+        }
+    }
+    if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0{
+        return Err(anyhow!("unsupported Y4M frame size: {width}x{height}"));
+    }
+    Ok(((width, height), fps, range))
+}
+
+/// 从文件名中解析宽高，如 dump_1280x720.nv21
+fn parse_size_from_name(path: &Path) -> Option<(u32, u32)>{
+    let stem = path.file_stem()?.to_str()?;
+    stem.split(|c: char| !c.is_ascii_alphanumeric()).rev().find_map(|part| {
+        let (width, height) = part.split_once('x')?;
+        Some((width.parse().ok()?, height.parse().ok()?))
+    })
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn y4m_colorspaces(){
+        for c in ["420", "420jpeg", "420paldv", "420mpeg2"]{
+            let header = format!("YUV4MPEG2 W64 H48 F25:1 Ip A1:1 C{c}\n");
+            assert_eq!(parse_y4m_header(&header).unwrap(), ((64, 48), 25., ColorRange::Limited));
+        }
+        for c in ["420p10", "420p12", "422", "444", "mono"]{
+            assert!(parse_y4m_header(&format!("YUV4MPEG2 W64 H48 F25:1 C{c}\n")).is_err(), "C{c}");
+        }
+        let header = "YUV4MPEG2 W64 H48 F30000:1001 XCOLORRANGE=FULL\n";
+        assert_eq!(parse_y4m_header(header).unwrap().2, ColorRange::Full);
+    }
+
+    #[test]
+    fn nv21_playback_decodes_on_gpu(){
+        let (width, height) = (64, 48);
+        // 亮度在 limited range 内任意取值，色度平滑变化，GPU 对色度的双线性采样和 CPU 的最近邻结果接近
+        let mut nv21: Vec<u8> = (0..width * height).map(|i| (16 + i * 7 % 219) as u8).collect();
+        for y in 0..height / 2{
+            for x in 0..width / 2{
+                nv21.extend_from_slice(&[(100 + x) as u8, (150 - y) as u8]);
+            }
+        }
+        let path = std::env::temp_dir().join(format!("playback_test_{}_{width}x{height}.nv21", std::process::id()));
+        std::fs::write(&path, &nv21).unwrap();
+
+        let mailbox = FrameMailbox::new();
+        let receiver = mailbox.subscribe(4);
+        let options = PlaybackOptions{ fps: 100., looping: false, ..Default::default() };
+        let mut camera = PlaybackCamera::new(mailbox, &path, options);
+        let id = camera.list_cameras().unwrap()[0].id.clone();
+        camera.open(&id).unwrap();
+        camera.start_preview(0, 0).unwrap();
+        let frame = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        camera.stop_preview().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let expected = Frame::new(nv21, PixelFormat::Nv21, width, height).convert(PixelFormat::Rgba8).unwrap();
+        assert_eq!((frame.width, frame.height, frame.sequence), (width, height, 0));
+        match frame.format{
+            // GPU 可用时发送的是转换后的 rgba，和 CPU 转换的误差在取整范围内
+            PixelFormat::Rgba8 => {
+                let max_diff = frame.data.iter().zip(&expected).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+                assert!(max_diff <= 4, "max diff {max_diff}");
+            }
+            PixelFormat::Nv21 => assert_eq!(frame.convert(PixelFormat::Rgba8).unwrap(), expected),
+            format => panic!("unexpected format {format:?}"),
+        }
+    }
+
+    #[test]
+    fn display_device_skips_readback(){
+        let (width, height) = (64, 48);
+        let nv21: Vec<u8> = (0..width * height * 3 / 2).map(|i| (i * 13 % 256) as u8).collect();
+        let path = std::env::temp_dir().join(format!("playback_display_{}_{width}x{height}.nv21", std::process::id()));
+        std::fs::write(&path, &nv21).unwrap();
+
+        let gpu = match GpuContext::new(wgpu::Backends::all(), true){
+            Ok(gpu) => gpu,
+            Err(err) => {
+                eprintln!("skip: no fallback adapter: {err:?}");
+                return;
+            }
+        };
+        let mailbox = FrameMailbox::new();
+        let options = PlaybackOptions{ fps: 100., ..Default::default() };
+        let mut camera = PlaybackCamera::new(mailbox.clone(), &path, options);
+        assert!(camera.set_display_device(Some(gpu)).unwrap());
+        let id = camera.list_cameras().unwrap()[0].id.clone();
+        camera.open(&id).unwrap();
+        camera.start_preview(0, 0).unwrap();
+
+        // 没有订阅者时只发送 GPU 上的帧，纹理可以直接交给 slint
+        let start = Instant::now();
+        let frame = loop{
+            match mailbox.take(){
+                Some(frame) => break frame,
+                None if start.elapsed() < Duration::from_secs(10) => std::thread::sleep(Duration::from_millis(5)),
+                None => panic!("no frame"),
+            }
+        };
+        assert!(frame.data.is_empty());
+        let texture = frame.gpu.as_ref().expect("gpu frame").submit();
+        assert_eq!((texture.width(), texture.height()), (width, height));
+        assert!(slint::Image::try_from(texture).is_ok());
+
+        // 有订阅者时回读到内存
+        let receiver = mailbox.subscribe(4);
+        let frame = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        camera.stop_preview().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(frame.gpu.is_none());
+        assert_eq!(frame.data.len(), (width * height * 4) as usize);
+    }
+}
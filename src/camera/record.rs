@@ -0,0 +1,486 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use anyhow::{anyhow, Result};
+use image::{codecs::jpeg::JpegEncoder, ColorType, ImageEncoder};
+
+use super::{convert, worker::{FrameHandler, FrameWorker}, Frame, FrameMailbox, PixelFormat};
+
+/// 录像的容器格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordFormat{
+    /// 未压缩的 I420，文件很大，适合回放调试
+    Y4m,
+    /// 每帧一张 JPEG 的 AVI
+    #[default]
+    MjpegAvi,
+}
+
+impl RecordFormat{
+    pub fn extension(&self) -> &'static str{
+        match self{
+            RecordFormat::Y4m => "y4m",
+            RecordFormat::MjpegAvi => "avi",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordOptions{
+    pub format: RecordFormat,
+    /// 输出的帧率，相机的帧按时间戳对齐到这个帧率
+    pub fps: u32,
+    /// MJPEG 的 JPEG 质量 1~100
+    pub quality: u8,
+}
+
+impl Default for RecordOptions{
+    fn default() -> Self{
+        Self { format: RecordFormat::MjpegAvi, fps: 30, quality: 85 }
+    }
+}
+
+/// 录像统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordStats{
+    /// 录制期间(不包括暂停)收到的相机帧数
+    pub received: u64,
+    /// 写入文件的帧数，包括重复写入的帧
+    pub written: u64,
+    /// 相机帧间隔大于一帧时，为了保持时间重复写入的帧数
+    pub duplicated: u64,
+    /// 相机帧率高于输出帧率、或者大小和第一帧不同而丢弃的帧数
+    pub dropped: u64,
+}
+
+/// 录像，订阅 FrameMailbox 的帧，在单独的线程中编码写入文件
+///
+/// 输出为固定帧率，按相机的时间戳重复或丢弃帧，暂停的时间不计入视频
+pub struct Recorder{
+    path: PathBuf,
+    worker: FrameWorker<RecordStats>,
+}
+
+impl Recorder{
+    pub fn start(mailbox: &FrameMailbox, path: impl Into<PathBuf>, options: RecordOptions) -> Result<Self>{
+        if options.fps == 0{
+            return Err(anyhow!("invalid fps 0"));
+        }
+        let path = path.into();
+        let file = BufWriter::new(File::create(&path).map_err(|err| anyhow!("create {path:?} failed: {err}"))?);
+        let writer: Box<dyn VideoWriter + Send> = match options.format{
+            RecordFormat::Y4m => Box::new(Y4mWriter { file, fps: options.fps, size: None, i420: vec![] }),
+            RecordFormat::MjpegAvi => Box::new(AviWriter::new(file, options.fps, options.quality)),
+        };
+        let task = RecordTask { writer, timeline: Timeline::new(options.fps), size: None, stats: RecordStats::default() };
+        // 编码慢于相机时最多缓存几帧，之后的帧由时间戳补齐
+        let worker = FrameWorker::start(mailbox.subscribe(4), task);
+        Ok(Self { path, worker })
+    }
+
+    pub fn path(&self) -> &Path{
+        &self.path
+    }
+
+    pub fn pause(&self){
+        self.worker.pause();
+    }
+
+    pub fn resume(&self){
+        self.worker.resume();
+    }
+
+    pub fn is_paused(&self) -> bool{
+        self.worker.is_paused()
+    }
+
+    /// 停止录制并写完文件
+    pub fn stop(mut self) -> Result<RecordStats>{
+        self.worker.stop()
+    }
+}
+
+/// 录像线程: 按时间戳重复或丢弃帧，写入文件
+struct RecordTask{
+    writer: Box<dyn VideoWriter + Send>,
+    timeline: Timeline,
+    size: Option<(u32, u32)>,
+    stats: RecordStats,
+}
+
+impl FrameHandler for RecordTask{
+    type Output = RecordStats;
+
+    fn on_frame(&mut self, frame: Frame) -> Result<()>{
+        self.stats.received += 1;
+        if *self.size.get_or_insert((frame.width, frame.height)) != (frame.width, frame.height){
+            self.stats.dropped += 1;
+            return Ok(());
+        }
+        let repeat = self.timeline.advance(frame.timestamp_ns);
+        if repeat == 0{
+            self.stats.dropped += 1;
+            return Ok(());
+        }
+        // 空缺的时间显示的是上一帧
+        for _ in 1..repeat{
+            self.writer.repeat_frame()?;
+        }
+        self.writer.write_frame(&frame)?;
+        self.stats.written += repeat;
+        self.stats.duplicated += repeat - 1;
+        Ok(())
+    }
+
+    fn on_paused(&mut self) -> Result<()>{
+        self.timeline.pause();
+        Ok(())
+    }
+
+    fn on_stop(mut self) -> Result<RecordStats>{
+        self.writer.finish()?;
+        Ok(self.stats)
+    }
+}
+
+/// 把相机时间戳对齐到固定帧率的输出序号
+struct Timeline{
+    frame_ns: i64,
+    first: Option<i64>,
+    last: i64,
+    /// 暂停和时间戳倒退时去掉的时间
+    offset: i64,
+    next_index: i64,
+    paused: bool,
+}
+
+impl Timeline{
+    fn new(fps: u32) -> Self{
+        Self { frame_ns: 1_000_000_000 / fps as i64, first: None, last: 0, offset: 0, next_index: 0, paused: false }
+    }
+
+    fn pause(&mut self){
+        self.paused = true;
+    }
+
+    /// 这一帧需要写入的次数，0 表示距离上一帧不到一帧的时间，丢弃
+    fn advance(&mut self, timestamp: i64) -> u64{
+        let first = *self.first.get_or_insert(timestamp);
+        if self.next_index > 0 && (self.paused || timestamp < self.last){
+            // 恢复后的第一帧紧接着暂停前的最后一帧
+            self.offset += timestamp - self.last - self.frame_ns;
+        }
+        self.paused = false;
+        self.last = timestamp;
+        let index = (timestamp - first - self.offset + self.frame_ns / 2).div_euclid(self.frame_ns);
+        if index < self.next_index{
+            return 0;
+        }
+        let repeat = index - self.next_index + 1;
+        self.next_index = index + 1;
+        repeat as u64
+    }
+}
+
+trait VideoWriter{
+    fn write_frame(&mut self, frame: &Frame) -> Result<()>;
+    /// 再写一次上一帧
+    fn repeat_frame(&mut self) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// YUV4MPEG2，C420jpeg，奇数宽高去掉最后一列、一行
+struct Y4mWriter<W>{
+    file: W,
+    fps: u32,
+    size: Option<(u32, u32)>,
+    /// 上一帧的 I420，重复写入时使用
+    i420: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W>{
+    fn write_chunk(&mut self) -> Result<()>{
+        self.file.write_all(b"FRAME\n")?;
+        self.file.write_all(&self.i420)?;
+        Ok(())
+    }
+}
+
+impl<W: Write> VideoWriter for Y4mWriter<W>{
+    fn write_frame(&mut self, frame: &Frame) -> Result<()>{
+        let rgb = frame.convert(PixelFormat::Rgb8)?;
+        let (width, height) = convert::rgb_to_i420(&rgb, frame.width, frame.height, &mut self.i420);
+        if self.size.is_none(){
+            writeln!(self.file, "YUV4MPEG2 W{width} H{height} F{}:1 Ip A1:1 C420jpeg", self.fps)?;
+            self.size = Some((width, height));
+        }
+        self.write_chunk()
+    }
+
+    fn repeat_frame(&mut self) -> Result<()>{
+        if self.size.is_none(){
+            return Ok(());
+        }
+        self.write_chunk()
+    }
+
+    fn finish(&mut self) -> Result<()>{
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// 'movi' 之前的文件头大小
+const AVI_HEADER_LEN: u64 = 224;
+
+/// MJPEG AVI(RIFF)，结束时补上 idx1 索引和文件头中的帧数、大小
+struct AviWriter<W>{
+    file: W,
+    fps: u32,
+    quality: u8,
+    size: Option<(u32, u32)>,
+    /// 上一帧的 JPEG，重复写入时使用
+    jpeg: Vec<u8>,
+    /// 每一帧的 (相对 'movi' 的偏移, 大小)
+    index: Vec<(u32, u32)>,
+    /// 'movi' 之后写入的字节数
+    movi_len: u64,
+    max_chunk: u32,
+}
+
+impl<W: Write + Seek> AviWriter<W>{
+    fn new(file: W, fps: u32, quality: u8) -> Self{
+        Self { file, fps, quality, size: None, jpeg: vec![], index: vec![], movi_len: 4, max_chunk: 0 }
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> Result<()>{
+        let mut header = Vec::with_capacity(AVI_HEADER_LEN as usize);
+        let u32_le = |header: &mut Vec<u8>, value: u32| header.extend_from_slice(&value.to_le_bytes());
+        header.extend_from_slice(b"RIFF");
+        u32_le(&mut header, 0);
+        header.extend_from_slice(b"AVI LIST");
+        u32_le(&mut header, 192);
+        header.extend_from_slice(b"hdrlavih");
+        u32_le(&mut header, 56);
+        // MainAVIHeader
+        for value in [1_000_000 / self.fps, 0, 0, 0x10, 0, 0, 1, 0, width, height, 0, 0, 0, 0]{
+            u32_le(&mut header, value);
+        }
+        header.extend_from_slice(b"LIST");
+        u32_le(&mut header, 116);
+        header.extend_from_slice(b"strlstrh");
+        u32_le(&mut header, 56);
+        // AVIStreamHeader
+        header.extend_from_slice(b"vidsMJPG");
+        for value in [0, 0, 0, 1, self.fps, 0, 0, 0, u32::MAX, 0]{
+            u32_le(&mut header, value);
+        }
+        for value in [0, 0, width as u16, height as u16]{
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(b"strf");
+        u32_le(&mut header, 40);
+        // BITMAPINFOHEADER
+        for value in [40, width, height, 1 | (24 << 16)]{
+            u32_le(&mut header, value);
+        }
+        header.extend_from_slice(b"MJPG");
+        for value in [width * height * 3, 0, 0, 0, 0]{
+            u32_le(&mut header, value);
+        }
+        header.extend_from_slice(b"LIST");
+        u32_le(&mut header, 0);
+        header.extend_from_slice(b"movi");
+        debug_assert_eq!(header.len() as u64, AVI_HEADER_LEN);
+        self.file.write_all(&header)?;
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> Result<()>{
+        let len = self.jpeg.len() as u32;
+        let padded = len as u64 + (len & 1) as u64;
+        if AVI_HEADER_LEN + self.movi_len + 8 + padded + 16 * (self.index.len() as u64 + 1) + 8 > u32::MAX as u64{
+            return Err(anyhow!("AVI file exceeds 4GB"));
+        }
+        self.index.push((self.movi_len as u32, len));
+        self.file.write_all(b"00dc")?;
+        self.file.write_all(&len.to_le_bytes())?;
+        self.file.write_all(&self.jpeg)?;
+        if len & 1 == 1{
+            self.file.write_all(&[0])?;
+        }
+        self.movi_len += 8 + padded;
+        self.max_chunk = self.max_chunk.max(len);
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> VideoWriter for AviWriter<W>{
+    fn write_frame(&mut self, frame: &Frame) -> Result<()>{
+        if self.size.is_none(){
+            self.write_header(frame.width, frame.height)?;
+            self.size = Some((frame.width, frame.height));
+        }
+        let rgb = frame.convert(PixelFormat::Rgb8)?;
+        self.jpeg.clear();
+        JpegEncoder::new_with_quality(&mut self.jpeg, self.quality.clamp(1, 100))
+            .write_image(&rgb, frame.width, frame.height, ColorType::Rgb8)?;
+        self.write_chunk()
+    }
+
+    fn repeat_frame(&mut self) -> Result<()>{
+        if self.size.is_none(){
+            return Ok(());
+        }
+        self.write_chunk()
+    }
+
+    fn finish(&mut self) -> Result<()>{
+        if self.size.is_none(){
+            // 没有收到帧，留下空文件
+            return Ok(self.file.flush()?);
+        }
+        self.file.write_all(b"idx1")?;
+        self.file.write_all(&(self.index.len() as u32 * 16).to_le_bytes())?;
+        for (offset, len) in &self.index{
+            self.file.write_all(b"00dc")?;
+            // AVIIF_KEYFRAME
+            self.file.write_all(&0x10u32.to_le_bytes())?;
+            self.file.write_all(&offset.to_le_bytes())?;
+            self.file.write_all(&len.to_le_bytes())?;
+        }
+        self.file.flush()?;
+
+        // 补上文件头中的大小和帧数
+        let file_len = AVI_HEADER_LEN + self.movi_len - 4 + 8 + self.index.len() as u64 * 16;
+        let frames = self.index.len() as u32;
+        for (position, value) in [
+            (4, (file_len - 8) as u32),
+            // avih dwTotalFrames、dwSuggestedBufferSize
+            (48, frames),
+            (60, self.max_chunk + 8),
+            // strh dwLength、dwSuggestedBufferSize
+            (140, frames),
+            (144, self.max_chunk + 8),
+            // movi 列表的大小
+            (AVI_HEADER_LEN - 8, self.movi_len as u32),
+        ]{
+            self.file.seek(SeekFrom::Start(position))?;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::io::Cursor;
+    use super::*;
+
+    const MS: i64 = 1_000_000;
+
+    fn frame(width: u32, height: u32, seed: u8) -> Frame{
+        let rgba = (0..width * height * 4).map(|i| (i as u8).wrapping_mul(seed)).collect();
+        Frame::new(rgba, PixelFormat::Rgba8, width, height)
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32{
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn timeline_follows_fps(){
+        let mut timeline = Timeline::new(10);
+        let repeats: Vec<u64> = [0, 100, 200].iter().map(|ms| timeline.advance(ms * MS)).collect();
+        assert_eq!(repeats, [1, 1, 1]);
+        // 相机丢了两帧，最后一帧重复写入补齐时间
+        assert_eq!(timeline.advance(500 * MS), 3);
+        // 距离上一帧不到一帧的时间，丢弃
+        assert_eq!(timeline.advance(520 * MS), 0);
+        // 暂停的时间不计入，恢复后的第一帧紧接着暂停前的帧
+        timeline.pause();
+        assert_eq!(timeline.advance(5000 * MS), 1);
+        assert_eq!(timeline.advance(5100 * MS), 1);
+        // 时间戳倒退时同样接在上一帧之后
+        assert_eq!(timeline.advance(100 * MS), 1);
+        assert_eq!(timeline.advance(300 * MS), 2);
+    }
+
+    #[test]
+    fn y4m_frames(){
+        let mut writer = Y4mWriter { file: vec![], fps: 25, size: None, i420: vec![] };
+        writer.repeat_frame().unwrap();
+        writer.write_frame(&frame(5, 3, 3)).unwrap();
+        writer.repeat_frame().unwrap();
+        writer.write_frame(&frame(5, 3, 7)).unwrap();
+        writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W4 H2 F25:1 Ip A1:1 C420jpeg\n";
+        let data = writer.file;
+        assert!(data.starts_with(header));
+        // 奇数宽高去掉最后一列、一行，每帧 4x2 的 I420
+        let frame_len = b"FRAME\n".len() + 4 * 2 * 3 / 2;
+        assert_eq!(data.len(), header.len() + 3 * frame_len);
+        let frames: Vec<&[u8]> = data[header.len()..].chunks(frame_len).collect();
+        assert!(frames.iter().all(|frame| frame.starts_with(b"FRAME\n")));
+        assert_eq!(frames[0], frames[1]);
+        assert_ne!(frames[1], frames[2]);
+    }
+
+    #[test]
+    fn avi_sizes_and_index(){
+        let mut writer = AviWriter::new(Cursor::new(vec![]), 30, 80);
+        writer.write_frame(&frame(16, 8, 3)).unwrap();
+        writer.repeat_frame().unwrap();
+        writer.write_frame(&frame(16, 8, 7)).unwrap();
+        writer.finish().unwrap();
+        let data = writer.file.into_inner();
+
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], b"AVI ");
+        // avih: 帧间隔、帧数、宽高
+        assert_eq!(u32_at(&data, 32), 1_000_000 / 30);
+        assert_eq!(u32_at(&data, 48), 3);
+        assert_eq!((u32_at(&data, 64), u32_at(&data, 68)), (16, 8));
+        // strh: 帧率和长度
+        assert_eq!(u32_at(&data, 132), 30);
+        assert_eq!(u32_at(&data, 140), 3);
+
+        // movi 列表从 'movi' 开始，到 idx1 之前结束
+        let movi = AVI_HEADER_LEN as usize - 4;
+        assert_eq!(&data[movi - 8..movi - 4], b"LIST");
+        assert_eq!(&data[movi..movi + 4], b"movi");
+        let idx1 = movi + u32_at(&data, movi - 4) as usize;
+        assert_eq!(&data[idx1..idx1 + 4], b"idx1");
+        assert_eq!(u32_at(&data, idx1 + 4), 3 * 16);
+        assert_eq!(idx1 + 8 + 3 * 16, data.len());
+
+        let mut chunks = vec![];
+        for entry in data[idx1 + 8..].chunks_exact(16){
+            assert_eq!((&entry[..4], u32_at(entry, 4)), (&b"00dc"[..], 0x10));
+            // 偏移相对 'movi'，指向块头
+            let (offset, len) = (movi + u32_at(entry, 8) as usize, u32_at(entry, 12) as usize);
+            assert_eq!(&data[offset..offset + 4], b"00dc");
+            assert_eq!(u32_at(&data, offset + 4) as usize, len);
+            let jpeg = &data[offset + 8..offset + 8 + len];
+            assert_eq!(&jpeg[..2], [0xFF, 0xD8]);
+            chunks.push(jpeg);
+        }
+        assert_eq!(chunks[0], chunks[1]);
+        // dwSuggestedBufferSize 能放下最大的块
+        let max_chunk = chunks.iter().map(|chunk| chunk.len() as u32 + 8).max().unwrap();
+        assert_eq!((u32_at(&data, 60), u32_at(&data, 144)), (max_chunk, max_chunk));
+    }
+
+    #[test]
+    fn avi_without_frames_is_empty(){
+        let mut writer = AviWriter::new(Cursor::new(vec![]), 30, 80);
+        writer.repeat_frame().unwrap();
+        writer.finish().unwrap();
+        assert!(writer.file.into_inner().is_empty());
+    }
+}
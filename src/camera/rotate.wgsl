@@ -1,10 +1,20 @@
-struct RotationConfig {
-    degree : i32,
+// Orientation::matrix()
+// 旋转和镜像都表示为整数仿射变换:
+// out_x = a*x + b*y + tx
+// out_y = c*x + d*y + ty
+// 例如原图 60x30 顺时针旋转90度后= 30x60，(x, y) => (29-y, x)，即 a=0 b=-1 c=1 d=0 tx=29 ty=0
+struct Transform {
+    a : i32,
+    b : i32,
+    c : i32,
+    d : i32,
+    tx : i32,
+    ty : i32,
 }
 
 @group(0) @binding(0) var input_texture : texture_2d<f32>;
 @group(0) @binding(1) var output_texture : texture_storage_2d<rgba8unorm, write>;
-@group(0) @binding(2) var<storage, read> config : RotationConfig;
+@group(0) @binding(2) var<storage, read> transform : Transform;
 
 @compute @workgroup_size(16,16)
 fn main(@builtin(global_invocation_id) global_id : vec3u) {
@@ -17,33 +27,9 @@ fn main(@builtin(global_invocation_id) global_id : vec3u) {
 
     let pixel = textureLoad(input_texture, coords.xy, 0);
 
-    let output_dim = textureDimensions(output_texture);
-
-    if config.degree == 90{
-        // 旋转90度
-        // 原图 60x30 旋转90度后= 30x60
-        // (0, 0) => (29, 0)
-        // (1, 0) => (29, 1)
-        // (2, 0) => (29, 2)
-        // 即 (x, y) => (output_dim.x-y, x)
-        textureStore(output_texture, vec2<i32>(i32(output_dim.x) - coords.y, coords.x), pixel);
-    }else if config.degree == 180{
-        // 旋转180度
-        // 原图 60x30 旋转180度后 = 仍然是 60x30
-        // (0, 0) => (29, 59)
-        // (1, 0) => (28, 59)
-        // (2, 0) => (27, 59)
-        // (2, 1) => (27, 58)
-        // 即 (x, y) => (output_dim.x-x, output_dim.y-y)
-        textureStore(output_texture, vec2<i32>(i32(output_dim.x) - coords.x, i32(output_dim.y) - coords.y), pixel);
-    }else if config.degree == 270{
-        // 旋转270度
-        // 原图 60x30 旋转180度后 = 30x60
-        // (0, 0) => (0, 59)
-        // (1, 0) => (0, 58)
-        // (2, 0) => (0, 57)
-        // (2, 1) => (1, 58)
-        // 即 (x, y) => (coords.y, output_dim.y-x)
-        textureStore(output_texture, vec2<i32>(coords.y, i32(output_dim.y) - coords.x), pixel);
-    }
-}
\ No newline at end of file
+    let target_coords = vec2<i32>(
+        transform.a * coords.x + transform.b * coords.y + transform.tx,
+        transform.c * coords.x + transform.d * coords.y + transform.ty
+    );
+    textureStore(output_texture, target_coords, pixel);
+}
@@ -0,0 +1,57 @@
+// 缩放、裁剪 rgba 图像
+// 输入图像中 [src_origin, src_origin + src_size) 的区域缩放到整个输出图像
+struct ScaleParams {
+    src_origin : vec2<f32>,
+    src_size : vec2<f32>,
+}
+
+@group(0) @binding(0) var input_texture : texture_2d<f32>;
+@group(0) @binding(1) var input_sampler : sampler;
+@group(0) @binding(2) var output_texture : texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(3) var<uniform> params : ScaleParams;
+
+// 双线性插值，适合放大或小幅缩小
+@compute @workgroup_size(8,8)
+fn bilinear(@builtin(global_invocation_id) global_id : vec3u) {
+    let output_dim = vec2<i32>(textureDimensions(output_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if(coords.x >= output_dim.x || coords.y >= output_dim.y) {
+        return;
+    }
+    let input_dim = vec2<f32>(textureDimensions(input_texture));
+    let scale = params.src_size / vec2<f32>(output_dim);
+    // 输出像素中心对应的输入坐标
+    let position = params.src_origin + (vec2<f32>(coords) + 0.5) * scale;
+    let pixel = textureSampleLevel(input_texture, input_sampler, position / input_dim, 0.0);
+    textureStore(output_texture, coords, pixel);
+}
+
+// 按面积平均，缩小倍数较大时没有锯齿
+@compute @workgroup_size(8,8)
+fn area(@builtin(global_invocation_id) global_id : vec3u) {
+    let output_dim = vec2<i32>(textureDimensions(output_texture));
+    let coords = vec2<i32>(global_id.xy);
+    if(coords.x >= output_dim.x || coords.y >= output_dim.y) {
+        return;
+    }
+    let input_max = vec2<i32>(textureDimensions(input_texture)) - 1;
+    let scale = params.src_size / vec2<f32>(output_dim);
+    // 输出像素覆盖的输入区域 [start, end)
+    let start = params.src_origin + vec2<f32>(coords) * scale;
+    let end = start + scale;
+    let first = vec2<i32>(floor(start));
+    let last = vec2<i32>(ceil(end)) - 1;
+
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var y = first.y; y <= last.y; y++) {
+        let wy = min(end.y, f32(y + 1)) - max(start.y, f32(y));
+        for (var x = first.x; x <= last.x; x++) {
+            let wx = min(end.x, f32(x + 1)) - max(start.x, f32(x));
+            let weight = wx * wy;
+            sum += textureLoad(input_texture, clamp(vec2<i32>(x, y), vec2<i32>(0), input_max), 0) * weight;
+            total += weight;
+        }
+    }
+    textureStore(output_texture, coords, sum / max(total, 1e-6));
+}
@@ -0,0 +1,106 @@
+use std::{path::PathBuf, thread, time::{Duration, Instant}};
+use anyhow::{anyhow, Result};
+
+use super::{still, Camera, ExifInfo, Frame, StillCapture, StillOptions};
+
+/// 连拍、延时拍摄的输出，文件名为 dir/prefix0001.jpg
+#[derive(Debug, Clone)]
+pub struct SequenceOptions{
+    pub dir: PathBuf,
+    pub prefix: String,
+    /// 编码格式和质量，path 忽略
+    pub still: StillOptions,
+}
+
+impl SequenceOptions{
+    pub fn new(dir: impl Into<PathBuf>, prefix: impl Into<String>) -> Self{
+        Self { dir: dir.into(), prefix: prefix.into(), still: StillOptions::default() }
+    }
+
+    /// 第 index 张(从 0 开始)的文件路径，序号从 1 开始
+    pub fn path(&self, index: usize) -> PathBuf{
+        self.dir.join(format!("{}{:04}.{}", self.prefix, index + 1, self.still.format.extension()))
+    }
+
+    fn still_options(&self, index: usize) -> StillOptions{
+        StillOptions { path: Some(self.path(index)), ..self.still.clone() }
+    }
+
+    fn create_dir(&self) -> Result<()>{
+        std::fs::create_dir_all(&self.dir).map_err(|err| anyhow!("create {:?} failed: {err}", self.dir))
+    }
+}
+
+/// 在多个线程上编码并写入文件，结果按拍摄顺序
+pub(super) fn encode_frames(frames: &[(Frame, ExifInfo)], options: &SequenceOptions) -> Result<Vec<StillCapture>>{
+    options.create_dir()?;
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(frames.len()).max(1);
+    let chunk_size = frames.len().div_ceil(threads).max(1);
+    let results: Vec<Result<Vec<StillCapture>>> = thread::scope(|scope| {
+        let tasks: Vec<_> = frames
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(chunk_index, chunk)| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (frame, exif))| still::encode(frame, &options.still_options(chunk_index * chunk_size + i), Some(exif)))
+                        .collect()
+                })
+            })
+            .collect();
+        tasks.into_iter().map(|task| task.join().map_err(|err| anyhow!("{:?}", err))?).collect()
+    });
+    let mut photos = Vec::with_capacity(frames.len());
+    for result in results{
+        photos.extend(result?);
+    }
+    Ok(photos)
+}
+
+/// 延时拍摄: 每隔 interval 拍一张，共拍 duration 时长
+///
+/// 由调用者定时调用 poll()，例如在界面的 Timer 中，照片在工作线程中等待和保存
+pub struct TimeLapse{
+    options: SequenceOptions,
+    interval: Duration,
+    count: usize,
+    start: Instant,
+    index: usize,
+}
+
+impl TimeLapse{
+    pub fn new(options: SequenceOptions, interval: Duration, duration: Duration) -> Result<Self>{
+        if interval.is_zero(){
+            return Err(anyhow!("time-lapse interval must be greater than 0"));
+        }
+        options.create_dir()?;
+        let count = (duration.as_secs_f64() / interval.as_secs_f64()).ceil().max(1.) as usize;
+        Ok(Self { options, interval, count, start: Instant::now(), index: 0 })
+    }
+
+    /// 到了拍摄时间时发出拍摄请求并返回 true，保存完成后在工作线程中调用 callback
+    pub fn poll(&mut self, camera: &mut Camera, callback: impl FnOnce(Result<StillCapture>) + Send + 'static) -> Result<bool>{
+        if self.is_finished() || self.start.elapsed() < self.interval * self.index as u32{
+            return Ok(false);
+        }
+        let options = self.options.still_options(self.index);
+        self.index += 1;
+        camera.capture_still_async(&options, callback).map(|_| true)
+    }
+
+    /// 已经拍摄的张数
+    pub fn captured(&self) -> usize{
+        self.index
+    }
+
+    /// 总共要拍的张数
+    pub fn count(&self) -> usize{
+        self.count
+    }
+
+    pub fn is_finished(&self) -> bool{
+        self.index >= self.count
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+use image::{codecs::{jpeg::JpegEncoder, png::PngEncoder}, ColorType, ImageEncoder};
+
+use super::{ExifInfo, Frame, PixelFormat};
+
+/// 照片的编码格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StillFormat{
+    #[default]
+    Jpeg,
+    Png,
+}
+
+impl StillFormat{
+    pub fn extension(&self) -> &'static str{
+        match self{
+            StillFormat::Jpeg => "jpg",
+            StillFormat::Png => "png",
+        }
+    }
+}
+
+/// 拍照参数
+#[derive(Debug, Clone)]
+pub struct StillOptions{
+    pub format: StillFormat,
+    /// JPEG 质量 1~100，PNG 忽略
+    pub quality: u8,
+    /// 保存的文件路径，为 None 时只返回编码后的数据
+    pub path: Option<PathBuf>,
+}
+
+impl Default for StillOptions{
+    fn default() -> Self{
+        Self { format: StillFormat::Jpeg, quality: 90, path: None }
+    }
+}
+
+/// 拍摄的照片
+#[derive(Debug, Clone)]
+pub struct StillCapture{
+    /// 编码后的 JPEG 或 PNG 数据
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub timestamp_ns: i64,
+    /// 保存的文件路径
+    pub path: Option<PathBuf>,
+}
+
+/// 按 options 编码一帧，设置了 path 时同时写入文件，JPEG 同时写入 exif
+pub fn encode(frame: &Frame, options: &StillOptions, exif: Option<&ExifInfo>) -> Result<StillCapture>{
+    let (width, height) = (frame.width, frame.height);
+    let mut data = vec![];
+    match options.format{
+        StillFormat::Jpeg => {
+            let rgb = frame.convert(PixelFormat::Rgb8)?;
+            JpegEncoder::new_with_quality(&mut data, options.quality.clamp(1, 100))
+                .write_image(&rgb, width, height, ColorType::Rgb8)?;
+            if let Some(exif) = exif{
+                data = exif.insert_into_jpeg(&data)?;
+            }
+        }
+        StillFormat::Png => {
+            let rgba = frame.convert(PixelFormat::Rgba8)?;
+            PngEncoder::new(&mut data).write_image(&rgba, width, height, ColorType::Rgba8)?;
+        }
+    }
+    if let Some(path) = options.path.as_ref(){
+        std::fs::write(path, &data).map_err(|err| anyhow!("write {path:?} failed: {err}"))?;
+    }
+    Ok(StillCapture { data, width, height, timestamp_ns: frame.timestamp_ns, path: options.path.clone() })
+}
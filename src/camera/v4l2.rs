@@ -0,0 +1,262 @@
+use std::{io::ErrorKind, sync::{Arc, Mutex}, time::Duration};
+use anyhow::{anyhow, Result};
+use v4l::{
+    buffer::Type,
+    capability::Flags,
+    context,
+    frameinterval::FrameIntervalEnum,
+    framesize::FrameSizeEnum,
+    io::traits::CaptureStream,
+    prelude::*,
+    format::{Colorspace, Quantization},
+    video::Capture,
+    Format, FourCC, Fraction,
+};
+
+use super::{CameraBackend, CameraInfo, ColorMatrix, ColorRange, ColorSpace, Frame, FrameMailbox, LensFacing, PendingStill, PixelFormat};
+
+/// 枚举系统中支持视频采集的设备: (设备序号, 设备名称)
+pub fn list_devices() -> Vec<(usize, String)>{
+    let mut devices: Vec<(usize, String)> = context::enum_devices()
+        .into_iter()
+        .filter(|node| {
+            // /dev/videoN 中有些节点只输出元数据，需要排除
+            match Device::with_path(node.path()).and_then(|dev| dev.query_caps()){
+                Ok(caps) => caps.capabilities.contains(Flags::VIDEO_CAPTURE),
+                Err(_) => false,
+            }
+        })
+        .map(|node| {
+            let index = node.index();
+            (index, node.name().unwrap_or(format!("video{index}")))
+        })
+        .collect();
+    devices.sort_by_key(|(index, _)| *index);
+    devices
+}
+
+pub struct Camera{
+    index: Option<usize>,
+    camera_handle: Option<Arc<Mutex<bool>>>,
+    camera_task: Option<std::thread::JoinHandle<Result<()>>>,
+    image_sender: FrameMailbox,
+}
+
+impl Camera{
+    pub fn new(image_sender: FrameMailbox) -> Self{
+        Self { index: None, camera_handle:None, camera_task: None, image_sender }
+    }
+}
+
+impl CameraBackend for Camera{
+    fn list_cameras(&mut self) -> Result<Vec<CameraInfo>>{
+        Ok(list_devices()
+            .into_iter()
+            .filter_map(|(index, name)| camera_info(index, name).ok())
+            .collect())
+    }
+
+    fn open(&mut self, camera_id: &str) -> Result<()>{
+        self.index = Some(camera_id.parse()?);
+        Ok(())
+    }
+
+    fn start_preview(&mut self, width: u32, height: u32) -> Result<()>{
+        self.stop_preview()?;
+        let index = self.index.ok_or(anyhow!("camera not opened"))?;
+        let (device, format) = open_device(index, width, height)?;
+        log::info!("v4l2 format:\n{format}");
+        let color_space = color_space(&format);
+
+        let camera_handle = Arc::new(Mutex::new(true));
+        self.camera_handle = Some(camera_handle.clone());
+        let image_sender_clone = self.image_sender.clone();
+        let camera_id = format!("{index}");
+        self.camera_task = Some(std::thread::spawn(move ||{
+            let mut stream = MmapStream::with_buffers(&device, Type::VideoCapture, 4)?;
+            // 设置超时，避免关闭相机时阻塞在取帧上
+            stream.set_timeout(Duration::from_millis(200));
+            // 解码失败只记录第一次，停止时记录总数
+            let mut decode_errors = 0u64;
+            loop {
+                if let Ok(opened) = camera_handle.lock(){
+                    if !*opened{
+                        break;
+                    }
+                }
+
+                let (data, meta) = match stream.next(){
+                    Ok(v) => v,
+                    Err(err) if err.kind() == ErrorKind::TimedOut => continue,
+                    Err(err) => return Err(anyhow!("v4l2 capture error: {:?}", err)),
+                };
+                let data = &data[..(meta.bytesused as usize).min(data.len())];
+
+                let (width, height) = (format.width, format.height);
+                let mut frame = if format.fourcc == FourCC::new(b"MJPG"){
+                    match image::load_from_memory_with_format(data, image::ImageFormat::Jpeg){
+                        Ok(image) => {
+                            let image = image.to_rgba8();
+                            let (width, height) = image.dimensions();
+                            Frame::new(image.into_raw(), PixelFormat::Rgba8, width, height)
+                        }
+                        Err(err) => {
+                            // 有些摄像头启动时前几帧是不完整的JPEG
+                            if decode_errors == 0{
+                                log::warn!("MJPG解码失败:{:?}", err);
+                            }
+                            decode_errors += 1;
+                            continue;
+                        }
+                    }
+                }else{
+                    // YUYV 原样发送，显示时再转换
+                    let mut frame = Frame::new(data.to_vec(), PixelFormat::Yuyv, width, height);
+                    if format.stride > 0{
+                        frame.strides = vec![format.stride as usize];
+                    }
+                    frame.color_space = color_space;
+                    frame
+                };
+                frame.timestamp_ns = (Duration::from_secs(meta.timestamp.sec as u64) + Duration::from_micros(meta.timestamp.usec as u64)).as_nanos() as i64;
+                frame.sequence = meta.sequence as u64;
+                frame.camera_id = camera_id.clone();
+                image_sender_clone.send(frame);
+            }
+            if decode_errors > 0{
+                log::warn!("MJPG解码失败 {decode_errors} 帧");
+            }
+            Ok(())
+        }));
+        Ok(())
+    }
+
+    fn stop_preview(&mut self) -> Result<()>{
+        let mut need_close = false;
+        if let Some(handle) = self.camera_handle.as_ref(){
+            if let Ok(mut handle) = handle.lock(){
+                *handle = false;
+                need_close = true;
+            }
+        }
+
+        if need_close{
+            println!("stop preview..");
+            if let Some(handle) = self.camera_task.take(){
+                let res = handle.join();
+                println!("stop preview: {:?}", res);
+            }
+        }
+        Ok(())
+    }
+
+    fn capabilities(&mut self) -> Result<CameraInfo>{
+        let index = self.index.ok_or(anyhow!("camera not opened"))?;
+        let name = list_devices()
+            .into_iter()
+            .find(|(i, _)| *i == index)
+            .map(|(_, name)| name)
+            .unwrap_or(format!("video{index}"));
+        camera_info(index, name)
+    }
+
+    /// 只打开了一个视频流，从预览流取下一帧，需要更高分辨率时以更大的尺寸开始预览
+    fn capture_still(&mut self, count: usize) -> Result<PendingStill>{
+        Ok(self.image_sender.still_frames(count))
+    }
+}
+
+impl Drop for Camera{
+    fn drop(&mut self) {
+        let _ = self.stop_preview();
+    }
+}
+
+/// 查询设备支持的分辨率、格式和帧率，只列出能够解码的 YUYV 和 MJPG
+fn camera_info(index: usize, name: String) -> Result<CameraInfo>{
+    // 连续范围的分辨率只列出常用的
+    const COMMON_SIZES: [(u32, u32); 4] = [(640, 480), (1280, 720), (1920, 1080), (3840, 2160)];
+
+    let device = Device::new(index)?;
+    let mut info = CameraInfo{
+        id: format!("{index}"),
+        name,
+        lens_facing: LensFacing::External,
+        ..Default::default()
+    };
+    let formats: Vec<FourCC> = device.enum_formats()?.into_iter().map(|desc| desc.fourcc).collect();
+    for fourcc in [FourCC::new(b"YUYV"), FourCC::new(b"MJPG")]{
+        if !formats.contains(&fourcc){
+            continue;
+        }
+        info.pixel_formats.push(fourcc.str().unwrap_or("").to_string());
+        let mut sizes = vec![];
+        for framesize in device.enum_framesizes(fourcc)?{
+            match framesize.size{
+                FrameSizeEnum::Discrete(size) => sizes.push((size.width, size.height)),
+                FrameSizeEnum::Stepwise(step) => {
+                    sizes.push((step.min_width, step.min_height));
+                    sizes.extend(COMMON_SIZES.into_iter().filter(|(w, h)| {
+                        (step.min_width..=step.max_width).contains(w) && (step.min_height..=step.max_height).contains(h)
+                    }));
+                    sizes.push((step.max_width, step.max_height));
+                }
+            }
+        }
+        for (width, height) in sizes{
+            if !info.supported_sizes.contains(&(width, height)){
+                info.supported_sizes.push((width, height));
+            }
+            for interval in device.enum_frameintervals(fourcc, width, height).unwrap_or_default(){
+                // 帧间隔(秒)取倒数得到帧率
+                let fps = |f: Fraction| f.denominator.checked_div(f.numerator).unwrap_or(0);
+                let range = match interval.interval{
+                    FrameIntervalEnum::Discrete(f) => (fps(f), fps(f)),
+                    FrameIntervalEnum::Stepwise(step) => (fps(step.max), fps(step.min)),
+                };
+                if !info.fps_ranges.contains(&range){
+                    info.fps_ranges.push(range);
+                }
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// 打开设备并设置采集格式，优先使用 YUYV，不支持时退回 MJPG
+fn open_device(index: usize, width: u32, height: u32) -> Result<(Device, Format)>{
+    let devices = list_devices();
+    if !devices.iter().any(|(i, _)| *i == index){
+        return Err(anyhow!("camera id not exist, available: {:?}", devices));
+    }
+    let device = Device::new(index).map_err(|err| anyhow!("open /dev/video{index} failed: {:?}", err))?;
+    let supported: Vec<FourCC> = device.enum_formats()?.into_iter().map(|desc| desc.fourcc).collect();
+    for fourcc in [FourCC::new(b"YUYV"), FourCC::new(b"MJPG")]{
+        if !supported.contains(&fourcc){
+            continue;
+        }
+        // 驱动会把分辨率调整为最接近的支持值，以返回的格式为准
+        let format = device.set_format(&Format::new(width, height, fourcc))?;
+        if format.fourcc == fourcc{
+            return Ok((device, format));
+        }
+    }
+    Err(anyhow!("camera does not support YUYV or MJPG: {:?}", supported))
+}
+
+/// 根据驱动返回的 colorspace 和 quantization 确定 YUV 的色彩空间
+fn color_space(format: &Format) -> ColorSpace{
+    let matrix = match format.colorspace{
+        Colorspace::Rec709 | Colorspace::DCIP3 => ColorMatrix::Bt709,
+        Colorspace::Rec2020 => ColorMatrix::Bt2020,
+        // sRGB、JPEG 等其他色彩空间的 YUV 编码都是 BT.601
+        _ => ColorMatrix::Bt601,
+    };
+    let range = match format.quantization{
+        Quantization::FullRange => ColorRange::Full,
+        Quantization::LimitedRange => ColorRange::Limited,
+        // 默认值: JPEG 色彩空间是 full range，其他 YUV 格式是 limited range
+        _ => if matches!(format.colorspace, Colorspace::JPEG) { ColorRange::Full } else { ColorRange::Limited },
+    };
+    ColorSpace::new(matrix, range)
+}
@@ -0,0 +1,162 @@
+use std::{
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, RecvTimeoutError}, Arc},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use anyhow::{anyhow, Result};
+
+use super::Frame;
+
+/// 订阅者线程处理帧的逻辑，返回错误时线程结束
+pub(super) trait FrameHandler: Send + 'static{
+    type Output: Send + 'static;
+    /// 收到一帧，相机不提供时间戳时 timestamp_ns 已经换成收到帧的时间
+    fn on_frame(&mut self, frame: Frame) -> Result<()>;
+    /// 暂停期间收到一帧，这一帧丢弃
+    fn on_paused(&mut self) -> Result<()>{
+        Ok(())
+    }
+    /// 100ms 内没有收到帧
+    fn on_idle(&mut self) -> Result<()>{
+        Ok(())
+    }
+    /// 线程结束前调用一次
+    fn on_stop(self) -> Result<Self::Output>;
+}
+
+/// 在单独的线程中把 Receiver 收到的帧交给 FrameHandler，ClipRecorder、Recorder、EncodeRecorder 共用
+///
+/// 停止标志每 100ms 检查一次，发送端全部释放时线程也会结束，Drop 时停止并等待线程结束
+pub(super) struct FrameWorker<T>{
+    paused: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    task: Option<JoinHandle<Result<T>>>,
+}
+
+impl<T: Send + 'static> FrameWorker<T>{
+    pub fn start<H: FrameHandler<Output = T>>(receiver: Receiver<Frame>, mut handler: H) -> Self{
+        let paused = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let task = {
+            let (paused, stopped) = (paused.clone(), stopped.clone());
+            thread::spawn(move || {
+                let start = Instant::now();
+                while !stopped.load(Ordering::Relaxed){
+                    let mut frame = match receiver.recv_timeout(Duration::from_millis(100)){
+                        Ok(frame) => frame,
+                        Err(RecvTimeoutError::Timeout) => {
+                            handler.on_idle()?;
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    if paused.load(Ordering::Relaxed){
+                        handler.on_paused()?;
+                        continue;
+                    }
+                    if frame.timestamp_ns == 0{
+                        frame.timestamp_ns = start.elapsed().as_nanos() as i64;
+                    }
+                    handler.on_frame(frame)?;
+                }
+                handler.on_stop()
+            })
+        };
+        Self { paused, stopped, task: Some(task) }
+    }
+
+    pub fn pause(&self){
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self){
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool{
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// 停止线程，丢弃还没有处理的帧，返回 on_stop 的结果
+    pub fn stop(&mut self) -> Result<T>{
+        self.stopped.store(true, Ordering::Relaxed);
+        self.join()
+    }
+
+    /// 等待线程自己结束，例如发送端已经全部释放，已经发送的帧都会处理
+    pub fn join(&mut self) -> Result<T>{
+        match self.task.take(){
+            Some(task) => task.join().map_err(|err| anyhow!("{:?}", err))?,
+            None => Err(anyhow!("worker already stopped")),
+        }
+    }
+}
+
+impl<T> Drop for FrameWorker<T>{
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(task) = self.task.take(){
+            let _ = task.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::mpsc::sync_channel;
+    use super::*;
+    use super::super::PixelFormat;
+
+    /// 记录收到的帧的时间戳和暂停期间丢弃的帧数
+    #[derive(Default)]
+    struct Collector{
+        timestamps: Vec<i64>,
+        paused: usize,
+    }
+
+    impl FrameHandler for Collector{
+        type Output = Collector;
+
+        fn on_frame(&mut self, frame: Frame) -> Result<()>{
+            self.timestamps.push(frame.timestamp_ns);
+            Ok(())
+        }
+
+        fn on_paused(&mut self) -> Result<()>{
+            self.paused += 1;
+            Ok(())
+        }
+
+        fn on_stop(self) -> Result<Collector>{
+            Ok(self)
+        }
+    }
+
+    fn frame(timestamp_ns: i64) -> Frame{
+        let mut frame = Frame::new(vec![0; 4], PixelFormat::Rgba8, 1, 1);
+        frame.timestamp_ns = timestamp_ns;
+        frame
+    }
+
+    #[test]
+    fn timestamps_and_pause(){
+        let (sender, receiver) = sync_channel(8);
+        let mut worker = FrameWorker::start(receiver, Collector::default());
+        worker.pause();
+        sender.send(frame(1)).unwrap();
+        // 等待线程取走暂停期间的帧
+        thread::sleep(Duration::from_millis(200));
+        worker.resume();
+        sender.send(frame(5)).unwrap();
+        sender.send(frame(0)).unwrap();
+        // 发送端释放后处理完已经发送的帧
+        drop(sender);
+        let collector = worker.join().unwrap();
+        assert_eq!(collector.paused, 1);
+        assert_eq!(collector.timestamps.len(), 2);
+        assert_eq!(collector.timestamps[0], 5);
+        // 没有时间戳的帧使用收到的时间
+        assert!(collector.timestamps[1] > 0);
+        assert!(worker.join().is_err());
+    }
+}
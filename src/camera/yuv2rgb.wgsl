@@ -8,6 +8,20 @@ var uvsamp: sampler;
 @group(0) @binding(3) 
 var rgbstorage : texture_storage_2d<rgba8unorm, write>;
 
+// ColorSpace::coefficients()
+struct ColorParams {
+  y_offset: f32,
+  y_gain: f32,
+  r_v: f32,
+  g_u: f32,
+  g_v: f32,
+  b_u: f32,
+  _pad0: f32,
+  _pad1: f32,
+}
+@group(0) @binding(4)
+var<uniform> params: ColorParams;
+
 @compute @workgroup_size(8,8,1)
 fn main(@builtin(workgroup_id) WorkGroupID : vec3<u32>,
   @builtin(global_invocation_id) global_id: vec3<u32>,
@@ -15,30 +29,38 @@ fn main(@builtin(workgroup_id) WorkGroupID : vec3<u32>,
     let ydims = vec2<i32>(textureDimensions(ytexture, 0));
     let uvdims = vec2<i32>(textureDimensions(uvtexture, 0));
     let baseIndex : vec2<i32> = vec2<i32>(global_id.xy);
-      
-    let y:f32 = textureLoad(
+    // 宽高不是 8 的倍数时，最后一组有一部分超出图像
+    if (baseIndex.x >= ydims.x || baseIndex.y >= ydims.y) {
+      return;
+    }
+    // 每个色度像素覆盖 2x2 个亮度像素，宽高为奇数时最后一列(行)只覆盖一个
+    let uvcoord = (vec2<f32>(baseIndex) + 0.5) * 0.5 / vec2<f32>(uvdims);
+
+    // 和 CPU 的 YuvTable 一致，黑电平以下的亮度按黑色处理
+    let y:f32 = max(textureLoad(
       ytexture,
       baseIndex,
       0
-    ).r - 0.0625;
+    ).r - params.y_offset, 0.0) * params.y_gain;
     
+    // 色度的零点是 128，不是 0.5
     let v:f32 = textureSampleLevel(
       uvtexture,
       uvsamp,
-      vec2<f32>(baseIndex) / vec2<f32>(ydims), 
-      0.0 
-    ).r - 0.5;
+      uvcoord,
+      0.0
+    ).r - 128.0 / 255.0;
 
     let u:f32 = textureSampleLevel(
       uvtexture,
       uvsamp,
-      vec2<f32>(baseIndex) / vec2<f32>(ydims), 
+      uvcoord,
       0.0
-    ).g - 0.5;
+    ).g - 128.0 / 255.0;
 
-    var r = 1.164 * (y) + 1.596 * (v);
-    var g = 1.164 * (y) - 0.813 * (v) - 0.392 * (u);
-    var b = 1.164 * (y) + 2.017 * (u);
+    var r = y + params.r_v * (v);
+    var g = y - params.g_v * (v) - params.g_u * (u);
+    var b = y + params.b_u * (u);
     var rgb : vec3<f32> = vec3<f32>(r,g,b);
     // rgb = pow(rgb,vec3<f32>(2.2));
 
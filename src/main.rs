@@ -1,10 +1,14 @@
 use anyhow::Result;
 
-mod camera;
+// 相机模块由 lib 编译，桌面程序直接使用
+use slint_android_camera::camera;
 mod app;
 
 fn main() -> Result<()> {
     #[cfg(not(target_os = "android"))]
-    app::run()?;
+    {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+        app::run()?;
+    }
     Ok(())
 }